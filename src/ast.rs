@@ -1,8 +1,14 @@
-#[derive(Debug, RustcEncodable)]
+use std::fmt;
+
+// Expressions, items, prototypes and files are serialized in `schema`
+#[derive(Debug, Clone)]
 pub enum Expr {
     // f64 literal
     Number(f64),
 
+    // String literal, `"a\n"`, holding the text its escapes stand for
+    Str(String),
+
     // variable usage
     Name(String),
 
@@ -17,42 +23,105 @@ pub enum Expr {
 
     // Parenthesized expression, `(a + 3*b)`
     Paren(Box<Expr>),
+
+    // Conditional, `if x < 3 then 1 else fib(x-1)`
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+
+    // Binding introduced by optimizations, with no surface syntax:
+    // evaluates the value once and makes it available by name in the body
+    Let(String, Box<Expr>, Box<Expr>),
 }
 
-#[derive(Debug, RustcEncodable)]
+#[derive(Debug, Clone)]
 pub struct FuncProto(pub String, pub Vec<String>);
 
-#[derive(Debug, RustcEncodable)]
+impl fmt::Display for FuncProto {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}({})", self.0, self.1.join(", "))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Attr {
+    NoInline,  // `@noinline`
+    Export,    // `@export`
+    Memo,      // `@memo`
+}
+
+impl fmt::Display for Attr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Attr::NoInline => write!(f, "@noinline"),
+            Attr::Export => write!(f, "@export"),
+            Attr::Memo => write!(f, "@memo"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Purity {
+    Pure,       // no observable effects, i.e. `pure extern`
+    Effectful,
+}
+
+#[derive(Debug, Clone)]
 pub enum Item {
-    Function(Box<FuncProto>, Box<Expr>),
-    Extern(Box<FuncProto>),
-    Expr(Box<Expr>)
+    // Each starts with the text of the `##` doc comment above it, if any
+    Function(Option<String>, Vec<Attr>, Box<FuncProto>, Box<Expr>),
+    Extern(Option<String>, Purity, Box<FuncProto>),
+
+    Expr(Box<Expr>),
+
+    // `test name expr`, passing when the expression is true, and only run by `test`
+    Test(String, Box<Expr>),
+
+    // `import "lib.k"` or `import lib as l`, with the name calls qualify its functions by
+    Import(ModulePath, Option<String>),
+}
+
+/// Where an `import` finds its module.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModulePath {
+    File(String),  // `"lib/util.k"`, relative to the importing file
+    Name(String),  // `util.math`, searched for as `util/math.k`
+}
+
+impl fmt::Display for ModulePath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ModulePath::File(ref path) => write!(f, "\"{}\"", path),
+            ModulePath::Name(ref name) => write!(f, "{}", name),
+        }
+    }
 }
 
-#[derive(Debug, RustcEncodable)]
+#[derive(Debug, Clone)]
 pub struct File(pub Vec<Box<Item>>);
 
-#[derive(Debug, RustcEncodable)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BinOp {
-    Add,  // `+`
-    Sub,  // `-`
-    Mul,  // `*`
-    Div,  // `/`
-    Rem,  // `%`
+    #[serde(rename = "+")] Add,
+    #[serde(rename = "-")] Sub,
+    #[serde(rename = "*")] Mul,
+    #[serde(rename = "/")] Div,
+    #[serde(rename = "%")] Rem,
 
-    And,  // `&&`
-    Or,   // `||`
+    #[serde(rename = "&&")] And,
+    #[serde(rename = "||")] Or,
 
-    Eq,   // `==`
-    Ne,   // `!=`
-    Gt,   // `>`
-    Ge,   // `>=`
-    Lt,   // `<`
-    Le,   // `<=`
+    #[serde(rename = "==")] Eq,
+    #[serde(rename = "!=")] Ne,
+    #[serde(rename = ">")] Gt,
+    #[serde(rename = ">=")] Ge,
+    #[serde(rename = "<")] Lt,
+    #[serde(rename = "<=")] Le,
 }
 
-#[derive(Debug, RustcEncodable)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum UnOp {
-    Neg,   // `-`
-    Not,   // `!`
+    #[serde(rename = "-")] Neg,
+    #[serde(rename = "!")] Not,
 }
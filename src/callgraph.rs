@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use ast::*;
+
+#[derive(Debug, PartialEq)]
+pub enum NodeKind {
+    Function,
+    Extern,
+
+    // Called, but neither defined nor declared
+    Undefined,
+}
+
+#[derive(Debug)]
+pub struct Node {
+    pub name: String,
+    pub kind: NodeKind,
+    pub exported: bool,
+}
+
+/// Which functions call which, built from the `Expr::Call`s in a file.
+pub struct CallGraph {
+    /// Every function, extern and undefined callee, in order of first appearance.
+    pub nodes: Vec<Node>,
+
+    /// Indices of the nodes called by each node, without duplicates.
+    pub edges: Vec<Vec<usize>>,
+
+    /// Nodes called from top-level expressions and tests, or marked `@export`.
+    pub roots: Vec<usize>,
+
+    index: HashMap<String, usize>,
+}
+
+impl CallGraph {
+    pub fn build(file: &File) -> CallGraph {
+        let mut graph = CallGraph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            roots: Vec::new(),
+            index: HashMap::new(),
+        };
+
+        // Declare everything first so forward references resolve to the right kind
+        for item in &file.0 {
+            match **item {
+                Item::Function(_, ref attrs, ref proto, _) => {
+                    let node = graph.node(&proto.0, NodeKind::Function);
+                    if attrs.contains(&Attr::Export) {
+                        graph.nodes[node].exported = true;
+                        graph.add_root(node);
+                    }
+                }
+
+                Item::Extern(_, _, ref proto) => {
+                    graph.node(&proto.0, NodeKind::Extern);
+                }
+
+                Item::Expr(_) | Item::Test(..) | Item::Import(..) => {}
+            }
+        }
+
+        for item in &file.0 {
+            match **item {
+                Item::Function(_, _, ref proto, ref body) => {
+                    let caller = graph.index[&proto.0];
+                    graph.add_calls(body, Some(caller));
+                }
+
+                // Tests keep what they call alive, as top-level expressions do
+                Item::Expr(ref expr) | Item::Test(_, ref expr) => graph.add_calls(expr, None),
+                Item::Extern(..) | Item::Import(..) => {}
+            }
+        }
+
+        graph
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<usize> {
+        self.index.get(name).cloned()
+    }
+
+    fn node(&mut self, name: &str, kind: NodeKind) -> usize {
+        if let Some(&i) = self.index.get(name) {
+            // A definition takes precedence over a declaration of the same name
+            if kind == NodeKind::Function || self.nodes[i].kind == NodeKind::Undefined {
+                self.nodes[i].kind = kind;
+            }
+
+            return i
+        }
+
+        self.nodes.push(Node { name: name.to_string(), kind, exported: false });
+        self.edges.push(Vec::new());
+        self.index.insert(name.to_string(), self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    fn add_root(&mut self, node: usize) {
+        if !self.roots.contains(&node) {
+            self.roots.push(node);
+        }
+    }
+
+    /// Record the calls made by `expr`; `caller` is `None` for top-level expressions.
+    fn add_calls(&mut self, expr: &Expr, caller: Option<usize>) {
+        match *expr {
+            Expr::Number(_) | Expr::Str(_) | Expr::Name(_) => {}
+
+            Expr::Binary(_, ref lhs, ref rhs) => {
+                self.add_calls(lhs, caller);
+                self.add_calls(rhs, caller);
+            }
+
+            Expr::Unary(_, ref operand) | Expr::Paren(ref operand) => self.add_calls(operand, caller),
+
+            Expr::Let(_, ref value, ref body) => {
+                self.add_calls(value, caller);
+                self.add_calls(body, caller);
+            }
+
+            Expr::If(ref cond, ref then, ref otherwise) => {
+                self.add_calls(cond, caller);
+                self.add_calls(then, caller);
+                self.add_calls(otherwise, caller);
+            }
+
+            Expr::Call(ref name, ref args) => {
+                let callee = self.node(name, NodeKind::Undefined);
+
+                match caller {
+                    Some(caller) if !self.edges[caller].contains(&callee) => self.edges[caller].push(callee),
+                    Some(_) => {}
+                    None => self.add_root(callee),
+                }
+
+                for arg in args {
+                    self.add_calls(arg, caller);
+                }
+            }
+        }
+    }
+
+    /// Strongly connected components, each listed after every component it calls into.
+    ///
+    /// ```
+    /// use kaleidescope_rs::callgraph::CallGraph;
+    ///
+    /// let file = kaleidescope_rs::parse("
+    ///     def even(n) if n == 0 then 1 else odd(n - 1)
+    ///     def odd(n) if n == 0 then 0 else even(n - 1)
+    ///     def main(n) even(n) + leaf(n)
+    ///     def leaf(n) n
+    /// ").unwrap();
+    /// let graph = CallGraph::build(&file);
+    ///
+    /// let names: Vec<Vec<&str>> = graph.sccs().iter()
+    ///     .map(|scc| {
+    ///         let mut names: Vec<&str> = scc.iter().map(|&node| &graph.nodes[node].name[..]).collect();
+    ///         names.sort();
+    ///         names
+    ///     })
+    ///     .collect();
+    /// assert_eq!(names, vec![vec!["even", "odd"], vec!["leaf"], vec!["main"]]);
+    /// ```
+    pub fn sccs(&self) -> Vec<Vec<usize>> {
+        let mut tarjan = Tarjan {
+            graph: self,
+            next_index: 0,
+            index: vec![None; self.nodes.len()],
+            lowlink: vec![0; self.nodes.len()],
+            on_stack: vec![false; self.nodes.len()],
+            stack: Vec::new(),
+            sccs: Vec::new(),
+        };
+
+        for node in 0..self.nodes.len() {
+            if tarjan.index[node].is_none() {
+                tarjan.visit(node);
+            }
+        }
+
+        tarjan.sccs
+    }
+
+    /// For each node, whether it can call itself, directly or through other functions.
+    ///
+    /// ```
+    /// use kaleidescope_rs::callgraph::CallGraph;
+    ///
+    /// let file = kaleidescope_rs::parse("
+    ///     def ping(n) if n then pong(n - 1) else 0
+    ///     def pong(n) ping(n)
+    ///     def loop(n) loop(n)
+    ///     def leaf(n) n
+    /// ").unwrap();
+    /// let graph = CallGraph::build(&file);
+    ///
+    /// assert_eq!(graph.recursive(), vec![true, true, true, false]);
+    /// ```
+    pub fn recursive(&self) -> Vec<bool> {
+        let mut recursive = vec![false; self.nodes.len()];
+
+        for scc in self.sccs() {
+            let cycle = scc.len() > 1 || self.edges[scc[0]].contains(&scc[0]);
+            for node in scc {
+                recursive[node] = cycle;
+            }
+        }
+
+        recursive
+    }
+
+    /// For each node, whether it can be called starting from the roots.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.nodes.len()];
+        let mut stack = self.roots.clone();
+
+        while let Some(node) = stack.pop() {
+            if reachable[node] {
+                continue
+            }
+
+            reachable[node] = true;
+            stack.extend(self.edges[node].iter().filter(|&&callee| !reachable[callee]));
+        }
+
+        reachable
+    }
+
+    /// Render the graph in Graphviz dot format.
+    ///
+    /// Recursive components are drawn as clusters, externs as boxes, and functions
+    /// the roots never reach are greyed out.
+    pub fn to_dot(&self) -> String {
+        let reachable = self.reachable();
+        let mut out = String::new();
+
+        writeln!(out, "digraph callgraph {{").unwrap();
+        writeln!(out, "    \"<top level>\" [shape=house];").unwrap();
+
+        for (i, scc) in self.sccs().iter().enumerate() {
+            let cycle = scc.len() > 1 || self.edges[scc[0]].contains(&scc[0]);
+            let indent = if cycle { "        " } else { "    " };
+
+            if cycle {
+                writeln!(out, "    subgraph cluster_{} {{", i).unwrap();
+                writeln!(out, "        label=\"recursive\";").unwrap();
+                writeln!(out, "        style=dashed;").unwrap();
+            }
+
+            for &node in scc {
+                writeln!(out, "{}{};", indent, self.dot_node(node, reachable[node])).unwrap();
+            }
+
+            if cycle {
+                writeln!(out, "    }}").unwrap();
+            }
+        }
+
+        for &root in &self.roots {
+            if !self.nodes[root].exported {
+                writeln!(out, "    \"<top level>\" -> {:?};", self.nodes[root].name).unwrap();
+            }
+        }
+
+        for (caller, callees) in self.edges.iter().enumerate() {
+            for &callee in callees {
+                writeln!(out, "    {:?} -> {:?};", self.nodes[caller].name, self.nodes[callee].name).unwrap();
+            }
+        }
+
+        writeln!(out, "}}").unwrap();
+        out
+    }
+
+    fn dot_node(&self, node: usize, reachable: bool) -> String {
+        let node = &self.nodes[node];
+        let mut attrs: Vec<&str> = Vec::new();
+
+        match node.kind {
+            NodeKind::Function => {}
+            NodeKind::Extern => attrs.push("shape=box"),
+            NodeKind::Undefined => attrs.push("shape=box, color=red"),
+        }
+
+        if node.exported {
+            attrs.push("peripheries=2");
+        }
+
+        if !reachable {
+            attrs.push("color=grey, fontcolor=grey");
+        }
+
+        if attrs.is_empty() {
+            format!("{:?}", node.name)
+        } else {
+            format!("{:?} [{}]", node.name, attrs.join(", "))
+        }
+    }
+}
+
+struct Tarjan<'a> {
+    graph: &'a CallGraph,
+    next_index: usize,
+    index: Vec<Option<usize>>,
+    lowlink: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    sccs: Vec<Vec<usize>>,
+}
+
+impl<'a> Tarjan<'a> {
+    fn visit(&mut self, node: usize) {
+        self.index[node] = Some(self.next_index);
+        self.lowlink[node] = self.next_index;
+        self.next_index += 1;
+        self.stack.push(node);
+        self.on_stack[node] = true;
+
+        for &callee in &self.graph.edges[node] {
+            match self.index[callee] {
+                None => {
+                    self.visit(callee);
+                    self.lowlink[node] = self.lowlink[node].min(self.lowlink[callee]);
+                }
+
+                Some(index) if self.on_stack[callee] => {
+                    self.lowlink[node] = self.lowlink[node].min(index);
+                }
+
+                Some(_) => {}
+            }
+        }
+
+        if Some(self.lowlink[node]) == self.index[node] {
+            let mut scc = Vec::new();
+
+            loop {
+                let member = self.stack.pop().unwrap();
+                self.on_stack[member] = false;
+                scc.push(member);
+
+                if member == node {
+                    break
+                }
+            }
+
+            self.sccs.push(scc);
+        }
+    }
+}
+
+/// Remove every function that cannot be reached from a top-level expression or `@export`.
+pub fn eliminate_dead_functions(file: &mut File) {
+    let graph = CallGraph::build(file);
+    let reachable = graph.reachable();
+
+    file.0.retain(|item| match **item {
+        Item::Function(_, _, ref proto, _) => reachable[graph.index[&proto.0]],
+        _ => true
+    });
+}
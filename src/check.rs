@@ -0,0 +1,176 @@
+use std::collections::{HashMap, HashSet};
+
+use ast::*;
+use purity::PurityAnalysis;
+
+/// Find mistakes that make a program meaningless without running it: undefined
+/// or redefined functions, redefined tests, calls with the wrong number of arguments, and names
+/// that are not parameters of the enclosing function.
+///
+/// ```
+/// let file = kaleidescope_rs::parse("def f(x) x  def f(y) f(y, 2)").unwrap();
+/// assert_eq!(kaleidescope_rs::check::check(&file), [
+///     "function `f` is defined more than once",
+///     "`f` takes 1 arguments, but 2 were given",
+/// ]);
+/// ```
+pub fn check(file: &File) -> Vec<String> {
+    let mut errors: Vec<String> = Vec::new();
+    let mut arities: HashMap<&str, usize> = HashMap::new();
+    let mut defined: HashSet<&str> = HashSet::new();
+    let mut tests: HashSet<&str> = HashSet::new();
+
+    for item in &file.0 {
+        let proto = match **item {
+            Item::Function(_, _, ref proto, _) => {
+                if !defined.insert(&proto.0) {
+                    errors.push(format!("function `{}` is defined more than once", proto.0));
+                }
+
+                proto
+            }
+
+            Item::Extern(_, _, ref proto) => proto,
+
+            Item::Test(ref name, _) => {
+                if !tests.insert(name) {
+                    errors.push(format!("test `{}` is defined more than once", name));
+                }
+
+                continue
+            }
+
+            Item::Expr(_) | Item::Import(..) => continue,
+        };
+
+        if let Some(&arity) = arities.get(proto.0.as_str()) {
+            if arity != proto.1.len() {
+                errors.push(format!("`{}` is declared with both {} and {} parameters", proto.0, arity, proto.1.len()));
+            }
+        }
+
+        arities.insert(&proto.0, proto.1.len());
+
+        for (i, param) in proto.1.iter().enumerate() {
+            if proto.1[..i].contains(param) {
+                errors.push(format!("parameter `{}` of `{}` is declared more than once", param, proto.0));
+            }
+        }
+    }
+
+    let no_params: Vec<String> = Vec::new();
+
+    for item in &file.0 {
+        match **item {
+            Item::Function(_, _, ref proto, ref body) => check_expr(body, &proto.1, &arities, &mut errors),
+            Item::Expr(ref expr) | Item::Test(_, ref expr) => check_expr(expr, &no_params, &arities, &mut errors),
+            Item::Extern(..) | Item::Import(..) => {}
+        }
+    }
+
+    errors
+}
+
+fn check_expr(expr: &Expr, params: &[String], arities: &HashMap<&str, usize>, errors: &mut Vec<String>) {
+    match *expr {
+        Expr::Number(_) | Expr::Str(_) => {}
+
+        Expr::Name(ref name) => {
+            if params.contains(name) {
+                return
+            }
+
+            match arities.get(name.as_str()) {
+                // Such as `pi` of the prelude, which has no constants
+                Some(&0) => errors.push(format!("unknown variable `{}`, but there is a function `{}()`", name, name)),
+                _ => errors.push(format!("unknown variable `{}`", name)),
+            }
+        }
+
+        Expr::Binary(_, ref lhs, ref rhs) => {
+            check_expr(lhs, params, arities, errors);
+            check_expr(rhs, params, arities, errors);
+        }
+
+        Expr::Unary(_, ref operand) | Expr::Paren(ref operand) => check_expr(operand, params, arities, errors),
+
+        Expr::If(ref cond, ref then, ref otherwise) => {
+            check_expr(cond, params, arities, errors);
+            check_expr(then, params, arities, errors);
+            check_expr(otherwise, params, arities, errors);
+        }
+
+        Expr::Let(ref name, ref value, ref body) => {
+            check_expr(value, params, arities, errors);
+
+            let mut scope = params.to_vec();
+            scope.push(name.clone());
+            check_expr(body, &scope, arities, errors);
+        }
+
+        Expr::Call(ref name, ref args) => {
+            match arities.get(name.as_str()) {
+                None => errors.push(format!("call to undefined function `{}`", name)),
+                Some(&arity) if arity != args.len() => {
+                    errors.push(format!("`{}` takes {} arguments, but {} were given", name, arity, args.len()))
+                }
+                Some(_) => {}
+            }
+
+            for arg in args {
+                check_expr(arg, params, arities, errors);
+            }
+        }
+    }
+}
+
+/// Find legal but probably unintended constructs.
+///
+/// ```
+/// let file = kaleidescope_rs::parse("extern printd(x)  @memo def log(x) printd(x)").unwrap();
+/// assert_eq!(kaleidescope_rs::check::warnings(&file).len(), 1);
+/// ```
+pub fn warnings(file: &File) -> Vec<String> {
+    let purity = PurityAnalysis::of(file);
+    let mut warnings: Vec<String> = Vec::new();
+
+    for item in &file.0 {
+        if let Item::Function(_, ref attrs, ref proto, _) = **item {
+            if attrs.contains(&Attr::Memo) && purity.purity(&proto.0) == Purity::Effectful {
+                warnings.push(format!(
+                    "`{}` is effectful, so memoizing it skips its effects when a result is cached",
+                    proto.0
+                ));
+            }
+        }
+    }
+
+    warnings
+}
+
+/// Describe each function and extern in `file`, one per line, with its purity.
+///
+/// ```
+/// let file = kaleidescope_rs::parse("pure extern sin(x)  def f(x) sin(x) * 2").unwrap();
+/// assert_eq!(kaleidescope_rs::check::summarize(&file), ["pure extern sin(x): pure", "def f(x): pure"]);
+/// ```
+pub fn summarize(file: &File) -> Vec<String> {
+    let purity = PurityAnalysis::of(file);
+
+    file.0.iter().filter_map(|item| {
+        let (decl, proto) = match **item {
+            Item::Function(_, _, ref proto, _) => ("def", proto),
+            Item::Extern(_, Purity::Pure, ref proto) => ("pure extern", proto),
+            Item::Extern(_, Purity::Effectful, ref proto) => ("extern", proto),
+            Item::Expr(_) | Item::Test(..) | Item::Import(..) => return None,
+        };
+
+        let description = match (purity.purity(&proto.0), purity.cause(&proto.0)) {
+            (Purity::Pure, _) => "pure".to_string(),
+            (Purity::Effectful, Some(callee)) => format!("effectful, calls `{}`", callee),
+            (Purity::Effectful, None) => "effectful".to_string(),
+        };
+
+        Some(format!("{} {}: {}", decl, proto, description))
+    }).collect()
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+
+use ast::*;
+use purity::{self, PurityAnalysis};
+
+/// Evaluate repeated pure subexpressions once per function body.
+///
+/// Subexpressions get the same value number when they are structurally
+/// identical, up to parentheses and the operand order of commutative operators,
+/// which `+` only is on numbers.
+/// A repeated one is bound with a `let` at the root of the nearest region that
+/// evaluates it unconditionally, where a region is a body, a branch of a
+/// conditional or the right-hand side of a short-circuiting operator. Nothing is ever evaluated on a path that
+/// would not have evaluated it before, and a value that can fail is only bound when nothing with effects or
+/// that can fail is evaluated before it, so that it fails at the same point as before.
+pub fn eliminate_common_subexpressions(file: &mut File) {
+    let mut cse = Cse { purity: PurityAnalysis::of(file), next_temp: 0 };
+
+    for item in file.0.iter_mut() {
+        match **item {
+            Item::Function(_, _, _, ref mut body) | Item::Expr(ref mut body) | Item::Test(_, ref mut body) => {
+                cse.region(body)
+            }
+
+            Item::Extern(..) | Item::Import(..) => {}
+        }
+    }
+}
+
+struct Cse {
+    purity: PurityAnalysis,
+    next_temp: usize,
+}
+
+impl Cse {
+    fn region(&mut self, expr: &mut Box<Expr>) {
+        let mut bindings: Vec<(String, Box<Expr>)> = Vec::new();
+
+        // Bind the largest repeated value, then look again: its binding may repeat a smaller one
+        loop {
+            // In the order of evaluation, bindings first
+            let mut counter = Counter::new(&self.purity);
+            for (_, value) in &bindings {
+                counter.count(value, false);
+            }
+            counter.count(expr, false);
+
+            let (value, repr) = match counter.best() {
+                Some(best) => best,
+                None => break
+            };
+
+            // Temporaries can never clash with a parameter name
+            let temp = format!("%{}", self.next_temp);
+            self.next_temp += 1;
+
+            counter.table.replace(expr, value, &temp);
+            for (_, bound) in bindings.iter_mut() {
+                counter.table.replace(bound, value, &temp);
+            }
+
+            bindings.push((temp, Box::new(repr)));
+            bindings = ordered(bindings);
+        }
+
+        self.nested_regions(expr);
+        for (_, value) in bindings.iter_mut() {
+            self.nested_regions(value);
+        }
+
+        for (temp, value) in bindings.into_iter().rev() {
+            let body = ::std::mem::replace(expr, Box::new(Expr::Number(0.0)));
+            **expr = Expr::Let(temp, value, body);
+        }
+    }
+
+    fn nested_regions(&mut self, expr: &mut Box<Expr>) {
+        match **expr {
+            Expr::Number(_) | Expr::Str(_) | Expr::Name(_) => {}
+
+            Expr::Binary(op, ref mut lhs, ref mut rhs) => {
+                self.nested_regions(lhs);
+
+                if is_short_circuit(op) {
+                    self.region(rhs);
+                } else {
+                    self.nested_regions(rhs);
+                }
+            }
+
+            Expr::Unary(_, ref mut operand) | Expr::Paren(ref mut operand) => self.nested_regions(operand),
+
+            Expr::Call(_, ref mut args) => {
+                for arg in args.iter_mut() {
+                    self.nested_regions(arg);
+                }
+            }
+
+            Expr::Let(_, ref mut value, ref mut body) => {
+                self.nested_regions(value);
+                self.nested_regions(body);
+            }
+
+            Expr::If(ref mut cond, ref mut then, ref mut otherwise) => {
+                self.nested_regions(cond);
+                self.region(then);
+                self.region(otherwise);
+            }
+        }
+    }
+}
+
+/// Order bindings so that each comes after the temporaries its value refers to.
+fn ordered(mut bindings: Vec<(String, Box<Expr>)>) -> Vec<(String, Box<Expr>)> {
+    let mut ordered: Vec<(String, Box<Expr>)> = Vec::new();
+
+    while !bindings.is_empty() {
+        let ready = bindings.iter().position(|(_, value)| {
+            !bindings.iter().any(|(temp, _)| refers_to(value, temp))
+        });
+
+        // A binding's value never refers to itself, directly or through others
+        ordered.push(bindings.remove(ready.unwrap()));
+    }
+
+    ordered
+}
+
+fn refers_to(expr: &Expr, name: &str) -> bool {
+    match *expr {
+        Expr::Number(_) | Expr::Str(_) => false,
+        Expr::Name(ref n) => n == name,
+        Expr::Binary(_, ref lhs, ref rhs) => refers_to(lhs, name) || refers_to(rhs, name),
+        Expr::Unary(_, ref operand) | Expr::Paren(ref operand) => refers_to(operand, name),
+        Expr::Call(_, ref args) => args.iter().any(|arg| refers_to(arg, name)),
+        Expr::Let(_, ref value, ref body) => refers_to(value, name) || refers_to(body, name),
+        Expr::If(ref cond, ref then, ref otherwise) => {
+            refers_to(cond, name) || refers_to(then, name) || refers_to(otherwise, name)
+        }
+    }
+}
+
+fn is_short_circuit(op: BinOp) -> bool {
+    matches!(op, BinOp::And | BinOp::Or)
+}
+
+/// Whether `lhs op rhs` is `rhs op lhs`. Joining strings with `+` isn't.
+fn is_commutative(op: BinOp, lhs: &Expr, rhs: &Expr) -> bool {
+    match op {
+        BinOp::Mul | BinOp::Eq | BinOp::Ne => true,
+        BinOp::Add => purity::is_number(lhs) && purity::is_number(rhs),
+        _ => false,
+    }
+}
+
+/// The shape of an expression, with subexpressions replaced by their value numbers.
+#[derive(PartialEq, Eq, Hash)]
+enum Value {
+    Number(u64),
+    Str(String),
+    Name(String),
+    Binary(BinOp, usize, usize),
+    Unary(UnOp, usize),
+    Call(String, Vec<usize>),
+    If(usize, usize, usize),
+
+    // Bindings are never considered equal to anything else
+    Let(usize),
+}
+
+/// Hash-consing table assigning equal numbers to expressions computing equal values.
+struct ValueTable {
+    numbers: HashMap<Value, usize>,
+}
+
+impl ValueTable {
+    fn number(&mut self, expr: &Expr) -> usize {
+        let value = match *expr {
+            Expr::Number(val) => Value::Number(val.to_bits()),
+            Expr::Str(ref text) => Value::Str(text.clone()),
+            Expr::Name(ref name) => Value::Name(name.clone()),
+
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                let commutative = is_commutative(op, lhs, rhs);
+                let (lhs, rhs) = (self.number(lhs), self.number(rhs));
+
+                if commutative && rhs < lhs {
+                    Value::Binary(op, rhs, lhs)
+                } else {
+                    Value::Binary(op, lhs, rhs)
+                }
+            }
+
+            Expr::Unary(op, ref operand) => Value::Unary(op, self.number(operand)),
+            Expr::Paren(ref inner) => return self.number(inner),
+            Expr::Call(ref name, ref args) => Value::Call(name.clone(), args.iter().map(|a| self.number(a)).collect()),
+            Expr::If(ref cond, ref then, ref otherwise) => {
+                Value::If(self.number(cond), self.number(then), self.number(otherwise))
+            }
+
+            Expr::Let(..) => Value::Let(self.numbers.len()),
+        };
+
+        let next = self.numbers.len();
+        *self.numbers.entry(value).or_insert(next)
+    }
+
+    /// Replace every subexpression numbered `value` with a reference to `temp`.
+    fn replace(&mut self, expr: &mut Box<Expr>, value: usize, temp: &str) {
+        if self.number(expr) == value {
+            **expr = Expr::Name(temp.to_string());
+            return
+        }
+
+        match **expr {
+            Expr::Number(_) | Expr::Str(_) | Expr::Name(_) => {}
+
+            Expr::Binary(_, ref mut lhs, ref mut rhs) => {
+                self.replace(lhs, value, temp);
+                self.replace(rhs, value, temp);
+            }
+
+            Expr::Unary(_, ref mut operand) | Expr::Paren(ref mut operand) => self.replace(operand, value, temp),
+
+            Expr::Call(_, ref mut args) => {
+                for arg in args.iter_mut() {
+                    self.replace(arg, value, temp);
+                }
+            }
+
+            Expr::Let(_, ref mut val, ref mut body) => {
+                self.replace(val, value, temp);
+                self.replace(body, value, temp);
+            }
+
+            Expr::If(ref mut cond, ref mut then, ref mut otherwise) => {
+                self.replace(cond, value, temp);
+                self.replace(then, value, temp);
+                self.replace(otherwise, value, temp);
+            }
+        }
+    }
+}
+
+/// How often a value is computed within a region.
+struct Occurrences {
+    unconditional: usize,
+    total: usize,
+    size: usize,
+    first_seen: usize,
+    repr: Expr,
+
+    // Whether it can be computed at the root of the region instead: it can't
+    // fail, or nothing that has effects or can fail comes before its first
+    // unconditional occurrence
+    hoistable: bool,
+}
+
+struct Counter<'a> {
+    purity: &'a PurityAnalysis,
+    table: ValueTable,
+    occurrences: HashMap<usize, Occurrences>,
+
+    // Whether anything counted so far has effects or can fail
+    hazard: bool,
+}
+
+impl<'a> Counter<'a> {
+    fn new(purity: &'a PurityAnalysis) -> Counter<'a> {
+        Counter {
+            purity,
+            table: ValueTable { numbers: HashMap::new() },
+            occurrences: HashMap::new(),
+            hazard: false,
+        }
+    }
+
+    /// Record the candidate subexpressions of `expr`, which must be counted in
+    /// the order they are evaluated, returning its size.
+    fn count(&mut self, expr: &Expr, conditional: bool) -> usize {
+        let hazard_before = self.hazard;
+
+        let size = match *expr {
+            // Not worth a binding
+            Expr::Number(_) | Expr::Str(_) | Expr::Name(_) => return 1,
+
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                1 + self.count(lhs, conditional) + self.count(rhs, conditional || is_short_circuit(op))
+            }
+
+            Expr::Unary(_, ref operand) => 1 + self.count(operand, conditional),
+            Expr::Paren(ref inner) => return self.count(inner, conditional),
+            Expr::Call(_, ref args) => 1 + args.iter().map(|arg| self.count(arg, conditional)).sum::<usize>(),
+
+            Expr::If(ref cond, ref then, ref otherwise) => {
+                1 + self.count(cond, conditional) + self.count(then, true) + self.count(otherwise, true)
+            }
+
+            Expr::Let(_, ref value, ref body) => {
+                return 1 + self.count(value, conditional) + self.count(body, conditional)
+            }
+        };
+
+        let pure = self.purity.is_pure(expr);
+        if !pure || purity::fails_itself(expr) {
+            self.hazard = true;
+        }
+
+        if pure {
+            let value = self.table.number(expr);
+            let first_seen = self.occurrences.len();
+
+            let entry = self.occurrences.entry(value).or_insert_with(|| Occurrences {
+                unconditional: 0,
+                total: 0,
+                size,
+                first_seen,
+                repr: expr.clone(),
+                hoistable: false,
+            });
+
+            entry.total += 1;
+            if !conditional {
+                if entry.unconditional == 0 {
+                    entry.hoistable = !hazard_before || !purity::can_fail(expr);
+                }
+
+                entry.unconditional += 1;
+            }
+        }
+
+        size
+    }
+
+    /// The largest hoistable value computed more than once, at least once unconditionally.
+    fn best(&self) -> Option<(usize, Expr)> {
+        self.occurrences.iter()
+            .filter(|&(_, occ)| occ.unconditional >= 1 && occ.total >= 2 && occ.hoistable)
+            .max_by_key(|&(_, occ)| (occ.size, ::std::cmp::Reverse(occ.first_seen)))
+            .map(|(&value, occ)| (value, occ.repr.clone()))
+    }
+}
@@ -0,0 +1,486 @@
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::io::prelude::*;
+use std::io::{self, StdinLock};
+use std::rc::Rc;
+
+use serde_json::Value;
+
+use ast::Expr;
+use debug::{self, Program, Resume, Stops};
+use interp::{Debugger, Interpreter};
+use kaleidescope_rs::limits::Limits;
+use lsp::{read_message, write_message};
+use modules::Loader;
+use value;
+use EXIT_RUNTIME;
+
+// The program runs on the only thread there is
+const THREAD: u64 = 1;
+
+/// Serve the Debug Adapter Protocol over stdin and stdout for one `launch`,
+/// returning the exit status it should cause.
+///
+/// The program runs once the client is done configuring, and while it runs
+/// requests are only read at stops, so it can't be paused. What the program
+/// writes is sent in `output` events, as are the values of its top-level
+/// expressions. The program runs within `limits`.
+pub fn run(limits: Limits) -> Result<i32, String> {
+    let client = Rc::new(RefCell::new(Client { input: io::stdin().lock(), seq: 1 }));
+
+    let (program, stop_on_entry) = loop {
+        let request = match client.borrow_mut().receive()? {
+            Some(request) => request,
+            None => return Ok(1),
+        };
+
+        let mut client = client.borrow_mut();
+
+        match command(&request) {
+            "initialize" => client.respond(&request, Ok(capabilities())),
+
+            "launch" => match launch(&request["arguments"]) {
+                Ok(program) => {
+                    client.respond(&request, Ok(Value::Null));
+
+                    // Breakpoints can only be placed once the program is loaded
+                    client.event("initialized", Value::Null);
+
+                    let stop_on_entry = request.pointer("/arguments/stopOnEntry").and_then(Value::as_bool).unwrap_or(false);
+                    break (program, stop_on_entry)
+                }
+                Err(e) => client.respond(&request, Err(e)),
+            },
+
+            "disconnect" => {
+                client.respond(&request, Ok(Value::Null));
+                return Ok(0)
+            }
+
+            command => client.respond(&request, Err(format!("`{}` is not supported before `launch`", command))),
+        }
+    };
+
+    let mut interpreter = program.interpreter();
+    interpreter.set_limits(limits);
+    interpreter.set_output(Box::new(OutputEvents(client.clone())));
+
+    let quit = Rc::new(Cell::new(false));
+    let mut session = Session {
+        client: client.clone(),
+        stops: Stops::new(program.source_map(&interpreter)),
+        breakpoints: Vec::new(),
+        next_breakpoint: 1,
+        entry: stop_on_entry,
+        quit: quit.clone(),
+    };
+
+    if !stop_on_entry {
+        session.stops.resume = Resume::Continue;
+    }
+
+    loop {
+        let request = match client.borrow_mut().receive()? {
+            Some(request) => request,
+            None => return Ok(1),
+        };
+
+        if command(&request) == "configurationDone" {
+            client.borrow_mut().respond(&request, Ok(Value::Null));
+            break
+        }
+
+        if let Action::Quit = session.handle(&request, None) {
+            return Ok(0)
+        }
+    }
+
+    interpreter.set_debugger(Box::new(session));
+
+    let mut exit_code = 0;
+
+    for expr in program.top_level() {
+        match interpreter.eval_top_level(expr) {
+            Ok(value) => client.borrow_mut().output("stdout", &format!("{}\n", value)),
+            Err(_) if quit.get() => return Ok(0),
+            Err(e) => {
+                client.borrow_mut().output("stderr", &format!("error: {}\n", e));
+                exit_code = EXIT_RUNTIME;
+                break
+            }
+        }
+    }
+
+    let mut client = client.borrow_mut();
+    client.event("exited", json!({ "exitCode": exit_code }));
+    client.event("terminated", Value::Null);
+
+    // The client still disconnects once it knows the program is over
+    while let Some(request) = client.receive()? {
+        match command(&request) {
+            "disconnect" => {
+                client.respond(&request, Ok(Value::Null));
+                break
+            }
+            _ => client.respond(&request, Err("the program has finished".to_string())),
+        }
+    }
+
+    Ok(0)
+}
+
+fn command(request: &Value) -> &str {
+    request.get("command").and_then(Value::as_str).unwrap_or("")
+}
+
+fn capabilities() -> Value {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsTerminateRequest": true,
+    })
+}
+
+/// Load the file named by the `program` argument of `launch`.
+fn launch(arguments: &Value) -> Result<Program, String> {
+    let path = arguments.get("program").and_then(Value::as_str).ok_or("`launch` needs a `program` to run")?;
+    let source = fs::read_to_string(path).map_err(|e| format!("unable to read `{}`: {}", path, e))?;
+    Program::load(vec![(path.to_string(), source)], true, &mut Loader::new(Vec::new()))
+}
+
+/// The connection to the client, with the sequence number of the next message sent.
+struct Client {
+    input: StdinLock<'static>,
+    seq: u64,
+}
+
+impl Client {
+    fn receive(&mut self) -> Result<Option<Value>, String> {
+        read_message(&mut self.input).map_err(|e| e.to_string())
+    }
+
+    fn send(&mut self, mut message: Value) {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+
+        let stdout = io::stdout();
+        let mut output = stdout.lock();
+
+        // Nothing more can be said to a client that went away
+        let _ = write_message(&mut output, &message);
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request.get("seq").cloned().unwrap_or(Value::Null),
+            "command": command(request),
+        });
+
+        match result {
+            Ok(body) => {
+                response["success"] = json!(true);
+                if !body.is_null() {
+                    response["body"] = body;
+                }
+            }
+
+            Err(message) => {
+                response["success"] = json!(false);
+                response["message"] = json!(message);
+            }
+        }
+
+        self.send(response);
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+
+        self.send(message);
+    }
+
+    fn output(&mut self, category: &str, text: &str) {
+        self.event("output", json!({ "category": category, "output": text }));
+    }
+}
+
+/// Where the interpreter writes the program's output, which standard output
+/// can't be since it carries the protocol.
+struct OutputEvents(Rc<RefCell<Client>>);
+
+impl Write for OutputEvents {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().output("stdout", &String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What to do after handling a request.
+enum Action {
+    Wait,
+    Resume,
+    Quit,
+}
+
+/// The state of a launched program, which the interpreter stops in.
+struct Session {
+    client: Rc<RefCell<Client>>,
+    stops: Stops,
+
+    // Identifier, input and zero-based line of each breakpoint
+    breakpoints: Vec<(u64, usize, usize)>,
+    next_breakpoint: u64,
+
+    // Whether the next stop is the one asked for by `stopOnEntry`
+    entry: bool,
+    quit: Rc<Cell<bool>>,
+}
+
+impl Session {
+    /// Handle `request`, with the interpreter and the bindings of the current
+    /// frame when the program is stopped.
+    fn handle(&mut self, request: &Value, stopped: Option<(&mut Interpreter, &[(String, value::Value)])>) -> Action {
+        let arguments = &request["arguments"];
+        let mut action = Action::Wait;
+
+        let result = match (command(request), stopped) {
+            ("threads", _) => Ok(json!({ "threads": [{ "id": THREAD, "name": "main" }] })),
+            ("setBreakpoints", _) => self.set_breakpoints(arguments),
+
+            ("disconnect", _) | ("terminate", _) => {
+                action = Action::Quit;
+                Ok(Value::Null)
+            }
+
+            ("stackTrace", Some((interpreter, _))) => Ok(self.stack_trace(interpreter)),
+            ("scopes", Some((interpreter, _))) => frame_depth(arguments, interpreter).map(scopes),
+            ("variables", Some((interpreter, _))) => variables(arguments, interpreter),
+            ("evaluate", Some((interpreter, scope))) => evaluate(arguments, interpreter, scope),
+
+            ("continue", Some(_)) => {
+                self.stops.resume = Resume::Continue;
+                action = Action::Resume;
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+
+            (resume @ "next", Some((interpreter, _)))
+            | (resume @ "stepIn", Some((interpreter, _)))
+            | (resume @ "stepOut", Some((interpreter, _))) => {
+                let depth = interpreter.frames().len();
+
+                self.stops.resume = match resume {
+                    "next" => Resume::Next(depth),
+                    "stepIn" => Resume::Step,
+                    _ => Resume::Finish(depth),
+                };
+
+                action = Action::Resume;
+                Ok(Value::Null)
+            }
+
+            ("stackTrace", None) | ("scopes", None) | ("variables", None) | ("evaluate", None)
+            | ("continue", None) | ("next", None) | ("stepIn", None) | ("stepOut", None) => {
+                Err("the program is not stopped".to_string())
+            }
+
+            (command, _) => Err(format!("unsupported request `{}`", command)),
+        };
+
+        self.client.borrow_mut().respond(request, result);
+        action
+    }
+
+    /// Replace the breakpoints of a source, moving each to the first line at
+    /// or after it with code to stop at.
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = arguments.pointer("/source/path").and_then(Value::as_str).ok_or("no source path")?;
+        let input = self.input(path);
+
+        if let Some(input) = input {
+            self.breakpoints.retain(|&(_, i, _)| i != input);
+        }
+
+        let lines: Vec<u64> = match arguments.get("breakpoints").and_then(Value::as_array) {
+            Some(breakpoints) => breakpoints.iter().filter_map(|breakpoint| breakpoint["line"].as_u64()).collect(),
+            None => Vec::new(),
+        };
+
+        let mut results: Vec<Value> = Vec::new();
+
+        for requested in lines {
+            let line = input.and_then(|input| {
+                let map = &self.stops.map;
+                (requested.saturating_sub(1) as usize..map.line_count(input)).find(|&line| map.has_code(input, line))
+            });
+
+            match (input, line) {
+                (Some(input), Some(line)) => {
+                    let id = self.next_breakpoint;
+                    self.next_breakpoint += 1;
+                    self.breakpoints.push((id, input, line));
+                    results.push(json!({ "id": id, "verified": true, "line": line + 1 }));
+                }
+
+                (Some(_), None) => results.push(json!({
+                    "verified": false,
+                    "line": requested,
+                    "message": "no code to stop at on or after this line",
+                })),
+
+                (None, _) => results.push(json!({
+                    "verified": false,
+                    "line": requested,
+                    "message": "not part of the program",
+                })),
+            }
+        }
+
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    /// The input read from `path`, which may be spelled differently by the client.
+    fn input(&self, path: &str) -> Option<usize> {
+        let names = &self.stops.map.names;
+
+        names.iter().position(|name| name == path).or_else(|| {
+            let path = fs::canonicalize(path).ok()?;
+            names.iter().position(|name| fs::canonicalize(name).ok().as_ref() == Some(&path))
+        })
+    }
+
+    /// The frames innermost first, identified by how many calls are in progress in them.
+    fn stack_trace(&self, interpreter: &Interpreter) -> Value {
+        let frames = interpreter.frames();
+        let map = &self.stops.map;
+
+        let stack: Vec<Value> = (0..=frames.len()).rev().map(|depth| {
+            let name = if depth == 0 { "<top level>".to_string() } else { frames[depth - 1].proto.0.clone() };
+            let mut frame = json!({ "id": depth, "name": name, "line": 0, "column": 0 });
+
+            if let Some((input, offset)) = self.stops.location(depth) {
+                frame["source"] = json!({ "name": map.names[input], "path": map.names[input] });
+                frame["line"] = json!(map.line(input, offset) + 1);
+                frame["column"] = json!(map.column(input, offset) + 1);
+            }
+
+            frame
+        }).collect();
+
+        json!({ "stackFrames": stack, "totalFrames": frames.len() + 1 })
+    }
+}
+
+impl Debugger for Session {
+    fn stop(&mut self, interpreter: &mut Interpreter, expr: &Expr, scope: &[(String, value::Value)]) -> Result<(), String> {
+        let stop = self.stops.arrive(expr, interpreter.frames().len());
+
+        let hits: Vec<u64> = self.breakpoints.iter()
+            .filter(|&&(_, input, line)| stop.arrived && stop.line == Some((input, line)))
+            .map(|&(id, _, _)| id)
+            .collect();
+
+        let mut event = json!({ "threadId": THREAD, "allThreadsStopped": true });
+
+        if !hits.is_empty() {
+            event["reason"] = json!("breakpoint");
+            event["hitBreakpointIds"] = json!(hits);
+        } else if stop.stepped {
+            event["reason"] = json!(if self.entry { "entry" } else { "step" });
+        } else {
+            return Ok(())
+        }
+
+        self.entry = false;
+        self.client.borrow_mut().event("stopped", event);
+
+        loop {
+            let request = self.client.borrow_mut().receive();
+
+            let action = match request {
+                Ok(Some(request)) => self.handle(&request, Some((&mut *interpreter, scope))),
+                Ok(None) | Err(_) => Action::Quit,
+            };
+
+            match action {
+                Action::Wait => {}
+                Action::Resume => return Ok(()),
+                Action::Quit => {
+                    self.quit.set(true);
+                    return Err("stopped debugging".to_string())
+                }
+            }
+        }
+    }
+}
+
+/// The number of calls in progress in the frame of the `frameId` argument.
+fn frame_depth(arguments: &Value, interpreter: &Interpreter) -> Result<usize, String> {
+    match arguments.get("frameId").and_then(Value::as_u64) {
+        Some(depth) if depth as usize <= interpreter.frames().len() => Ok(depth as usize),
+        Some(depth) => Err(format!("no frame {}", depth)),
+        None => Err("no `frameId`".to_string()),
+    }
+}
+
+/// The arguments of the frame with `depth` calls in progress are variables
+/// reference `depth`; the top level has none.
+fn scopes(depth: usize) -> Value {
+    if depth == 0 {
+        return json!({ "scopes": [] })
+    }
+
+    json!({
+        "scopes": [{
+            "name": "Arguments",
+            "presentationHint": "arguments",
+            "variablesReference": depth,
+            "expensive": false,
+        }]
+    })
+}
+
+fn variables(arguments: &Value, interpreter: &Interpreter) -> Result<Value, String> {
+    let frames = interpreter.frames();
+
+    let frame = match arguments.get("variablesReference").and_then(Value::as_u64) {
+        Some(reference) if reference >= 1 && reference as usize <= frames.len() => &frames[reference as usize - 1],
+        _ => return Err("unknown `variablesReference`".to_string()),
+    };
+
+    let variables: Vec<Value> = frame.proto.1.iter().zip(&frame.args)
+        .map(|(name, value)| json!({ "name": name, "value": value.to_string(), "variablesReference": 0 }))
+        .collect();
+
+    Ok(json!({ "variables": variables }))
+}
+
+/// Evaluate the `expression` argument in the frame of `frameId`, or else in
+/// the current one, whose bindings are `scope`.
+fn evaluate(arguments: &Value, interpreter: &mut Interpreter, scope: &[(String, value::Value)]) -> Result<Value, String> {
+    let source = arguments.get("expression").and_then(Value::as_str).ok_or("no `expression`")?;
+    let expr = debug::parse_expr(source)?;
+
+    let depth = match arguments.get("frameId") {
+        Some(_) => frame_depth(arguments, interpreter)?,
+        None => interpreter.frames().len(),
+    };
+
+    let frame_scope: Vec<(String, value::Value)> = if depth == interpreter.frames().len() {
+        scope.to_vec()
+    } else if depth == 0 {
+        Vec::new()
+    } else {
+        let frame = &interpreter.frames()[depth - 1];
+        frame.proto.1.iter().cloned().zip(frame.args.iter().cloned()).collect()
+    };
+
+    let value = interpreter.eval_in_scope(&expr, &frame_scope).map_err(|e| e.to_string())?;
+    Ok(json!({ "result": value.to_string(), "variablesReference": 0 }))
+}
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+
+use ast::*;
+use format::{line_of, line_starts};
+use interp::Interpreter;
+use lexer::Lexer;
+use limits::Limits;
+use modules::{Loader, SpannedInput};
+use parser::Parser;
+use prelude;
+use symbols::spanned_tokens;
+use tokens::{Delim, Token};
+
+/// A program spread over several inputs, ready to be run under the debugger.
+pub struct Program {
+    inputs: Vec<SpannedInput>,
+    prelude: bool,
+    limits: Limits,
+
+    // Functions and externs of the modules the inputs import
+    modules: File,
+}
+
+impl Program {
+    /// Load the named `inputs` with `loader`, as `Loader::load_spanned` does.
+    /// The program runs with the prelude defined first if `prelude` is set.
+    pub fn load(inputs: Vec<(String, String)>, prelude: bool, loader: &mut Loader) -> Result<Program, String> {
+        let (inputs, modules) = loader.load_spanned(inputs)?;
+        Ok(Program { inputs, prelude, limits: Limits::default(), modules: File(modules) })
+    }
+
+    /// Stop evaluations that go beyond `limits`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// An interpreter with every function and extern of the program defined,
+    /// and the program's limits.
+    pub fn interpreter(&self) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(self.limits.clone());
+
+        if self.prelude {
+            prelude::define(&mut interpreter);
+        }
+
+        for item in &self.modules.0 {
+            interpreter.define(item);
+        }
+
+        for input in &self.inputs {
+            for (item, _, _) in &input.items {
+                interpreter.define(item);
+            }
+        }
+
+        interpreter
+    }
+
+    /// The top-level expressions, in the order they run.
+    pub fn top_level(&self) -> Vec<&Expr> {
+        self.inputs.iter()
+            .flat_map(|input| input.items.iter())
+            .filter_map(|(item, _, _)| match **item {
+                Item::Expr(ref expr) => Some(&**expr),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Where the code that `interpreter` runs for the program is in its inputs.
+    pub fn source_map(&self, interpreter: &Interpreter) -> SourceMap {
+        SourceMap {
+            names: self.inputs.iter().map(|input| input.name.clone()).collect(),
+            sources: self.inputs.iter().map(|input| input.source.clone()).collect(),
+            lines: self.inputs.iter().map(|input| line_starts(&input.source)).collect(),
+            positions: self.positions(interpreter),
+        }
+    }
+
+    /// Where each node of the code that can run starts, as an input and a
+    /// byte offset: the top-level expressions, and the definition of each
+    /// function that `interpreter` runs.
+    fn positions(&self, interpreter: &Interpreter) -> HashMap<*const Expr, (usize, usize)> {
+        let mut positions: HashMap<*const Expr, (usize, usize)> = HashMap::new();
+
+        // Only the last definition of a function is the one that runs
+        let mut defined: HashMap<&str, (usize, usize)> = HashMap::new();
+
+        for (i, input) in self.inputs.iter().enumerate() {
+            for (n, (item, start, end)) in input.items.iter().enumerate() {
+                match **item {
+                    Item::Expr(ref expr) => {
+                        let leaves = leaf_offsets(&input.source, *start, *end, 0);
+                        locate(expr, &mut leaves.iter(), i, &mut positions);
+                    }
+
+                    Item::Function(_, _, ref proto, _) => {
+                        defined.insert(&proto.0, (i, n));
+                    }
+
+                    Item::Extern(..) | Item::Test(..) | Item::Import(..) => {}
+                }
+            }
+        }
+
+        for (name, (i, n)) in defined {
+            if let Some(body) = interpreter.function_body(name) {
+                let input = &self.inputs[i];
+                let (_, start, end) = input.items[n];
+
+                // The body follows the `)` that closes the parameters
+                let tokens = spanned_tokens(&input.source[start..end]);
+                let skip = tokens.iter()
+                    .position(|(_, _, tok)| *tok == Token::CloseDelim(Delim::Paren))
+                    .map_or(tokens.len(), |n| n + 1);
+
+                let leaves = leaf_offsets(&input.source, start, end, skip);
+                locate(body, &mut leaves.iter(), i, &mut positions);
+            }
+        }
+
+        positions
+    }
+}
+
+/// The start of each number and identifier among the tokens of an item,
+/// after the first `skip` tokens.
+fn leaf_offsets(source: &str, start: usize, end: usize, skip: usize) -> Vec<usize> {
+    spanned_tokens(&source[start..end]).into_iter()
+        .skip(skip)
+        .filter(|(_, _, tok)| matches!(*tok, Token::Number(_) | Token::Str(_) | Token::Ident(_)))
+        .map(|(offset, _, _)| start + offset)
+        .collect()
+}
+
+/// Record where each node of `expr` starts, from the offsets of the numbers
+/// and identifiers it was parsed from, which appear in the same order as the
+/// numbers, names and calls of the tree. Returns the start of `expr`.
+fn locate(expr: &Expr, leaves: &mut ::std::slice::Iter<usize>, input: usize,
+          positions: &mut HashMap<*const Expr, (usize, usize)>) -> Option<usize> {
+    let start = match *expr {
+        Expr::Number(_) | Expr::Str(_) | Expr::Name(_) => leaves.next().cloned(),
+
+        Expr::Call(_, ref args) => {
+            let start = leaves.next().cloned();
+
+            for arg in args {
+                locate(arg, leaves, input, positions);
+            }
+
+            start
+        }
+
+        Expr::Binary(_, ref lhs, ref rhs) | Expr::Let(_, ref lhs, ref rhs) => {
+            let start = locate(lhs, leaves, input, positions);
+            locate(rhs, leaves, input, positions);
+            start
+        }
+
+        Expr::Unary(_, ref operand) | Expr::Paren(ref operand) => locate(operand, leaves, input, positions),
+
+        Expr::If(ref cond, ref then, ref otherwise) => {
+            let start = locate(cond, leaves, input, positions);
+            locate(then, leaves, input, positions);
+            locate(otherwise, leaves, input, positions);
+            start
+        }
+    };
+
+    if let Some(start) = start {
+        positions.insert(expr as *const Expr, (input, start));
+    }
+
+    start
+}
+
+/// Where the nodes of a program that the interpreter stops at are in its
+/// inputs, by input and byte offset.
+pub struct SourceMap {
+    pub names: Vec<String>,
+    sources: Vec<String>,
+    lines: Vec<Vec<usize>>,
+    positions: HashMap<*const Expr, (usize, usize)>,
+}
+
+impl SourceMap {
+    pub fn position(&self, expr: &Expr) -> Option<(usize, usize)> {
+        self.positions.get(&(expr as *const Expr)).cloned()
+    }
+
+    /// The zero-based line of `offset` in `input`.
+    pub fn line(&self, input: usize, offset: usize) -> usize {
+        line_of(&self.lines[input], offset)
+    }
+
+    /// The zero-based column of `offset` in `input`, in characters.
+    pub fn column(&self, input: usize, offset: usize) -> usize {
+        let start = self.lines[input][self.line(input, offset)];
+        self.sources[input][start..offset].chars().count()
+    }
+
+    pub fn line_count(&self, input: usize) -> usize {
+        self.lines[input].len()
+    }
+
+    /// Whether anything on the zero-based `line` of `input` can be stopped at.
+    pub fn has_code(&self, input: usize, line: usize) -> bool {
+        self.positions.values().any(|&(i, offset)| i == input && self.line(i, offset) == line)
+    }
+
+    pub fn source_line(&self, input: usize, line: usize) -> &str {
+        let start = self.lines[input][line];
+        let end = self.lines[input].get(line + 1).cloned().unwrap_or(self.sources[input].len());
+        self.sources[input][start..end].trim_end()
+    }
+}
+
+/// How far to run before stopping again, regardless of breakpoints.
+#[derive(Clone, Copy)]
+pub enum Resume {
+    Continue,
+    Step,
+
+    // Stop at a depth of at most, or less than, the one given
+    Next(usize),
+    Finish(usize),
+}
+
+/// A stop of the interpreter, as seen by `Stops::arrive`.
+pub struct Stop {
+    pub position: Option<(usize, usize)>,
+
+    // Input and zero-based line
+    pub line: Option<(usize, usize)>,
+
+    // Whether this is the first stop on the line since leaving it, so a
+    // line breakpoint is hit once each time its line is reached
+    pub arrived: bool,
+
+    // Whether stepping should stop here
+    pub stepped: bool,
+}
+
+/// Where a program has stopped so far, and how far it should run next.
+pub struct Stops {
+    pub map: SourceMap,
+    pub resume: Resume,
+
+    // Input, line and depth of the last stop
+    last_line: Option<(usize, usize, usize)>,
+
+    // The last position stopped at in each frame, the top level first
+    locations: Vec<Option<(usize, usize)>>,
+}
+
+impl Stops {
+    /// Start out stepping, so the first stop is taken.
+    pub fn new(map: SourceMap) -> Stops {
+        Stops { map, resume: Resume::Step, last_line: None, locations: Vec::new() }
+    }
+
+    /// Record a stop at `expr` with `depth` calls in progress.
+    pub fn arrive(&mut self, expr: &Expr, depth: usize) -> Stop {
+        let position = self.map.position(expr);
+        let line = position.map(|(input, offset)| (input, self.map.line(input, offset)));
+
+        self.locations.truncate(depth + 1);
+        self.locations.resize(depth + 1, None);
+        self.locations[depth] = position;
+
+        let arrived = match line {
+            Some((input, line)) => self.last_line != Some((input, line, depth)),
+            None => false,
+        };
+
+        if let Some((input, line)) = line {
+            self.last_line = Some((input, line, depth));
+        }
+
+        let stepped = match self.resume {
+            Resume::Continue => false,
+            Resume::Step => true,
+            Resume::Next(from) => depth <= from,
+            Resume::Finish(from) => depth < from,
+        };
+
+        Stop { position, line, arrived, stepped }
+    }
+
+    /// The last position stopped at with `depth` calls in progress.
+    pub fn location(&self, depth: usize) -> Option<(usize, usize)> {
+        self.locations.get(depth).cloned().unwrap_or(None)
+    }
+
+    /// The position stopped at.
+    pub fn current(&self) -> Option<(usize, usize)> {
+        self.locations.last().cloned().unwrap_or(None)
+    }
+}
+
+/// Parse `source` as a single expression.
+pub fn parse_expr(source: &str) -> Result<Box<Expr>, String> {
+    let file = Parser::new(Lexer::new(source.to_string())).and_then(|mut parser| parser.parse())?;
+    let mut items = file.0.into_iter();
+
+    match (items.next(), items.next()) {
+        (Some(item), None) => match *item {
+            Item::Expr(expr) => Ok(expr),
+            _ => Err("expected an expression".to_string()),
+        },
+        _ => Err("expected one expression".to_string()),
+    }
+}
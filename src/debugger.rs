@@ -0,0 +1,314 @@
+use std::cell::Cell;
+use std::ptr;
+use std::rc::Rc;
+
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+use ast::*;
+use debug::{parse_expr, Program, Resume, SourceMap, Stop, Stops};
+use interp::{Debugger, Frame, Interpreter};
+use value::Value;
+
+const HELP: &str = "\
+The program stops before each top-level expression, each function body,
+each call once its arguments are evaluated, and each branch of an `if`.
+
+    break FUNCTION      stop whenever FUNCTION is called (b)
+    break [FILE:]LINE   stop at the code on LINE of FILE, or of the current file
+    delete N            remove breakpoint N (d)
+    breakpoints         list the breakpoints
+    continue            run until a breakpoint is hit (c)
+    step                run to the next stop, entering calls (s)
+    next                run to the next stop outside of calls made from here (n)
+    finish              run until the current function returns (f)
+    backtrace           list the calls in progress, innermost first (bt)
+    print EXPR          evaluate EXPR with the arguments of the current call (p)
+    list                print the source around the current line (l)
+    help                print this help (h)
+    quit                stop debugging (q, or ctrl-d)
+
+An empty line repeats the previous command.";
+
+/// Evaluate the top-level expressions of `program`, printing their values
+/// and taking commands from the terminal at each stop, starting with the first.
+///
+/// Quitting is not an error; a runtime error of the program is.
+pub fn run(program: Program) -> Result<(), String> {
+    let mut interpreter = program.interpreter();
+
+    let quit = Rc::new(Cell::new(false));
+    let session = Session::new(program.source_map(&interpreter), quit.clone())?;
+    interpreter.set_debugger(Box::new(session));
+
+    println!("type `help` for a list of commands");
+
+    for expr in program.top_level() {
+        match interpreter.eval_top_level(expr) {
+            Ok(value) => println!("{}", value),
+            Err(_) if quit.get() => return Ok(()),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+
+    Ok(())
+}
+
+enum Breakpoint {
+    Function(String),
+
+    // Input and zero-based line
+    Line(usize, usize),
+}
+
+/// The terminal side of the debugger, which the interpreter stops in.
+struct Session {
+    stops: Stops,
+    breakpoints: Vec<(usize, Breakpoint)>,
+    next_breakpoint: usize,
+
+    editor: DefaultEditor,
+    last_command: String,
+    quit: Rc<Cell<bool>>,
+}
+
+impl Session {
+    fn new(map: SourceMap, quit: Rc<Cell<bool>>) -> Result<Session, String> {
+        Ok(Session {
+            stops: Stops::new(map),
+            breakpoints: Vec::new(),
+            next_breakpoint: 1,
+            editor: DefaultEditor::new().map_err(|e| e.to_string())?,
+            last_command: String::new(),
+            quit,
+        })
+    }
+
+    /// The number of the breakpoint that `stop` at `expr` hits, if any.
+    fn hit(&self, interpreter: &Interpreter, expr: &Expr, stop: &Stop) -> Option<usize> {
+        let entered = match interpreter.frames().last() {
+            Some(frame) => interpreter.function_body(&frame.proto.0)
+                .is_some_and(|body| ptr::eq(body, expr)),
+            None => false,
+        };
+
+        self.breakpoints.iter().find(|&(_, breakpoint)| match *breakpoint {
+            Breakpoint::Function(ref name) => entered && interpreter.frames().last().is_some_and(|frame| frame.proto.0 == *name),
+            Breakpoint::Line(input, n) => stop.arrived && stop.line == Some((input, n)),
+        }).map(|&(id, _)| id)
+    }
+
+    fn describe(&self, frame: Option<&Frame>, location: Option<(usize, usize)>) -> String {
+        let call = match frame {
+            Some(frame) => {
+                let args: Vec<String> = frame.proto.1.iter().zip(&frame.args)
+                    .map(|(param, arg)| format!("{} = {}", param, arg))
+                    .collect();
+
+                format!("{}({})", frame.proto.0, args.join(", "))
+            }
+            None => "<top level>".to_string(),
+        };
+
+        match location {
+            Some((input, offset)) => {
+                let line = self.stops.map.line(input, offset);
+                format!("{} at {}:{}", call, self.stops.map.names[input], line + 1)
+            }
+            None => call,
+        }
+    }
+
+    /// Run debugger commands until one resumes the program. Returns whether to quit.
+    fn prompt(&mut self, interpreter: &mut Interpreter, scope: &[(String, Value)]) -> bool {
+        loop {
+            let line = match self.editor.readline("(debug) ") {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(_) => return true,
+            };
+
+            let line = if line.trim().is_empty() { self.last_command.clone() } else { line.trim().to_string() };
+            if line.is_empty() {
+                continue
+            }
+
+            let _ = self.editor.add_history_entry(line.as_str());
+            self.last_command = line.clone();
+
+            let (command, argument) = match line.find(char::is_whitespace) {
+                Some(n) => (&line[..n], line[n..].trim()),
+                None => (line.as_str(), ""),
+            };
+
+            let depth = interpreter.frames().len();
+
+            match command {
+                "c" | "continue" => self.stops.resume = Resume::Continue,
+                "s" | "step" => self.stops.resume = Resume::Step,
+                "n" | "next" => self.stops.resume = Resume::Next(depth),
+                "f" | "finish" => self.stops.resume = Resume::Finish(depth),
+                "q" | "quit" => return true,
+
+                "b" | "break" => {
+                    match self.breakpoint(interpreter, argument) {
+                        Ok(breakpoint) => {
+                            let id = self.next_breakpoint;
+                            self.next_breakpoint += 1;
+                            println!("breakpoint {} at {}", id, self.breakpoint_name(&breakpoint));
+                            self.breakpoints.push((id, breakpoint));
+                        }
+                        Err(e) => println!("error: {}", e),
+                    }
+
+                    continue
+                }
+
+                "d" | "delete" => {
+                    match argument.parse::<usize>().ok().and_then(|id| self.breakpoints.iter().position(|&(n, _)| n == id)) {
+                        Some(n) => {
+                            self.breakpoints.remove(n);
+                        }
+                        None => println!("error: no breakpoint `{}`", argument),
+                    }
+
+                    continue
+                }
+
+                "breakpoints" => {
+                    if self.breakpoints.is_empty() {
+                        println!("no breakpoints");
+                    }
+
+                    for &(id, ref breakpoint) in &self.breakpoints {
+                        println!("{}  {}", id, self.breakpoint_name(breakpoint));
+                    }
+
+                    continue
+                }
+
+                "bt" | "backtrace" => {
+                    let frames = interpreter.frames();
+
+                    for depth in (0..=frames.len()).rev() {
+                        let frame = if depth == 0 { None } else { Some(&frames[depth - 1]) };
+                        println!("#{}  {}", frames.len() - depth, self.describe(frame, self.stops.location(depth)));
+                    }
+
+                    continue
+                }
+
+                "p" | "print" => {
+                    match parse_expr(argument).and_then(|expr| interpreter.eval_in_scope(&expr, scope).map_err(|e| e.to_string())) {
+                        Ok(value) => println!("{}", value),
+                        Err(e) => println!("error: {}", e),
+                    }
+
+                    continue
+                }
+
+                "l" | "list" => {
+                    if let Some((input, offset)) = self.stops.current() {
+                        let map = &self.stops.map;
+                        let current = map.line(input, offset);
+                        let first = current.saturating_sub(2);
+                        let last = (current + 2).min(map.line_count(input) - 1);
+
+                        for line in first..=last {
+                            let marker = if line == current { ">" } else { " " };
+                            println!("{} {:>4}  {}", marker, line + 1, map.source_line(input, line));
+                        }
+                    }
+
+                    continue
+                }
+
+                "h" | "help" => {
+                    println!("{}", HELP);
+                    continue
+                }
+
+                _ => {
+                    println!("error: unknown command `{}`, try `help`", command);
+                    continue
+                }
+            }
+
+            return false
+        }
+    }
+
+    /// Parse the argument of `break`: a function name, or a line with an optional file.
+    fn breakpoint(&self, interpreter: &Interpreter, argument: &str) -> Result<Breakpoint, String> {
+        let (file, line) = match argument.rfind(':') {
+            Some(n) => (Some(&argument[..n]), &argument[n + 1..]),
+            None => (None, argument),
+        };
+
+        let line: usize = match line.parse() {
+            Ok(line) if line > 0 => line,
+            Ok(_) => return Err("lines are numbered from 1".to_string()),
+
+            Err(_) if file.is_none() && !argument.is_empty() => {
+                return match interpreter.function_body(argument) {
+                    Some(_) => Ok(Breakpoint::Function(argument.to_string())),
+                    None => Err(format!("no function named `{}`", argument)),
+                }
+            }
+
+            Err(_) => return Err(format!("expected a function or a line, not `{}`", argument)),
+        };
+
+        let input = match file {
+            Some(file) => match self.stops.map.names.iter().position(|name| name == file) {
+                Some(input) => input,
+                None => return Err(format!("no input named `{}`", file)),
+            },
+
+            // The file stopped in
+            None => self.stops.current().map_or(0, |(input, _)| input),
+        };
+
+        if !self.stops.map.has_code(input, line - 1) {
+            return Err(format!("no code to stop at on line {} of {}", line, self.stops.map.names[input]))
+        }
+
+        Ok(Breakpoint::Line(input, line - 1))
+    }
+
+    fn breakpoint_name(&self, breakpoint: &Breakpoint) -> String {
+        match *breakpoint {
+            Breakpoint::Function(ref name) => format!("`{}`", name),
+            Breakpoint::Line(input, line) => format!("{}:{}", self.stops.map.names[input], line + 1),
+        }
+    }
+}
+
+impl Debugger for Session {
+    fn stop(&mut self, interpreter: &mut Interpreter, expr: &Expr, scope: &[(String, Value)]) -> Result<(), String> {
+        let stop = self.stops.arrive(expr, interpreter.frames().len());
+        let hit = self.hit(interpreter, expr, &stop);
+
+        if hit.is_none() && !stop.stepped {
+            return Ok(())
+        }
+
+        let description = self.describe(interpreter.frames().last(), stop.position);
+
+        match hit {
+            Some(id) => println!("breakpoint {}, {}", id, description),
+            None => println!("{}", description),
+        }
+
+        if let Some((input, line)) = stop.line {
+            println!("{:>4}  {}", line + 1, self.stops.map.source_line(input, line));
+        }
+
+        if self.prompt(interpreter, scope) {
+            self.quit.set(true);
+            return Err("stopped debugging".to_string())
+        }
+
+        Ok(())
+    }
+}
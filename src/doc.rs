@@ -0,0 +1,204 @@
+//! Reference pages for the functions and externs of a program, with the
+//! text of their `##` doc comments and links to the functions they call.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use ast::*;
+use callgraph::{CallGraph, NodeKind};
+
+/// A function or extern as the pages list it.
+struct Entry<'a> {
+    name: &'a str,
+
+    // How it is declared, as in `@memo def f(x)` or `pure extern sin(x)`
+    declaration: String,
+    doc: Option<&'a str>,
+
+    // What it calls, each with whether it has an entry to link to
+    calls: Vec<(String, bool)>,
+
+    // Whether it is a `def`
+    definition: bool,
+
+    // Whether links to its name lead here, which is the case for the
+    // definition of a function, or else its first declaration
+    target: bool,
+}
+
+/// The pages as Markdown.
+///
+/// ```
+/// let file = kaleidescope_rs::parse("## Twice `x`.\ndef double(x) x * 2\ndef quad(x) double(double(x))").unwrap();
+/// let page = kaleidescope_rs::doc::markdown(&file, "Arithmetic");
+///
+/// assert!(page.starts_with("# Arithmetic\n"));
+/// assert!(page.contains("## `def double(x)`\n\nTwice `x`.\n"));
+/// assert!(page.contains("Calls [`double`](#double).\n"));
+/// ```
+pub fn markdown(file: &File, title: &str) -> String {
+    let entries = entries(file);
+    let mut out = format!("# {}\n\n", title);
+
+    for entry in &entries {
+        writeln!(out, "- [`{}`](#{})", entry.declaration, entry.name).unwrap();
+    }
+
+    for entry in &entries {
+        out.push('\n');
+
+        if entry.target {
+            writeln!(out, "<a id=\"{}\"></a>", entry.name).unwrap();
+        }
+
+        writeln!(out, "## `{}`", entry.declaration).unwrap();
+
+        if let Some(doc) = entry.doc {
+            write!(out, "\n{}\n", doc).unwrap();
+        }
+
+        if !entry.calls.is_empty() {
+            let calls: Vec<String> = entry.calls.iter().map(|&(ref name, linked)| {
+                if linked { format!("[`{}`](#{})", name, name) } else { format!("`{}`", name) }
+            }).collect();
+
+            write!(out, "\nCalls {}.\n", calls.join(", ")).unwrap();
+        }
+    }
+
+    out
+}
+
+/// The pages as a standalone HTML document.
+///
+/// ```
+/// let file = kaleidescope_rs::parse("## Whether `x < y`.\nextern less(x, y)\ndef min(x, y) if less(x, y) then x else y").unwrap();
+/// let page = kaleidescope_rs::doc::html(&file, "Ordering");
+///
+/// assert!(page.contains("<title>Ordering</title>"));
+/// assert!(page.contains("<p>Whether `x &lt; y`.</p>"));
+/// assert!(page.contains("<p>Calls <a href=\"#less\"><code>less</code></a>.</p>"));
+/// ```
+pub fn html(file: &File, title: &str) -> String {
+    let entries = entries(file);
+    let mut out = String::new();
+
+    writeln!(out, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">").unwrap();
+    writeln!(out, "<title>{}</title>", escape(title)).unwrap();
+    writeln!(out, "<style>{}</style>", STYLE).unwrap();
+    writeln!(out, "</head>\n<body>\n<h1>{}</h1>\n<ul>", escape(title)).unwrap();
+
+    for entry in &entries {
+        writeln!(out, "<li><a href=\"#{}\"><code>{}</code></a></li>", escape(entry.name), escape(&entry.declaration)).unwrap();
+    }
+
+    writeln!(out, "</ul>").unwrap();
+
+    for entry in &entries {
+        if entry.target {
+            writeln!(out, "<section id=\"{}\">", escape(entry.name)).unwrap();
+        } else {
+            writeln!(out, "<section>").unwrap();
+        }
+
+        writeln!(out, "<h2><code>{}</code></h2>", escape(&entry.declaration)).unwrap();
+
+        // Paragraphs are separated by blank lines, as in Markdown
+        for paragraph in entry.doc.into_iter().flat_map(|doc| doc.split("\n\n")) {
+            writeln!(out, "<p>{}</p>", escape(paragraph.trim())).unwrap();
+        }
+
+        if !entry.calls.is_empty() {
+            let calls: Vec<String> = entry.calls.iter().map(|&(ref name, linked)| {
+                if linked {
+                    format!("<a href=\"#{}\"><code>{}</code></a>", escape(name), escape(name))
+                } else {
+                    format!("<code>{}</code>", escape(name))
+                }
+            }).collect();
+
+            writeln!(out, "<p>Calls {}.</p>", calls.join(", ")).unwrap();
+        }
+
+        writeln!(out, "</section>").unwrap();
+    }
+
+    writeln!(out, "</body>\n</html>").unwrap();
+    out
+}
+
+/// Write both pages, as `index.html` and `index.md`, to `dir`, creating it if
+/// it doesn't exist.
+///
+/// ```
+/// let file = kaleidescope_rs::parse("def double(x) x * 2").unwrap();
+/// let dir = std::env::temp_dir().join("kaleidescope-doc-write");
+///
+/// kaleidescope_rs::doc::write(&file, "Arithmetic", &dir).unwrap();
+/// assert!(std::fs::read_to_string(dir.join("index.md")).unwrap().starts_with("# Arithmetic\n"));
+/// assert!(dir.join("index.html").is_file());
+/// ```
+pub fn write(file: &File, title: &str, dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("unable to create `{}`: {}", dir.display(), e))?;
+
+    for (name, page) in [("index.html", html(file, title)), ("index.md", markdown(file, title))] {
+        let path = dir.join(name);
+        fs::write(&path, page).map_err(|e| format!("unable to write `{}`: {}", path.display(), e))?;
+    }
+
+    Ok(())
+}
+
+const STYLE: &str = "body { font-family: sans-serif; max-width: 48em; margin: 2em auto; line-height: 1.5 } \
+section { border-top: 1px solid #ccc } \
+h2 { font-size: 1.1em }";
+
+/// An entry for each function and extern of `file`, in order.
+fn entries(file: &File) -> Vec<Entry<'_>> {
+    let graph = CallGraph::build(file);
+    let defined = |name: &str| graph.lookup(name).is_some_and(|node| graph.nodes[node].kind != NodeKind::Undefined);
+
+    let mut entries: Vec<Entry> = Vec::new();
+
+    for item in &file.0 {
+        let (name, declaration, doc) = match **item {
+            Item::Function(ref doc, ref attrs, ref proto, _) => {
+                let mut words: Vec<String> = attrs.iter().map(|attr| attr.to_string()).collect();
+                words.push(format!("def {}", proto));
+                (&proto.0, words.join(" "), doc)
+            }
+
+            Item::Extern(ref doc, Purity::Pure, ref proto) => (&proto.0, format!("pure extern {}", proto), doc),
+            Item::Extern(ref doc, Purity::Effectful, ref proto) => (&proto.0, format!("extern {}", proto), doc),
+            Item::Expr(_) | Item::Test(..) | Item::Import(..) => continue,
+        };
+
+        let definition = matches!(**item, Item::Function(..));
+
+        let calls = if definition {
+            graph.edges[graph.lookup(name).unwrap()].iter()
+                .map(|&callee| (graph.nodes[callee].name.clone(), defined(&graph.nodes[callee].name)))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        entries.push(Entry { name, declaration, doc: doc.as_deref(), calls, definition, target: false });
+    }
+
+    // A definition takes links to its name over the externs declaring it
+    for i in 0..entries.len() {
+        let name = entries[i].name;
+        let target = entries.iter().position(|entry| entry.name == name && entry.definition)
+            .or_else(|| entries.iter().position(|entry| entry.name == name));
+
+        entries[i].target = target == Some(i);
+    }
+
+    entries
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
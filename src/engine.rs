@@ -0,0 +1,171 @@
+use ast::*;
+use check;
+use interp::{EvalError, Interpreter};
+use limits::Limits;
+use parse;
+use value::Value;
+
+/// An interpreter embedded in a host program, which gives scripts its own
+/// functions as externs and calls the functions they define.
+///
+/// Calls nest at most `limits::DEFAULT_MAX_DEPTH` deep, which fits in the
+/// 8 MiB stack of a main thread. Threads spawned with the 2 MiB that Rust
+/// gives them by default, or allowing deeper calls with `set_limits`, need a
+/// larger stack.
+///
+/// ```
+/// use kaleidescope_rs::{Engine, Value};
+///
+/// let mut engine = Engine::new();
+/// engine.register("lookup_rate", 1, |args| if args[0] == 1.0 { 0.25 } else { 0.5 });
+///
+/// engine.load("extern lookup_rate(id)  def fee(id, amount) amount * lookup_rate(id)").unwrap();
+/// assert_eq!(engine.call("fee", &[Value::from(1.0), Value::from(100.0)]), Ok(Value::from(25.0)));
+/// assert_eq!(engine.call("fee", &[Value::from(2.0), Value::from(100.0)]), Ok(Value::from(50.0)));
+/// ```
+pub struct Engine {
+    interpreter: Interpreter,
+
+    // The functions and externs of every source loaded, which later ones
+    // are checked together with
+    declarations: Vec<Box<Item>>,
+}
+
+impl Default for Engine {
+    fn default() -> Engine {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine { interpreter: Interpreter::new(), declarations: Vec::new() }
+    }
+
+    /// Implement the extern `name`, taking `arity` arguments, with `function`.
+    /// It is used instead of any native of that name, and sources loaded
+    /// afterwards may declare it. Calling it with a string is an error.
+    ///
+    /// ```
+    /// use std::cell::Cell;
+    /// use std::rc::Rc;
+    /// use kaleidescope_rs::Value;
+    ///
+    /// let total = Rc::new(Cell::new(0.0));
+    /// let mut engine = kaleidescope_rs::Engine::new();
+    ///
+    /// let added = total.clone();
+    /// engine.register("add_to_total", 1, move |args| {
+    ///     added.set(added.get() + args[0]);
+    ///     added.get()
+    /// });
+    ///
+    /// assert_eq!(engine.load("extern add_to_total(x)  add_to_total(2)  add_to_total(3)"), Ok(vec![Value::from(2.0), Value::from(5.0)]));
+    /// assert_eq!(total.get(), 5.0);
+    /// ```
+    pub fn register<F>(&mut self, name: &str, arity: usize, function: F) where F: Fn(&[f64]) -> f64 + 'static {
+        self.interpreter.register(name, arity, Box::new(function));
+    }
+
+    /// Parse and check `source`, define its functions and externs, then
+    /// evaluate its top-level expressions in order, returning their values.
+    ///
+    /// Nothing is defined unless the whole source checks, with every extern
+    /// it declares implemented by a registered function or a native with as
+    /// many parameters. The source may call what earlier ones defined, but
+    /// not define it again.
+    ///
+    /// ```
+    /// use kaleidescope_rs::Value;
+    ///
+    /// let mut engine = kaleidescope_rs::Engine::new();
+    /// engine.register("lookup_rate", 1, |_| 0.25);
+    ///
+    /// assert!(engine.load("def double(x) x * 2").unwrap().is_empty());
+    /// assert_eq!(engine.load("double(pow(2, 3))").unwrap_err(), "call to undefined function `pow`");
+    /// assert_eq!(engine.load("extern pow(x, y)  double(pow(2, 3))").unwrap(), [Value::from(16.0)]);
+    ///
+    /// assert_eq!(
+    ///     engine.load("extern lookup_rate(id, day)").unwrap_err(),
+    ///     "extern `lookup_rate` is declared with 2 parameters, but its function takes 1"
+    /// );
+    /// assert_eq!(engine.load("extern lookup_fee(id)").unwrap_err(), "extern `lookup_fee` is not registered");
+    /// ```
+    pub fn load(&mut self, source: &str) -> Result<Vec<Value>, String> {
+        let file = parse(source)?;
+
+        for item in &file.0 {
+            if let Item::Extern(_, _, ref proto) = **item {
+                match self.interpreter.extern_arity(&proto.0) {
+                    Some(arity) if arity == proto.1.len() => {}
+
+                    Some(arity) => return Err(format!(
+                        "extern `{}` is declared with {} parameters, but its function takes {}",
+                        proto.0, proto.1.len(), arity
+                    )),
+
+                    None => return Err(format!("extern `{}` is not registered", proto.0)),
+                }
+            }
+        }
+
+        let mut program = self.declarations.clone();
+        program.extend(file.0.iter().cloned());
+
+        let errors = check::check(&File(program));
+        if !errors.is_empty() {
+            return Err(errors.join("\n"))
+        }
+
+        for item in &file.0 {
+            self.interpreter.define(item);
+
+            if let Item::Function(..) | Item::Extern(..) = **item {
+                self.declarations.push(item.clone());
+            }
+        }
+
+        let mut values: Vec<Value> = Vec::new();
+
+        for item in &file.0 {
+            if let Item::Expr(ref expr) = **item {
+                values.push(self.interpreter.eval_top_level(expr).map_err(|e| e.to_string())?);
+            }
+        }
+
+        Ok(values)
+    }
+
+    /// Call the function or extern `name` of a loaded source with `args`.
+    ///
+    /// ```
+    /// use kaleidescope_rs::{EvalError, Value};
+    ///
+    /// let mut engine = kaleidescope_rs::Engine::new();
+    /// engine.load("def fib(x) if x < 3 then 1 else fib(x - 1) + fib(x - 2)").unwrap();
+    ///
+    /// assert_eq!(engine.call("fib", &[Value::from(10.0)]), Ok(Value::from(55.0)));
+    /// assert_eq!(engine.call("fib", &[]), Err(EvalError::Runtime("`fib` takes 1 arguments, but 0 were given".to_string())));
+    /// assert_eq!(engine.call("fob", &[Value::from(1.0)]), Err(EvalError::Runtime("call to undefined function `fob`".to_string())));
+    /// ```
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, EvalError> {
+        self.interpreter.call_top_level(name, args)
+    }
+
+    /// Stop each later evaluation, by `load` or `call`, that goes beyond
+    /// `limits`.
+    ///
+    /// ```
+    /// use kaleidescope_rs::{EvalError, Value};
+    /// use kaleidescope_rs::limits::{Limit, Limits};
+    ///
+    /// let mut engine = kaleidescope_rs::Engine::new();
+    /// engine.load("def forever(x) forever(x + 1)").unwrap();
+    /// engine.set_limits(Limits { fuel: Some(100), ..Limits::default() });
+    ///
+    /// assert_eq!(engine.call("forever", &[Value::from(0.0)]), Err(EvalError::LimitExceeded(Limit::Fuel(100))));
+    /// ```
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.interpreter.set_limits(limits);
+    }
+}
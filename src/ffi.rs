@@ -0,0 +1,123 @@
+use std::ffi::{CStr, CString};
+use std::mem;
+
+use libc::{self, c_void};
+
+use ast::*;
+use interp::{HostFunction, Interpreter};
+
+/// Most parameters an extern can have to be called through the C ABI.
+pub const MAX_PARAMS: usize = 6;
+
+/// Finds the externs no native implements as C functions of the form
+/// `double f(double, ...)`, in the linked shared libraries in the order they
+/// were linked, then in the process itself.
+///
+/// Libraries stay loaded for as long as the process runs, since the
+/// functions resolved from them may outlive the linker.
+pub struct Linker {
+    // Handles from `dlopen`, ending with the process itself
+    libraries: Vec<*mut c_void>,
+}
+
+impl Default for Linker {
+    fn default() -> Linker {
+        Linker::new()
+    }
+}
+
+impl Linker {
+    pub fn new() -> Linker {
+        Linker { libraries: vec![libc::RTLD_DEFAULT] }
+    }
+
+    /// Load the shared library at `path` to look up symbols in, after those
+    /// linked earlier. A path without a `/` is searched for like the dynamic
+    /// linker does, as with `libm.so.6`.
+    pub fn link(&mut self, path: &str) -> Result<(), String> {
+        let name = CString::new(path).map_err(|_| format!("unable to link `{}`: the path contains a nul byte", path))?;
+        let handle = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+
+        if handle.is_null() {
+            return Err(format!("unable to link `{}`: {}", path, dl_error()))
+        }
+
+        let process = self.libraries.len() - 1;
+        self.libraries.insert(process, handle);
+        Ok(())
+    }
+
+    /// A function that calls the C function `name` with as many arguments
+    /// as `arity`.
+    pub fn resolve(&self, name: &str, arity: usize) -> Result<HostFunction, String> {
+        if arity > MAX_PARAMS {
+            return Err(format!("extern `{}` has {} parameters, but C functions can only be called with up to {}", name, arity, MAX_PARAMS))
+        }
+
+        let symbol = CString::new(name).map_err(|_| format!("extern `{}` can't be a C function", name))?;
+
+        for &library in &self.libraries {
+            let address = unsafe { libc::dlsym(library, symbol.as_ptr()) };
+
+            if !address.is_null() {
+                // The address is only ever called, so it can move into the closure as a number
+                let address = address as usize;
+                return Ok(Box::new(move |args| unsafe { call(address as *mut c_void, args) }))
+            }
+        }
+
+        Err(format!("unresolved extern `{}`: no native or C function has its name", name))
+    }
+
+    /// Register a C function with `interpreter` for each extern of `file` that
+    /// has no definition and that no native or host function implements,
+    /// returning the prototype and error of each one that can't be resolved.
+    pub fn link_externs<'a>(&self, interpreter: &mut Interpreter, file: &'a File) -> Vec<(&'a FuncProto, String)> {
+        let mut unresolved: Vec<(&FuncProto, String)> = Vec::new();
+
+        for item in &file.0 {
+            if let Item::Extern(_, _, ref proto) = **item {
+                if interpreter.function_body(&proto.0).is_some() || interpreter.extern_arity(&proto.0).is_some() {
+                    continue
+                }
+
+                match self.resolve(&proto.0, proto.1.len()) {
+                    Ok(function) => interpreter.register(&proto.0, proto.1.len(), function),
+                    Err(e) => unresolved.push((proto, e)),
+                }
+            }
+        }
+
+        unresolved
+    }
+}
+
+/// The message of the last failed `dlopen`.
+fn dl_error() -> String {
+    let message = unsafe { libc::dlerror() };
+
+    if message.is_null() {
+        return "unknown error".to_string()
+    }
+
+    unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned()
+}
+
+/// Call the C function at `address` with `args`, of which there are at most
+/// `MAX_PARAMS`.
+unsafe fn call(address: *mut c_void, args: &[f64]) -> f64 {
+    match *args {
+        [] => mem::transmute::<*mut c_void, extern "C" fn() -> f64>(address)(),
+        [a] => mem::transmute::<*mut c_void, extern "C" fn(f64) -> f64>(address)(a),
+        [a, b] => mem::transmute::<*mut c_void, extern "C" fn(f64, f64) -> f64>(address)(a, b),
+        [a, b, c] => mem::transmute::<*mut c_void, extern "C" fn(f64, f64, f64) -> f64>(address)(a, b, c),
+        [a, b, c, d] => mem::transmute::<*mut c_void, extern "C" fn(f64, f64, f64, f64) -> f64>(address)(a, b, c, d),
+        [a, b, c, d, e] => {
+            mem::transmute::<*mut c_void, extern "C" fn(f64, f64, f64, f64, f64) -> f64>(address)(a, b, c, d, e)
+        }
+        [a, b, c, d, e, f] => {
+            mem::transmute::<*mut c_void, extern "C" fn(f64, f64, f64, f64, f64, f64) -> f64>(address)(a, b, c, d, e, f)
+        }
+        _ => unreachable!("C functions are resolved with at most {} parameters", MAX_PARAMS),
+    }
+}
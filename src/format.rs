@@ -0,0 +1,381 @@
+use ast::*;
+use lexer::{quote, Lexer};
+use parser::Parser;
+use precedence::Op;
+use tokens::Token;
+
+/// Widest line the formatter aims for unless configured otherwise.
+pub const DEFAULT_WIDTH: usize = 80;
+
+const INDENT: usize = 4;
+
+/// Lay out `source` canonically, keeping its comments.
+///
+/// Parentheses are rewritten to the fewest the grammar needs, and anything
+/// that does not fit in `width` columns is broken over several lines. Items
+/// are separated by blank lines where the source had them, and always around
+/// function definitions. Comments stay on their own line or at the end of the
+/// line they were on, except that those inside an item are moved above it.
+pub fn format_source(source: &str, width: usize) -> Result<String, String> {
+    let lines = line_starts(source);
+    let printer = Printer { width };
+
+    let mut comments = comments(source)?.into_iter().peekable();
+    let mut parser = Parser::new(Lexer::new(source.to_string()))?;
+    let mut entries: Vec<Entry> = Vec::new();
+
+    while let Some((item, start, end)) = parser.parse_spanned_item()? {
+        let (first_line, last_line) = (line_of(&lines, start), line_of(&lines, end));
+
+        while let Some(&(offset, _)) = comments.peek() {
+            if offset >= end {
+                break
+            }
+
+            let (offset, text) = comments.next().unwrap();
+            let line = line_of(&lines, offset).min(first_line);
+            entries.push(Entry::comment(line, text));
+        }
+
+        let mut trailing = None;
+
+        if let Some(&(offset, _)) = comments.peek() {
+            if line_of(&lines, offset) == last_line {
+                trailing = comments.next().map(|(_, text)| text);
+            }
+        }
+
+        let text = printer.item(&item);
+
+        // Without a separator, the previous item would take this one as its continuation,
+        // as in `f(x)` followed by `-1` or `(1)`
+        if text.starts_with('-') || text.starts_with('(') {
+            if let Some(prev) = entries.iter_mut().rev().find(|entry| entry.kind != Kind::Comment) {
+                if prev.kind != Kind::Extern {
+                    prev.text.push(';');
+                }
+            }
+        }
+
+        let kind = match *item {
+            Item::Function(..) => Kind::Function,
+            Item::Extern(..) => Kind::Extern,
+            Item::Expr(_) => Kind::Expr,
+
+            // Imports are grouped like externs
+            Item::Import(..) => Kind::Extern,
+
+            // Tests are spaced out like definitions
+            Item::Test(..) => Kind::Function,
+        };
+
+        entries.push(Entry { first_line, last_line, kind, text, trailing });
+    }
+
+    for (offset, text) in comments {
+        let line = line_of(&lines, offset);
+        entries.push(Entry::comment(line, text));
+    }
+
+    let mut out = String::new();
+
+    for (i, entry) in entries.iter().enumerate() {
+        if i > 0 {
+            let prev = &entries[i - 1];
+
+            // A comment directly above a definition belongs to it
+            let blank = entry.first_line > prev.last_line + 1
+                || prev.kind == Kind::Function
+                || (entry.kind == Kind::Function && prev.kind != Kind::Comment);
+
+            if blank {
+                out.push('\n');
+            }
+        }
+
+        out.push_str(&entry.text);
+
+        if let Some(ref comment) = entry.trailing {
+            out.push_str(&format!(" #{}", comment));
+        }
+
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+#[derive(PartialEq)]
+enum Kind {
+    Function,
+    Extern,
+    Expr,
+    Comment,
+}
+
+/// A formatted item or comment, with the source lines it came from.
+struct Entry {
+    first_line: usize,
+    last_line: usize,
+    kind: Kind,
+    text: String,
+
+    // Comment at the end of the item's last line
+    trailing: Option<String>,
+}
+
+impl Entry {
+    fn comment(line: usize, text: String) -> Entry {
+        Entry { first_line: line, last_line: line, kind: Kind::Comment, text: format!("#{}", text), trailing: None }
+    }
+}
+
+/// The offset and text of every comment in `source`, in order.
+pub fn comments(source: &str) -> Result<Vec<(usize, String)>, String> {
+    let mut lexer = Lexer::new(source.to_string());
+    let mut comments: Vec<(usize, String)> = Vec::new();
+
+    loop {
+        let offset = lexer.offset();
+
+        match lexer.next_token()? {
+            Token::Comment(text) => comments.push((offset, text.trim_end().to_string())),
+            Token::DocComment(text) => comments.push((offset, format!("#{}", text.trim_end()))),
+            Token::Eof => return Ok(comments),
+            _ => {}
+        }
+    }
+}
+
+pub fn line_starts(source: &str) -> Vec<usize> {
+    let mut starts = vec![0];
+    starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+    starts
+}
+
+pub fn line_of(starts: &[usize], offset: usize) -> usize {
+    match starts.binary_search(&offset) {
+        Ok(line) => line,
+        Err(next) => next - 1,
+    }
+}
+
+struct Printer {
+    width: usize,
+}
+
+impl Printer {
+    fn item(&self, item: &Item) -> String {
+        match *item {
+            Item::Function(_, ref attrs, ref proto, ref body) => {
+                let mut out = String::new();
+
+                for attr in attrs {
+                    out.push_str(&format!("{}\n", attr));
+                }
+
+                out.push_str(&self.headed(&format!("def {}", proto), body));
+                out
+            }
+
+            Item::Test(ref name, ref expr) => self.headed(&format!("test {}", name), expr),
+
+            Item::Extern(_, Purity::Pure, ref proto) => format!("pure extern {}", proto),
+            Item::Extern(_, Purity::Effectful, ref proto) => format!("extern {}", proto),
+            Item::Expr(ref expr) => self.layout(expr, 0, 0),
+            Item::Import(ref path, None) => format!("import {}", path),
+            Item::Import(ref path, Some(ref alias)) => format!("import {} as {}", path, alias),
+        }
+    }
+
+    /// `header` followed by `body` on the same line, or indented below it if they don't fit.
+    fn headed(&self, header: &str, body: &Expr) -> String {
+        let line = format!("{} {}", header, flat(body));
+
+        if self.fits(0, &line) {
+            line
+        } else {
+            format!("{}\n{}{}", header, spaces(INDENT), self.layout(body, INDENT, INDENT))
+        }
+    }
+
+    fn fits(&self, column: usize, text: &str) -> bool {
+        column + text.chars().count() <= self.width
+    }
+
+    /// Format `expr` starting at `column`, breaking it over lines indented
+    /// from `indent` if it does not fit.
+    fn layout(&self, expr: &Expr, indent: usize, column: usize) -> String {
+        let expr = unparen(expr);
+        let line = flat(expr);
+
+        if self.fits(column, &line) {
+            return line
+        }
+
+        let inner = indent + INDENT;
+
+        match *expr {
+            Expr::Binary(op, ..) => {
+                let precedence = Op::from_ast_binop(op).precedence();
+
+                // Put each operator of a chain that groups left on its own line
+                let mut first = expr;
+                let mut rest: Vec<(BinOp, &Expr)> = Vec::new();
+
+                while let Expr::Binary(op, ref lhs, ref rhs) = *first {
+                    if Op::from_ast_binop(op).precedence() != precedence {
+                        break
+                    }
+
+                    rest.push((op, rhs));
+                    first = unparen(lhs);
+                }
+
+                let mut out = self.operand(first, precedence, false, indent, column);
+
+                for (op, rhs) in rest.into_iter().rev() {
+                    let symbol = Op::from_ast_binop(op).symbol();
+                    let rhs = self.operand(rhs, precedence, true, inner, inner + symbol.len() + 1);
+                    out.push_str(&format!("\n{}{} {}", spaces(inner), symbol, rhs));
+                }
+
+                out
+            }
+
+            Expr::Unary(op, ref operand) => {
+                let operand = unparen(operand);
+
+                if unary_needs_parens(operand) {
+                    format!("{}({})", unop_symbol(op), self.layout(operand, indent, column + 2))
+                } else {
+                    format!("{}{}", unop_symbol(op), self.layout(operand, indent, column + 1))
+                }
+            }
+
+            Expr::Call(ref name, ref args) if !args.is_empty() => {
+                let args: Vec<String> = args.iter().map(|arg| self.layout(arg, inner, inner)).collect();
+                let separator = format!(",\n{}", spaces(inner));
+                format!("{}(\n{}{}\n{})", name, spaces(inner), args.join(&separator), spaces(indent))
+            }
+
+            Expr::If(ref cond, ref then, ref otherwise) => {
+                let mut out = format!(
+                    "if {} then\n{}{}\n{}else",
+                    self.layout(cond, inner, column + 3),
+                    spaces(inner),
+                    self.layout(then, inner, inner),
+                    spaces(indent)
+                );
+
+                // Chains of conditions read as `else if`
+                if let Expr::If(..) = *unparen(otherwise) {
+                    out.push_str(&format!(" {}", self.layout(otherwise, indent, indent + 5)));
+                } else {
+                    out.push_str(&format!("\n{}{}", spaces(inner), self.layout(otherwise, inner, inner)));
+                }
+
+                out
+            }
+
+            _ => line,
+        }
+    }
+
+    fn operand(&self, expr: &Expr, precedence: usize, right: bool, indent: usize, column: usize) -> String {
+        let expr = unparen(expr);
+
+        if needs_parens(expr, precedence, right) {
+            format!("({})", self.layout(expr, indent, column + 1))
+        } else {
+            self.layout(expr, indent, column)
+        }
+    }
+}
+
+/// Format `expr` on a single line.
+pub fn flat(expr: &Expr) -> String {
+    match *unparen(expr) {
+        Expr::Number(val) => val.to_string(),
+        Expr::Str(ref text) => quote(text),
+        Expr::Name(ref name) => name.clone(),
+
+        Expr::Binary(op, ref lhs, ref rhs) => {
+            let op = Op::from_ast_binop(op);
+            let lhs = flat_operand(lhs, op.precedence(), false);
+            let rhs = flat_operand(rhs, op.precedence(), true);
+            format!("{} {} {}", lhs, op.symbol(), rhs)
+        }
+
+        Expr::Unary(op, ref operand) => {
+            if unary_needs_parens(unparen(operand)) {
+                format!("{}({})", unop_symbol(op), flat(operand))
+            } else {
+                format!("{}{}", unop_symbol(op), flat(operand))
+            }
+        }
+
+        Expr::Call(ref name, ref args) => {
+            let args: Vec<String> = args.iter().map(|arg| flat(arg)).collect();
+            format!("{}({})", name, args.join(", "))
+        }
+
+        Expr::If(ref cond, ref then, ref otherwise) => {
+            format!("if {} then {} else {}", flat(cond), flat(then), flat(otherwise))
+        }
+
+        Expr::Paren(_) => unreachable!(),
+
+        // Only introduced by optimizations, which never run before formatting
+        Expr::Let(..) => unreachable!(),
+    }
+}
+
+fn flat_operand(expr: &Expr, precedence: usize, right: bool) -> String {
+    let expr = unparen(expr);
+
+    if needs_parens(expr, precedence, right) {
+        format!("({})", flat(expr))
+    } else {
+        flat(expr)
+    }
+}
+
+fn unparen(mut expr: &Expr) -> &Expr {
+    while let Expr::Paren(ref inner) = *expr {
+        expr = inner;
+    }
+
+    expr
+}
+
+/// Whether `expr` must be parenthesized as an operand of an operator with
+/// `precedence`; operators group left, so only the right one needs them at equal precedence.
+fn needs_parens(expr: &Expr, precedence: usize, right: bool) -> bool {
+    match *expr {
+        Expr::Binary(op, ..) => {
+            let inner = Op::from_ast_binop(op).precedence();
+            inner < precedence || (right && inner == precedence)
+        }
+
+        // An `else` branch would swallow the rest of the expression
+        Expr::If(..) => true,
+        _ => false,
+    }
+}
+
+/// Unary operators only apply to primary expressions, which don't include other unary ones.
+fn unary_needs_parens(operand: &Expr) -> bool {
+    matches!(*operand, Expr::Binary(..) | Expr::Unary(..) | Expr::If(..))
+}
+
+pub fn unop_symbol(op: UnOp) -> &'static str {
+    match op {
+        UnOp::Neg => "-",
+        UnOp::Not => "!",
+    }
+}
+
+fn spaces(n: usize) -> String {
+    " ".repeat(n)
+}
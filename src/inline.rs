@@ -0,0 +1,401 @@
+use std::collections::{HashMap, HashSet};
+
+use ast::*;
+use callgraph::CallGraph;
+use purity::{self, PurityAnalysis};
+
+/// Knobs controlling how aggressively calls are inlined.
+pub struct InlineOptions {
+    /// Largest cost, in AST nodes, of the expression substituted for a call.
+    pub threshold: usize,
+
+    /// How many levels of nested calls are expanded at a single call site.
+    pub max_depth: usize,
+}
+
+impl InlineOptions {
+    /// Default knobs for an `-O` level; level 0 disables inlining entirely.
+    pub fn for_level(level: usize) -> InlineOptions {
+        let threshold = match level {
+            0 => 0,
+            1 => 8,
+            2 => 24,
+            _ => 64,
+        };
+
+        InlineOptions { threshold, max_depth: 4 }
+    }
+}
+
+/// Substitute the bodies of small, non-recursive functions at their call sites.
+///
+/// A call is only expanded when doing so cannot change what the program does:
+/// every argument with side effects must be used exactly once, and every pure
+/// one that can fail at least once, unconditionally, in the order of the
+/// arguments and before anything else in the callee's body that could have
+/// side effects or fail. Only calls reaching an effectful extern count as side
+/// effects.
+pub fn inline(file: &mut File, opts: &InlineOptions) {
+    if opts.threshold == 0 {
+        return
+    }
+
+    let inliner = Inliner {
+        candidates: collect_candidates(file, opts.threshold),
+        purity: PurityAnalysis::of(file),
+        opts,
+    };
+
+    if inliner.candidates.is_empty() {
+        return
+    }
+
+    for item in file.0.iter_mut() {
+        match **item {
+            Item::Function(_, _, _, ref mut body) | Item::Expr(ref mut body) | Item::Test(_, ref mut body) => {
+                inliner.rewrite(body, 0)
+            }
+
+            Item::Extern(..) | Item::Import(..) => {}
+        }
+    }
+}
+
+/// A function whose body may be substituted at its call sites.
+struct Candidate {
+    params: Vec<String>,
+    body: Box<Expr>,
+}
+
+struct Inliner<'a> {
+    candidates: HashMap<String, Candidate>,
+    purity: PurityAnalysis,
+    opts: &'a InlineOptions,
+}
+
+impl<'a> Inliner<'a> {
+    fn rewrite(&self, expr: &mut Box<Expr>, depth: usize) {
+        let expanded = match **expr {
+            Expr::Number(_) | Expr::Str(_) | Expr::Name(_) => None,
+
+            Expr::Binary(_, ref mut lhs, ref mut rhs) => {
+                self.rewrite(lhs, depth);
+                self.rewrite(rhs, depth);
+                None
+            }
+
+            Expr::Unary(_, ref mut operand) | Expr::Paren(ref mut operand) => {
+                self.rewrite(operand, depth);
+                None
+            }
+
+            Expr::Let(_, ref mut value, ref mut body) => {
+                self.rewrite(value, depth);
+                self.rewrite(body, depth);
+                None
+            }
+
+            Expr::If(ref mut cond, ref mut then, ref mut otherwise) => {
+                self.rewrite(cond, depth);
+                self.rewrite(then, depth);
+                self.rewrite(otherwise, depth);
+                None
+            }
+
+            Expr::Call(ref name, ref mut args) => {
+                for arg in args.iter_mut() {
+                    self.rewrite(arg, depth);
+                }
+
+                if depth < self.opts.max_depth {
+                    self.expand(name, args)
+                } else {
+                    None
+                }
+            }
+        };
+
+        // The callee body may itself contain calls worth expanding
+        if let Some(body) = expanded {
+            *expr = body;
+            self.rewrite(expr, depth + 1);
+        }
+    }
+
+    /// Build the expression replacing `name(args)`, if inlining it is safe and cheap enough.
+    fn expand(&self, name: &str, args: &[Box<Expr>]) -> Option<Box<Expr>> {
+        let callee = self.candidates.get(name)?;
+
+        if callee.params.len() != args.len() {
+            return None
+        }
+
+        let uses = ParamUses::of(&callee.params, &callee.body);
+        let mut cost = size(&callee.body);
+        let mut hazardous: Vec<usize> = Vec::new();
+
+        for (i, arg) in args.iter().enumerate() {
+            if is_trivial(arg) {
+                continue
+            }
+
+            if !self.purity.is_pure(arg) {
+                // Effects must happen exactly once, as they would have before the call
+                if uses.count[i] != 1 || uses.conditional[i] {
+                    return None
+                }
+
+                hazardous.push(i);
+            } else if purity::can_fail(arg) {
+                // A failure must happen, at the first use, and once that has
+                // succeeded the others will too
+                if uses.count[i] == 0 || uses.conditional[i] {
+                    return None
+                }
+
+                hazardous.push(i);
+            }
+
+            if uses.count[i] > 1 {
+                cost += size(arg) * (uses.count[i] - 1);
+            }
+        }
+
+        if cost > self.opts.threshold || !uses.evaluated_first(&hazardous) {
+            return None
+        }
+
+        let bindings: HashMap<&str, &Expr> = callee.params.iter()
+            .map(|p| p.as_str())
+            .zip(args.iter().map(|a| &**a))
+            .collect();
+
+        Some(substitute(&callee.body, &bindings))
+    }
+}
+
+/// Event in the evaluation of a callee body that matters for ordering side effects and failures.
+#[derive(PartialEq)]
+enum Event {
+    Param(usize),
+
+    // An operation that could have side effects or fail
+    Hazard,
+}
+
+/// How each parameter of a function is used by its body.
+struct ParamUses {
+    count: Vec<usize>,
+    conditional: Vec<bool>,
+    events: Vec<Event>,
+}
+
+impl ParamUses {
+    fn of(params: &[String], body: &Expr) -> ParamUses {
+        let mut uses = ParamUses {
+            count: vec![0; params.len()],
+            conditional: vec![false; params.len()],
+            events: Vec::new(),
+        };
+
+        uses.visit(params, body, false);
+        uses
+    }
+
+    /// Walk `expr` in evaluation order; `conditional` is set below short-circuiting operators
+    /// and in the branches of conditionals.
+    fn visit(&mut self, params: &[String], expr: &Expr, conditional: bool) {
+        match *expr {
+            Expr::Number(_) | Expr::Str(_) => {}
+
+            Expr::Name(ref name) => {
+                if let Some(i) = params.iter().position(|p| p == name) {
+                    self.count[i] += 1;
+                    self.conditional[i] |= conditional;
+                    self.events.push(Event::Param(i));
+                }
+            }
+
+            Expr::Binary(ref op, ref lhs, ref rhs) => {
+                let short_circuits = matches!(*op, BinOp::And | BinOp::Or);
+
+                self.visit(params, lhs, conditional);
+                self.visit(params, rhs, conditional || short_circuits);
+
+                if purity::fails_itself(expr) {
+                    self.events.push(Event::Hazard);
+                }
+            }
+
+            Expr::Unary(_, ref operand) => {
+                self.visit(params, operand, conditional);
+
+                if purity::fails_itself(expr) {
+                    self.events.push(Event::Hazard);
+                }
+            }
+
+            Expr::Paren(ref operand) => self.visit(params, operand, conditional),
+
+            Expr::Let(_, ref value, ref body) => {
+                self.visit(params, value, conditional);
+                self.visit(params, body, conditional);
+            }
+
+            Expr::If(ref cond, ref then, ref otherwise) => {
+                self.visit(params, cond, conditional);
+                self.visit(params, then, true);
+                self.visit(params, otherwise, true);
+            }
+
+            // Any call can fail, whether or not it has effects
+            Expr::Call(_, ref args) => {
+                for arg in args {
+                    self.visit(params, arg, conditional);
+                }
+
+                self.events.push(Event::Hazard);
+            }
+        }
+    }
+
+    /// Whether the first uses of the `hazardous` parameters are evaluated, in
+    /// order, before any hazard in the body.
+    fn evaluated_first(&self, hazardous: &[usize]) -> bool {
+        let mut used: Vec<usize> = Vec::new();
+        let mut ordered = self.events.iter().filter(|e| match **e {
+            Event::Param(i) if hazardous.contains(&i) && !used.contains(&i) => {
+                used.push(i);
+                true
+            }
+
+            Event::Param(_) => false,
+            Event::Hazard => true,
+        });
+
+        hazardous.iter().all(|&i| ordered.next() == Some(&Event::Param(i)))
+    }
+}
+
+/// Find every function that may be inlined: small, non-recursive, not opted out, and
+/// not memoized, since an inlined call would bypass its cache.
+fn collect_candidates(file: &File, threshold: usize) -> HashMap<String, Candidate> {
+    let mut functions: HashMap<&str, (&Vec<Attr>, &FuncProto, &Expr)> = HashMap::new();
+    let mut ambiguous: HashSet<&str> = HashSet::new();
+
+    for item in &file.0 {
+        match **item {
+            Item::Function(_, ref attrs, ref proto, ref body) => {
+                if functions.insert(&proto.0, (attrs, proto, body)).is_some() {
+                    ambiguous.insert(&proto.0);
+                }
+            }
+
+            Item::Extern(_, _, ref proto) => {
+                ambiguous.insert(&proto.0);
+            }
+
+            Item::Expr(_) | Item::Test(..) | Item::Import(..) => {}
+        }
+    }
+
+    let graph = CallGraph::build(file);
+    let recursive = graph.recursive();
+
+    functions.iter()
+        .filter(|&(name, &(attrs, proto, body))| {
+            !ambiguous.contains(name)
+                && !attrs.contains(&Attr::NoInline)
+                && !attrs.contains(&Attr::Memo)
+                && size(body) <= threshold
+                && !binds_names(body)
+                && names_params(body, &proto.1)
+                && !graph.lookup(name).is_some_and(|node| recursive[node])
+        })
+        .map(|(name, &(_, proto, body))| {
+            let candidate = Candidate { params: proto.1.clone(), body: Box::new(body.clone()) };
+            (name.to_string(), candidate)
+        })
+        .collect()
+}
+
+/// Number of AST nodes in `expr`, not counting parentheses.
+fn size(expr: &Expr) -> usize {
+    match *expr {
+        Expr::Number(_) | Expr::Str(_) | Expr::Name(_) => 1,
+        Expr::Binary(_, ref lhs, ref rhs) => 1 + size(lhs) + size(rhs),
+        Expr::Unary(_, ref operand) => 1 + size(operand),
+        Expr::Paren(ref inner) => size(inner),
+        Expr::Let(_, ref value, ref body) => 1 + size(value) + size(body),
+        Expr::If(ref cond, ref then, ref otherwise) => 1 + size(cond) + size(then) + size(otherwise),
+        Expr::Call(_, ref args) => 1 + args.iter().map(|a| size(a)).sum::<usize>(),
+    }
+}
+
+/// Expressions cheap enough to duplicate freely.
+fn is_trivial(expr: &Expr) -> bool {
+    match *expr {
+        Expr::Number(_) | Expr::Str(_) | Expr::Name(_) => true,
+        Expr::Paren(ref inner) => is_trivial(inner),
+        _ => false
+    }
+}
+
+/// Whether `expr` contains a `let`, whose name could capture a caller's binding.
+fn binds_names(expr: &Expr) -> bool {
+    match *expr {
+        Expr::Number(_) | Expr::Str(_) | Expr::Name(_) => false,
+        Expr::Binary(_, ref lhs, ref rhs) => binds_names(lhs) || binds_names(rhs),
+        Expr::Unary(_, ref operand) | Expr::Paren(ref operand) => binds_names(operand),
+        Expr::Call(_, ref args) => args.iter().any(|arg| binds_names(arg)),
+        Expr::If(ref cond, ref then, ref otherwise) => {
+            binds_names(cond) || binds_names(then) || binds_names(otherwise)
+        }
+        Expr::Let(..) => true,
+    }
+}
+
+/// Whether every name `expr` reads is one of `params`, so that a free name
+/// can't be captured by a binding at the call site.
+fn names_params(expr: &Expr, params: &[String]) -> bool {
+    match *expr {
+        Expr::Number(_) | Expr::Str(_) => true,
+        Expr::Name(ref name) => params.contains(name),
+        Expr::Binary(_, ref lhs, ref rhs) => names_params(lhs, params) && names_params(rhs, params),
+        Expr::Unary(_, ref operand) | Expr::Paren(ref operand) => names_params(operand, params),
+        Expr::Call(_, ref args) => args.iter().all(|arg| names_params(arg, params)),
+        Expr::If(ref cond, ref then, ref otherwise) => {
+            names_params(cond, params) && names_params(then, params) && names_params(otherwise, params)
+        }
+        Expr::Let(_, ref value, ref body) => names_params(value, params) && names_params(body, params),
+    }
+}
+
+/// Copy `expr`, replacing each bound name by its expression.
+fn substitute(expr: &Expr, bindings: &HashMap<&str, &Expr>) -> Box<Expr> {
+    let expr = match *expr {
+        Expr::Name(ref name) => match bindings.get(name.as_str()) {
+            Some(value) => (*value).clone(),
+            None => Expr::Name(name.clone())
+        },
+
+        Expr::Number(val) => Expr::Number(val),
+        Expr::Str(ref text) => Expr::Str(text.clone()),
+        Expr::Binary(op, ref lhs, ref rhs) => {
+            Expr::Binary(op, substitute(lhs, bindings), substitute(rhs, bindings))
+        }
+        Expr::Unary(op, ref operand) => Expr::Unary(op, substitute(operand, bindings)),
+        Expr::Paren(ref inner) => Expr::Paren(substitute(inner, bindings)),
+        Expr::Let(ref name, ref value, ref body) => {
+            Expr::Let(name.clone(), substitute(value, bindings), substitute(body, bindings))
+        }
+        Expr::If(ref cond, ref then, ref otherwise) => {
+            Expr::If(substitute(cond, bindings), substitute(then, bindings), substitute(otherwise, bindings))
+        }
+        Expr::Call(ref name, ref args) => {
+            Expr::Call(name.clone(), args.iter().map(|a| substitute(a, bindings)).collect())
+        }
+    };
+
+    Box::new(expr)
+}
@@ -0,0 +1,955 @@
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts;
+use std::fmt;
+use std::io::prelude::*;
+use std::io;
+use std::ptr;
+use std::rc::Rc;
+use std::time::Instant;
+
+use ast::*;
+use callgraph::CallGraph;
+use lexer::quote;
+use limits::{Limit, Limits};
+use precedence::Op;
+use profile::Profile;
+use purity::PurityAnalysis;
+use trace::Trace;
+use value::Value;
+
+/// A function of the program embedding the interpreter, which `extern`
+/// declarations can refer to like a native.
+pub type HostFunction = Box<dyn Fn(&[f64]) -> f64>;
+
+type Builtin = fn(&[f64]) -> f64;
+
+/// Native implementations that `extern` declarations can refer to.
+fn builtin(name: &str) -> Option<(usize, Builtin)> {
+    let builtin: (usize, Builtin) = match name {
+        "sin" => (1, |args| args[0].sin()),
+        "cos" => (1, |args| args[0].cos()),
+        "tan" => (1, |args| args[0].tan()),
+        "asin" => (1, |args| args[0].asin()),
+        "acos" => (1, |args| args[0].acos()),
+        "atan" => (1, |args| args[0].atan()),
+        "atan2" => (2, |args| args[0].atan2(args[1])),
+        "exp" => (1, |args| args[0].exp()),
+        "log" => (1, |args| args[0].ln()),
+        "sqrt" => (1, |args| args[0].sqrt()),
+        "pow" => (2, |args| args[0].powf(args[1])),
+        "floor" => (1, |args| args[0].floor()),
+        "ceil" => (1, |args| args[0].ceil()),
+        "fabs" => (1, |args| args[0].abs()),
+        "fmod" => (2, |args| args[0] % args[1]),
+        "abs" => (1, |args| args[0].abs()),
+        "min" => (2, |args| args[0].min(args[1])),
+        "max" => (2, |args| args[0].max(args[1])),
+        "pi" => (0, |_| consts::PI),
+        "e" => (0, |_| consts::E),
+        _ => return None
+    };
+
+    Some(builtin)
+}
+
+/// The numbers `args` are, for natives that only take numbers.
+fn numbers(name: &str, args: &[Value]) -> Result<Vec<f64>, String> {
+    args.iter().enumerate().map(|(i, arg)| match *arg {
+        Value::Number(val) => Ok(val),
+        Value::Str(_) => Err(format!("`{}` takes numbers, but argument {} is a string", name, i + 1)),
+    }).collect()
+}
+
+type StringNative = fn(&[Value]) -> Result<Value, String>;
+
+/// Natives for working with strings, which fail on arguments they can't use.
+fn string_native(name: &str) -> Option<(usize, StringNative)> {
+    let native: (usize, StringNative) = match name {
+        "len" => (1, len),
+        "str" => (1, |args| Ok(Value::from(args[0].text()))),
+        "num" => (1, num),
+        _ => return None
+    };
+
+    Some(native)
+}
+
+/// How many characters the string `args[0]` has.
+fn len(args: &[Value]) -> Result<Value, String> {
+    match args[0] {
+        Value::Str(ref text) => Ok(Value::Number(text.chars().count() as f64)),
+        Value::Number(_) => Err("expected a string, found a number".to_string()),
+    }
+}
+
+/// The number the string `args[0]` spells, leaving a number as it is.
+fn num(args: &[Value]) -> Result<Value, String> {
+    match args[0] {
+        Value::Number(val) => Ok(Value::Number(val)),
+        Value::Str(ref text) => text.trim().parse().map(Value::Number).map_err(|_| format!("{} is not a number", quote(text))),
+    }
+}
+
+type Assertion = fn(&[Value]) -> Result<(), String>;
+
+/// Natives that stop evaluation with an error when they fail, and otherwise
+/// return `1`. Like other natives, they are called through `extern` declarations.
+fn assertion(name: &str) -> Option<(usize, Assertion)> {
+    let assertion: (usize, Assertion) = match name {
+        "assert_eq" => (2, assert_eq),
+        "assert_close" => (3, assert_close),
+        _ => return None
+    };
+
+    Some(assertion)
+}
+
+/// Whether `name` is the name of an assertion native.
+pub fn is_assertion(name: &str) -> bool {
+    assertion(name).is_some()
+}
+
+/// The assertion calls in `expr`, in the order their names appear in the source.
+fn assertion_calls<'a>(expr: &'a Expr, calls: &mut Vec<&'a Expr>) {
+    match *expr {
+        Expr::Number(_) | Expr::Str(_) | Expr::Name(_) => {}
+
+        Expr::Binary(_, ref lhs, ref rhs) | Expr::Let(_, ref lhs, ref rhs) => {
+            assertion_calls(lhs, calls);
+            assertion_calls(rhs, calls);
+        }
+
+        Expr::Unary(_, ref operand) | Expr::Paren(ref operand) => assertion_calls(operand, calls),
+
+        Expr::Call(ref name, ref args) => {
+            if is_assertion(name) {
+                calls.push(expr);
+            }
+
+            for arg in args {
+                assertion_calls(arg, calls);
+            }
+        }
+
+        Expr::If(ref cond, ref then, ref otherwise) => {
+            assertion_calls(cond, calls);
+            assertion_calls(then, calls);
+            assertion_calls(otherwise, calls);
+        }
+    }
+}
+
+fn assert_eq(args: &[Value]) -> Result<(), String> {
+    if args[0] == args[1] {
+        Ok(())
+    } else {
+        Err(format!("{} != {}", args[0], args[1]))
+    }
+}
+
+/// Whether `args[0]` and `args[1]` are no further apart than `args[2]`.
+fn assert_close(args: &[Value]) -> Result<(), String> {
+    let (a, b, tolerance) = (args[0].number()?, args[1].number()?, args[2].number()?);
+    let difference = (a - b).abs();
+
+    if difference <= tolerance {
+        Ok(())
+    } else {
+        Err(format!("{} and {} differ by {}, more than {}", a, b, difference, tolerance))
+    }
+}
+
+type Printer = fn(&mut dyn Write, &[Value]) -> io::Result<()>;
+
+/// Natives that write to the interpreter's output, standard output unless
+/// redirected with `set_output`, and return `0`.
+fn printer(name: &str) -> Option<(usize, Printer)> {
+    let printer: (usize, Printer) = match name {
+        "putchard" => (1, putchard),
+        "printd" => (1, printd),
+        "print" => (1, print),
+        _ => return None
+    };
+
+    Some(printer)
+}
+
+/// Write the character with code `args[0]`, as in the tutorial, or the text
+/// of a string.
+fn putchard(out: &mut dyn Write, args: &[Value]) -> io::Result<()> {
+    match args[0] {
+        Value::Number(val) => out.write_all(&[val as u8]),
+        Value::Str(ref text) => out.write_all(text.as_bytes()),
+    }.and_then(|_| out.flush())
+}
+
+fn printd(out: &mut dyn Write, args: &[Value]) -> io::Result<()> {
+    writeln!(out, "{}", args[0].text())
+}
+
+/// Write the text of `args[0]` without ending the line.
+fn print(out: &mut dyn Write, args: &[Value]) -> io::Result<()> {
+    write!(out, "{}", args[0].text()).and_then(|_| out.flush())
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+}
+
+/// An argument of a memoized call, with numbers as their bit patterns.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum MemoKey {
+    Number(u64),
+    Str(Rc<str>),
+}
+
+impl<'a> From<&'a Value> for MemoKey {
+    fn from(value: &'a Value) -> MemoKey {
+        match *value {
+            Value::Number(val) => MemoKey::Number(val.to_bits()),
+            Value::Str(ref text) => MemoKey::Str(text.clone()),
+        }
+    }
+}
+
+/// Results of a memoized function, keyed by its arguments.
+#[derive(Default)]
+struct MemoCache {
+    entries: HashMap<Vec<MemoKey>, Value>,
+
+    // Keys in insertion order, so the oldest result is evicted first
+    order: VecDeque<Vec<MemoKey>>,
+    stats: MemoStats,
+}
+
+impl MemoCache {
+    fn get(&mut self, key: &[MemoKey]) -> Option<Value> {
+        let value = self.entries.get(key).cloned();
+
+        match value {
+            Some(_) => self.stats.hits += 1,
+            None => self.stats.misses += 1,
+        }
+
+        value
+    }
+
+    fn insert(&mut self, key: Vec<MemoKey>, value: Value, capacity: usize) {
+        if capacity == 0 {
+            return
+        }
+
+        while self.entries.len() >= capacity {
+            match self.order.pop_front() {
+                Some(oldest) => {
+                    self.entries.remove(&oldest);
+                    self.stats.evictions += 1;
+                }
+                None => break
+            }
+        }
+
+        if self.entries.insert(key.clone(), value).is_none() {
+            self.order.push_back(key);
+        }
+    }
+}
+
+/// Most results kept per memoized function unless configured otherwise.
+pub const DEFAULT_MEMO_CAPACITY: usize = 100_000;
+
+enum Callable {
+    Function(Rc<FuncProto>, Rc<Expr>),
+    Extern(Rc<FuncProto>),
+}
+
+/// Where an assertion that failed was called.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssertionSite {
+    /// The function whose body has the call, or `None` for the expression
+    /// evaluated at the top level.
+    pub function: Option<String>,
+
+    /// Which of the assertion calls there it is, counting from 0 in the
+    /// order their names appear in the source.
+    pub index: usize,
+}
+
+/// Why an evaluation ended without a value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    // A mistake in the program, like calling an undefined function
+    Runtime(String),
+
+    // The program needed more than its `Limits` allow
+    LimitExceeded(Limit),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EvalError::Runtime(ref message) => write!(f, "{}", message),
+            EvalError::LimitExceeded(ref limit) => write!(f, "limit exceeded: {}", limit),
+        }
+    }
+}
+
+impl From<String> for EvalError {
+    fn from(message: String) -> EvalError {
+        EvalError::Runtime(message)
+    }
+}
+
+/// A call of a defined function that has not returned yet.
+pub struct Frame {
+    pub proto: Rc<FuncProto>,
+    pub args: Vec<Value>,
+}
+
+/// Receives control at each stop point of an evaluation: every top-level
+/// expression, every function body as it is entered, every call once its
+/// arguments are evaluated, and whichever branch of an `if` is taken.
+pub trait Debugger {
+    /// Called before `expr` is evaluated with the bindings of the current frame
+    /// in `scope`. Evaluations made through `interpreter` meanwhile don't stop,
+    /// and an error ends the whole evaluation with it.
+    fn stop(&mut self, interpreter: &mut Interpreter, expr: &Expr, scope: &[(String, Value)]) -> Result<(), String>;
+}
+
+/// Tree-walking evaluator for programs, whose values are numbers and strings.
+///
+/// Comparisons and logical operators produce `1` or `0`, any non-zero number
+/// and non-empty string is true, and `&&` and `||` only evaluate their
+/// right-hand side when needed. Strings are joined with `+` and compared in
+/// lexicographic order, and a string is never equal to a number.
+pub struct Interpreter {
+    callables: HashMap<String, Callable>,
+    hosts: HashMap<String, (usize, HostFunction)>,
+    memo: HashMap<String, MemoCache>,
+    memo_capacity: usize,
+
+    // Where the assertion that stopped the last evaluation was called, once
+    // the error has unwound to the body the call is in
+    failed_assertion: Option<AssertionSite>,
+
+    // The `Expr::Call` of that assertion until then, only compared with the
+    // addresses of nodes, never dereferenced
+    failed_call: Option<*const Expr>,
+
+    // Only kept while debugging, since every call would otherwise pay for it
+    debugger: Option<Box<dyn Debugger>>,
+    debugging: bool,
+    frames: Vec<Frame>,
+
+    output: Box<dyn Write>,
+    profile: Option<Profile>,
+    trace: Option<Trace>,
+
+    // What the current top-level evaluation has used of its limits
+    limits: Limits,
+    steps: u64,
+    depth: usize,
+    deadline: Option<Instant>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter {
+            callables: HashMap::new(),
+            hosts: HashMap::new(),
+            memo: HashMap::new(),
+            memo_capacity: DEFAULT_MEMO_CAPACITY,
+            failed_assertion: None,
+            failed_call: None,
+            debugger: None,
+            debugging: false,
+            frames: Vec::new(),
+            output: Box::new(io::stdout()),
+            profile: None,
+            trace: None,
+            limits: Limits::default(),
+            steps: 0,
+            depth: 0,
+            deadline: None,
+        }
+    }
+
+    /// Make the function or extern declared by `item` callable.
+    ///
+    /// Definitions replace earlier ones of the same name, and take precedence
+    /// over externs. Top-level expressions, tests and imports are ignored.
+    pub fn define(&mut self, item: &Item) {
+        match *item {
+            Item::Function(_, ref attrs, ref proto, ref body) => {
+                let callable = Callable::Function(Rc::new((**proto).clone()), Rc::new((**body).clone()));
+                self.callables.insert(proto.0.clone(), callable);
+
+                // Results of an earlier definition no longer apply
+                self.memo.remove(&proto.0);
+                if attrs.contains(&Attr::Memo) {
+                    self.memoize(&proto.0);
+                }
+            }
+
+            Item::Extern(_, _, ref proto) => {
+                if let Some(&Callable::Function(..)) = self.callables.get(&proto.0) {
+                    return
+                }
+
+                self.callables.insert(proto.0.clone(), Callable::Extern(Rc::new((**proto).clone())));
+            }
+
+            Item::Expr(_) | Item::Test(..) | Item::Import(..) => {}
+        }
+    }
+
+    /// Implement the extern `name`, taking `arity` arguments, with a function
+    /// of the host program. It is used instead of any native of that name.
+    pub fn register(&mut self, name: &str, arity: usize, function: HostFunction) {
+        self.hosts.insert(name.to_string(), (arity, function));
+    }
+
+    /// How many arguments the extern `name` is implemented with, by a host
+    /// function or a native, if it is implemented at all.
+    pub fn extern_arity(&self, name: &str) -> Option<usize> {
+        match self.hosts.get(name) {
+            Some(&(arity, _)) => Some(arity),
+            None => builtin(name).map(|(arity, _)| arity)
+                .or_else(|| string_native(name).map(|(arity, _)| arity))
+                .or_else(|| assertion(name).map(|(arity, _)| arity))
+                .or_else(|| printer(name).map(|(arity, _)| arity)),
+        }
+    }
+
+    /// Cache the results of the function `name`, as if it was declared `@memo`.
+    pub fn memoize(&mut self, name: &str) {
+        self.memo.entry(name.to_string()).or_default();
+    }
+
+    /// Memoize every function in `file` that is both pure and recursive.
+    pub fn memoize_pure_recursive(&mut self, file: &File) {
+        let purity = PurityAnalysis::of(file);
+        let graph = CallGraph::build(file);
+        let recursive = graph.recursive();
+
+        for item in &file.0 {
+            if let Item::Function(_, _, ref proto, _) = **item {
+                let node = graph.lookup(&proto.0).unwrap();
+                if recursive[node] && purity.purity(&proto.0) == Purity::Pure {
+                    self.memoize(&proto.0);
+                }
+            }
+        }
+    }
+
+    /// Limit how many results are kept per memoized function; the oldest are evicted first.
+    pub fn set_memo_capacity(&mut self, capacity: usize) {
+        self.memo_capacity = capacity;
+    }
+
+    /// Cache statistics for each memoized function that has been called, by name.
+    pub fn memo_stats(&self) -> Vec<(&str, MemoStats)> {
+        let mut stats: Vec<(&str, MemoStats)> = self.memo.iter()
+            .filter(|&(_, cache)| cache.stats.hits + cache.stats.misses > 0)
+            .map(|(name, cache)| (name.as_str(), MemoStats { entries: cache.entries.len(), ..cache.stats }))
+            .collect();
+
+        stats.sort_by_key(|&(name, _)| name);
+        stats
+    }
+
+    /// `memo_stats` as a table for people, or nothing if no memoized function
+    /// has been called.
+    ///
+    /// ```
+    /// use kaleidescope_rs::{Interpreter, Value};
+    ///
+    /// let file = kaleidescope_rs::parse("@memo def sq(x) x * x").unwrap();
+    /// let mut interpreter = Interpreter::new();
+    /// interpreter.define(&file.0[0]);
+    /// assert_eq!(interpreter.memo_report(), "");
+    ///
+    /// interpreter.call_top_level("sq", &[Value::from(3.0)]).unwrap();
+    /// interpreter.call_top_level("sq", &[Value::from(3.0)]).unwrap();
+    /// assert_eq!(interpreter.memo_report(), "memoization statistics:\n    sq: 1 hits, 1 misses, 0 evictions, 1 entries\n");
+    /// ```
+    pub fn memo_report(&self) -> String {
+        let stats = self.memo_stats();
+
+        if stats.is_empty() {
+            return String::new()
+        }
+
+        let mut out = "memoization statistics:\n".to_string();
+        for (name, stats) in stats {
+            out.push_str(&format!(
+                "    {}: {} hits, {} misses, {} evictions, {} entries\n",
+                name, stats.hits, stats.misses, stats.evictions, stats.entries
+            ));
+        }
+
+        out
+    }
+
+    /// Stop later evaluations that go beyond `limits`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Evaluate an expression outside any function, with a fresh budget of
+    /// the limits.
+    ///
+    /// ```
+    /// use kaleidescope_rs::{Interpreter, Value};
+    ///
+    /// let file = kaleidescope_rs::parse("def f(x) x * 2  f(3) + 1").unwrap();
+    /// let mut interpreter = Interpreter::new();
+    /// interpreter.define(&file.0[0]);
+    ///
+    /// if let kaleidescope_rs::ast::Item::Expr(ref expr) = *file.0[1] {
+    ///     assert_eq!(interpreter.eval_top_level(expr), Ok(Value::from(7.0)));
+    /// }
+    /// ```
+    pub fn eval_top_level(&mut self, expr: &Expr) -> Result<Value, EvalError> {
+        let mut scope = Vec::new();
+        self.start();
+        self.stop(expr, &scope)?;
+
+        let value = self.eval(expr, &mut scope);
+        if value.is_err() {
+            self.locate_failed_assertion(expr, None);
+        }
+
+        value
+    }
+
+    /// Call the function or extern named `name` from outside the program,
+    /// with a fresh budget of the limits, as `eval_top_level` evaluates.
+    pub fn call_top_level(&mut self, name: &str, args: &[Value]) -> Result<Value, EvalError> {
+        self.start();
+        self.call(name, args)
+    }
+
+    /// Forget what the last evaluation did and used of the limits.
+    fn start(&mut self) {
+        self.failed_assertion = None;
+        self.failed_call = None;
+        self.frames.clear();
+        self.steps = 0;
+        self.depth = 0;
+        self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    /// Evaluate `expr` with the bindings in `scope`, as if it was written
+    /// inside the function they belong to.
+    pub fn eval_in_scope(&mut self, expr: &Expr, scope: &[(String, Value)]) -> Result<Value, EvalError> {
+        let mut scope = scope.to_vec();
+        self.eval(expr, &mut scope)
+    }
+
+    /// Time every call of a defined function from now on.
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::new());
+    }
+
+    /// What was measured since `enable_profiling`.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Record calls, and perhaps operations, in `trace` from now on.
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.trace = Some(trace);
+    }
+
+    /// Send what `putchard`, `printd` and `print` write to `output` instead of standard output.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    /// Hand control to `debugger` at every stop point of later evaluations.
+    pub fn set_debugger(&mut self, debugger: Box<dyn Debugger>) {
+        self.debugger = Some(debugger);
+        self.debugging = true;
+    }
+
+    /// The calls in progress while debugging, outermost first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    fn stop(&mut self, expr: &Expr, scope: &[(String, Value)]) -> Result<(), EvalError> {
+        match self.debugger.take() {
+            Some(mut debugger) => {
+                let result = debugger.stop(self, expr, scope);
+                self.debugger = Some(debugger);
+                result.map_err(EvalError::Runtime)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Where the assertion that stopped the last evaluation was called.
+    ///
+    /// ```
+    /// use kaleidescope_rs::interp::{AssertionSite, Interpreter};
+    ///
+    /// let file = kaleidescope_rs::parse("
+    ///     extern assert_eq(a, b)
+    ///     def check(x) assert_eq(x, x) && assert_eq(x, 2)
+    ///     assert_eq(1, 1) && check(1)
+    /// ").unwrap();
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// for item in &file.0 {
+    ///     interpreter.define(item);
+    /// }
+    ///
+    /// let expr = match *file.0[2] {
+    ///     kaleidescope_rs::ast::Item::Expr(ref expr) => expr,
+    ///     _ => unreachable!(),
+    /// };
+    /// assert!(interpreter.eval_top_level(expr).is_err());
+    /// assert_eq!(interpreter.failed_assertion(), Some(&AssertionSite { function: Some("check".to_string()), index: 1 }));
+    /// ```
+    pub fn failed_assertion(&self) -> Option<&AssertionSite> {
+        self.failed_assertion.as_ref()
+    }
+
+    /// Record where the failed assertion call is, if it is among the assertion
+    /// calls of `root`, the body of `function` or else a top-level expression.
+    fn locate_failed_assertion(&mut self, root: &Expr, function: Option<&str>) {
+        let call = match self.failed_call {
+            Some(call) => call,
+            None => return,
+        };
+
+        let mut calls: Vec<&Expr> = Vec::new();
+        assertion_calls(root, &mut calls);
+
+        if let Some(index) = calls.iter().position(|&other| ptr::eq(other, call)) {
+            self.failed_assertion = Some(AssertionSite { function: function.map(str::to_string), index });
+            self.failed_call = None;
+        }
+    }
+
+    /// The body the function `name` runs, if it is defined rather than an extern.
+    pub fn function_body(&self, name: &str) -> Option<&Expr> {
+        match self.callables.get(name) {
+            Some(Callable::Function(_, body)) => Some(body),
+            _ => None,
+        }
+    }
+
+    /// Call the function or extern named `name`.
+    ///
+    /// ```
+    /// use kaleidescope_rs::{EvalError, Interpreter, Value};
+    /// use kaleidescope_rs::limits::{Limit, Limits};
+    ///
+    /// let file = kaleidescope_rs::parse("extern sqrt(x)  def loop(x) loop(x + 1)").unwrap();
+    /// let mut interpreter = Interpreter::new();
+    /// for item in &file.0 {
+    ///     interpreter.define(item);
+    /// }
+    ///
+    /// assert_eq!(interpreter.call("sqrt", &[Value::from(9.0)]), Ok(Value::from(3.0)));
+    ///
+    /// interpreter.set_limits(Limits { max_depth: Some(100), ..Limits::default() });
+    /// assert_eq!(interpreter.call("loop", &[Value::from(0.0)]), Err(EvalError::LimitExceeded(Limit::Depth(100))));
+    /// ```
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, EvalError> {
+        let (proto, body) = match self.callables.get(name) {
+            Some(Callable::Function(proto, body)) => (proto.clone(), Some(body.clone())),
+            Some(Callable::Extern(proto)) => (proto.clone(), None),
+            None => return Err(format!("call to undefined function `{}`", name).into()),
+        };
+
+        if proto.1.len() != args.len() {
+            return Err(format!("`{}` takes {} arguments, but {} were given", name, proto.1.len(), args.len()).into())
+        }
+
+        if body.is_none() && self.limits.externs.as_ref().is_some_and(|allowed| !allowed.iter().any(|extern_| extern_ == name)) {
+            return Err(EvalError::LimitExceeded(Limit::Extern(name.to_string())))
+        }
+
+        match body {
+            Some(body) => {
+                let key: Option<Vec<MemoKey>> = match self.memo.get_mut(name) {
+                    Some(cache) => {
+                        let key: Vec<MemoKey> = args.iter().map(MemoKey::from).collect();
+                        if let Some(value) = cache.get(&key) {
+                            return Ok(value)
+                        }
+
+                        Some(key)
+                    }
+                    None => None
+                };
+
+                let mut scope: Vec<(String, Value)> = proto.1.iter().cloned().zip(args.iter().cloned()).collect();
+
+                if let Some(max_depth) = self.limits.max_depth {
+                    if self.depth >= max_depth {
+                        return Err(EvalError::LimitExceeded(Limit::Depth(max_depth)))
+                    }
+                }
+
+                self.depth += 1;
+
+                if self.debugging {
+                    self.frames.push(Frame { proto: proto.clone(), args: args.to_vec() });
+                }
+
+                if let Some(ref mut profile) = self.profile {
+                    profile.enter(name);
+                }
+
+                if let Some(ref mut trace) = self.trace {
+                    trace.enter(&proto, args);
+                }
+
+                let value = self.stop(&body, &scope).and_then(|_| self.eval(&body, &mut scope));
+                if value.is_err() {
+                    self.locate_failed_assertion(&body, Some(name));
+                }
+
+                if let Some(ref mut trace) = self.trace {
+                    trace.exit(name, &value);
+                }
+
+                if let Some(ref mut profile) = self.profile {
+                    profile.exit();
+                }
+
+                if self.debugging {
+                    self.frames.pop();
+                }
+
+                self.depth -= 1;
+
+                let value = value?;
+
+                if let Some(key) = key {
+                    let capacity = self.memo_capacity;
+                    if let Some(cache) = self.memo.get_mut(name) {
+                        cache.insert(key, value.clone(), capacity);
+                    }
+                }
+
+                Ok(value)
+            }
+
+            None => match self.hosts.get(name) {
+                Some(&(arity, ref f)) if arity == args.len() => Ok(Value::Number(f(&numbers(name, args)?))),
+                Some(&(arity, _)) => Err(format!("extern `{}` takes {} arguments, not {}", name, arity, args.len()).into()),
+
+                None => match builtin(name) {
+                    Some((arity, f)) if arity == args.len() => Ok(Value::Number(f(&numbers(name, args)?))),
+                    Some((arity, _)) => Err(format!("extern `{}` takes {} arguments, not {}", name, arity, args.len()).into()),
+
+                    None => match string_native(name) {
+                        Some((arity, f)) if arity == args.len() => Ok(f(args).map_err(|e| format!("`{}` failed: {}", name, e))?),
+                        Some((arity, _)) => Err(format!("extern `{}` takes {} arguments, not {}", name, arity, args.len()).into()),
+
+                        None => match assertion(name) {
+                            Some((arity, f)) if arity == args.len() => {
+                                f(args).map_err(|e| format!("`{}` failed: {}", name, e))?;
+                                Ok(Value::Number(1.0))
+                            }
+
+                            Some((arity, _)) => Err(format!("extern `{}` takes {} arguments, not {}", name, arity, args.len()).into()),
+
+                            None => match printer(name) {
+                                Some((arity, f)) if arity == args.len() => {
+                                    // A closed output doesn't stop the program
+                                    let _ = f(&mut *self.output, args);
+                                    Ok(Value::Number(0.0))
+                                }
+
+                                Some((arity, _)) => Err(format!("extern `{}` takes {} arguments, not {}", name, arity, args.len()).into()),
+                                None => Err(format!("extern `{}` is not available", name).into()),
+                            },
+                        },
+                    },
+                },
+            }
+        }
+    }
+
+    /// Evaluate `expr`, looking names up in `scope` from the innermost binding out.
+    fn eval(&mut self, expr: &Expr, scope: &mut Vec<(String, Value)>) -> Result<Value, EvalError> {
+        self.tick()?;
+
+        match *expr {
+            Expr::Number(val) => Ok(Value::Number(val)),
+            Expr::Str(ref text) => Ok(Value::from(text.as_str())),
+
+            Expr::Name(ref name) => match scope.iter().rev().find(|(n, _)| n == name) {
+                Some((_, val)) => Ok(val.clone()),
+                None => Err(format!("unknown variable `{}`", name).into()),
+            },
+
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                let lhs = self.eval(lhs, scope)?;
+
+                // The right-hand side of `&&` and `||` only when it decides the result
+                let rhs = match op {
+                    BinOp::And if !lhs.is_true() => None,
+                    BinOp::Or if lhs.is_true() => None,
+                    _ => Some(self.eval(rhs, scope)?),
+                };
+
+                let val = match (op, rhs.as_ref()) {
+                    (_, None) => truth(lhs.is_true()),
+                    (BinOp::And, Some(rhs)) | (BinOp::Or, Some(rhs)) => truth(rhs.is_true()),
+                    (_, Some(rhs)) => binary(op, &lhs, rhs)?,
+                };
+
+                if let Some(ref mut trace) = self.trace {
+                    trace.binary(op, &lhs, rhs.as_ref(), &val);
+                }
+
+                Ok(val)
+            }
+
+            Expr::Unary(op, ref operand) => {
+                let val = self.eval(operand, scope)?;
+
+                match (op, val) {
+                    (UnOp::Neg, Value::Number(val)) => Ok(Value::Number(-val)),
+                    (UnOp::Neg, Value::Str(_)) => Err("`-` needs a number, not a string".to_string().into()),
+                    (UnOp::Not, val) => Ok(truth(!val.is_true())),
+                }
+            }
+
+            Expr::Call(ref name, ref args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.eval(arg, scope)?);
+                }
+
+                self.stop(expr, scope)?;
+                let result = self.call(name, &values);
+
+                // Remember where an assertion failed, for reporting the test it was in
+                let extern_call = matches!(self.callables.get(name), Some(Callable::Extern(_)));
+                if result.is_err() && extern_call && self.failed_call.is_none() && self.failed_assertion.is_none() && is_assertion(name) {
+                    self.failed_call = Some(expr as *const Expr);
+                }
+
+                result
+            }
+
+            Expr::Paren(ref inner) => self.eval(inner, scope),
+
+            Expr::If(ref cond, ref then, ref otherwise) => {
+                let branch = if self.eval(cond, scope)?.is_true() { then } else { otherwise };
+                self.stop(branch, scope)?;
+                self.eval(branch, scope)
+            }
+
+            Expr::Let(ref name, ref value, ref body) => {
+                let value = self.eval(value, scope)?;
+                scope.push((name.clone(), value));
+                let result = self.eval(body, scope);
+                scope.pop();
+                result
+            }
+        }
+    }
+
+    /// Count one more expression evaluated against the limits.
+    fn tick(&mut self) -> Result<(), EvalError> {
+        self.steps += 1;
+
+        if let Some(fuel) = self.limits.fuel {
+            if self.steps > fuel {
+                return Err(EvalError::LimitExceeded(Limit::Fuel(fuel)))
+            }
+        }
+
+        // Reading the clock on every step would cost more than the step
+        if self.steps % 1024 == 0 {
+            if let (Some(deadline), Some(timeout)) = (self.deadline, self.limits.timeout) {
+                if Instant::now() >= deadline {
+                    return Err(EvalError::LimitExceeded(Limit::Time(timeout)))
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn truth(b: bool) -> Value {
+    Value::Number(if b { 1.0 } else { 0.0 })
+}
+
+fn binary(op: BinOp, lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    let (lhs, rhs) = match (lhs, rhs) {
+        (&Value::Number(lhs), &Value::Number(rhs)) => (lhs, rhs),
+        (Value::Str(lhs), Value::Str(rhs)) => return strings(op, lhs, rhs),
+
+        // A string is never equal to a number
+        _ if op == BinOp::Eq => return Ok(truth(false)),
+        _ if op == BinOp::Ne => return Ok(truth(true)),
+
+        _ => {
+            let needed = match op {
+                BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Rem => "numbers",
+                _ => "two numbers or two strings",
+            };
+
+            // The same either way round, so that swapping the operands of `*` changes nothing
+            return Err(format!("`{}` needs {}, not a number and a string", Op::from_ast_binop(op).symbol(), needed))
+        }
+    };
+
+    let val = match op {
+        BinOp::Add => Value::Number(lhs + rhs),
+        BinOp::Sub => Value::Number(lhs - rhs),
+        BinOp::Mul => Value::Number(lhs * rhs),
+        BinOp::Div => Value::Number(lhs / rhs),
+        BinOp::Rem => Value::Number(lhs % rhs),
+        BinOp::Eq => truth(lhs == rhs),
+        BinOp::Ne => truth(lhs != rhs),
+        BinOp::Gt => truth(lhs > rhs),
+        BinOp::Ge => truth(lhs >= rhs),
+        BinOp::Lt => truth(lhs < rhs),
+        BinOp::Le => truth(lhs <= rhs),
+        BinOp::And | BinOp::Or => unreachable!(),
+    };
+
+    Ok(val)
+}
+
+/// `lhs op rhs` for two strings, which `+` joins and comparisons order by
+/// their characters.
+fn strings(op: BinOp, lhs: &str, rhs: &str) -> Result<Value, String> {
+    let val = match op {
+        BinOp::Add => Value::from(format!("{}{}", lhs, rhs)),
+        BinOp::Eq => truth(lhs == rhs),
+        BinOp::Ne => truth(lhs != rhs),
+        BinOp::Gt => truth(lhs > rhs),
+        BinOp::Ge => truth(lhs >= rhs),
+        BinOp::Lt => truth(lhs < rhs),
+        BinOp::Le => truth(lhs <= rhs),
+        BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Rem => {
+            return Err(format!("`{}` needs numbers, not strings", Op::from_ast_binop(op).symbol()))
+        }
+        BinOp::And | BinOp::Or => unreachable!(),
+    };
+
+    Ok(val)
+}
@@ -11,7 +11,7 @@ pub struct Lexer {
 impl Lexer {
     pub fn new(body: String) -> Self {
         let mut lexer = Lexer {
-            body: body,
+            body,
             ch: Some('\x00'),
             index: 0,
             next_offset: 0,
@@ -21,6 +21,12 @@ impl Lexer {
         lexer
     }
 
+    /// Byte offset of the next character to be lexed, which is where the
+    /// next token starts and the previous one ended.
+    pub fn offset(&self) -> usize {
+        self.index
+    }
+
     fn advance(&mut self) {
         if self.next_offset < self.body.len() {
             self.ch = self.body[self.next_offset..].chars().next();
@@ -52,6 +58,34 @@ impl Lexer {
         }
     }
 
+    /// Lex the rest of the input, leaving out whitespace and comments. The
+    /// last token is always `Token::Eof`.
+    ///
+    /// ```
+    /// use kaleidescope_rs::lexer::Lexer;
+    /// use kaleidescope_rs::tokens::Token;
+    ///
+    /// let tokens = Lexer::new("x # a comment".to_string()).tokenize().unwrap();
+    /// assert_eq!(tokens, [Token::Ident("x".to_string()), Token::Eof]);
+    ///
+    /// assert_eq!(Lexer::new("x.".to_string()).tokenize().unwrap_err(), "invalid number `.`");
+    /// assert!(Lexer::new("9".repeat(400)).tokenize().unwrap_err().ends_with("is too large"));
+    /// ```
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, String> {
+        let mut tokens: Vec<Token> = Vec::new();
+
+        loop {
+            match self.next_token()? {
+                Token::Whitespace | Token::Comment(_) => {}
+                Token::Eof => break,
+                tok => tokens.push(tok),
+            }
+        }
+
+        tokens.push(Token::Eof);
+        Ok(tokens)
+    }
+
     pub fn next_token(&mut self) -> Result<Token, String> {
         // Detect eof
         let c = match self.ch {
@@ -75,8 +109,17 @@ impl Lexer {
             return Ok(Token::Whitespace);
         }
 
-        // Comments
+        // Comments, which document the item below them when they start with
+        // exactly two `#`s
         if c == '#' {
+            let doc = self.body[self.index..].starts_with("##") && !self.body[self.index..].starts_with("###");
+
+            if doc {
+                self.advance();
+            }
+
+            let text_start = self.next_offset;
+
             loop {
                 self.advance();
 
@@ -86,33 +129,83 @@ impl Lexer {
                 }
             }
 
-            return Ok(Token::Comment);
+            let text = self.body_from(text_start);
+            return Ok(if doc { Token::DocComment(text) } else { Token::Comment(text) });
         }
 
-        // identifiers: [a-zA-Z][a-zA-Z0-9]*
-        if c.is_alphabetic() {
+        // identifiers: [a-zA-Z_][a-zA-Z0-9_]* [ '.' [a-zA-Z_][a-zA-Z0-9_]* ]*
+        if c.is_alphabetic() || c == '_' {
             let ident_start = self.index;
             self.advance();
 
-            while self.ch.unwrap_or('\x00').is_alphanumeric() {
-                self.advance();
+            loop {
+                while self.ch.is_some_and(|c| c.is_alphanumeric() || c == '_') {
+                    self.advance();
+                }
+
+                // A qualified name continues after a dot
+                if self.ch == Some('.') && self.next_ch().is_some_and(|c| c.is_alphabetic() || c == '_') {
+                    self.advance();
+                } else {
+                    break
+                }
             }
 
             return match self.body_from(ident_start).as_ref() {
                 "def" => Ok(Token::Def),
                 "extern" => Ok(Token::Extern),
+                "pure" => Ok(Token::Pure),
+                "if" => Ok(Token::If),
+                "then" => Ok(Token::Then),
+                "else" => Ok(Token::Else),
+                "test" => Ok(Token::Test),
+                "import" => Ok(Token::Import),
                 s => Ok(Token::Ident(s.to_string())),
             };
         }
 
+        // strings: '"' ( [^"\\\n] | '\\' [nrt0"\\] ) * '"'
+        if c == '"' {
+            let mut text = String::new();
+
+            loop {
+                self.advance();
+
+                match self.ch {
+                    Some('"') => break,
+                    Some('\n') | None => return Err("unterminated string".to_string()),
+
+                    Some('\\') => {
+                        self.advance();
+
+                        text.push(match self.ch {
+                            Some('n') => '\n',
+                            Some('r') => '\r',
+                            Some('t') => '\t',
+                            Some('0') => '\0',
+                            Some('"') => '"',
+                            Some('\\') => '\\',
+                            Some('\n') | None => return Err("unterminated string".to_string()),
+                            Some(c) => return Err(format!("unknown escape sequence `\\{}`", c)),
+                        });
+                    }
+
+                    Some(c) => text.push(c),
+                }
+            }
+
+            self.advance();
+            return Ok(Token::Str(text));
+        }
+
         // numbers: [0-9.]+
-        if c.is_numeric() || c == '.' {
+        if c.is_ascii_digit() || c == '.' {
             let num_start = self.index;
 
             if c != '.' {
                 self.advance();
 
-                while self.ch.unwrap_or('\x00').is_numeric() {
+                while self.ch.unwrap_or('\x00').is_ascii_digit() {
                     self.advance();
                 }
             }
@@ -121,15 +214,21 @@ impl Lexer {
             if self.ch.unwrap_or('\x00') == '.' {
                 self.advance();
 
-                while self.ch.unwrap_or('\x00').is_numeric() {
+                while self.ch.unwrap_or('\x00').is_ascii_digit() {
                     self.advance();
                 }
             }
 
             let num_literal = self.body_from(num_start);
-            let float: f64 = num_literal.parse().unwrap();
+            return match num_literal.parse::<f64>() {
+                Ok(float) if float.is_finite() => Ok(Token::Number(float)),
+
+                // Infinity has no literal to format back to, nor json to serialize to
+                Ok(_) => Err(format!("number `{}` is too large", num_literal)),
 
-            return Ok(Token::Number(float));
+                // A lone `.`, with no digits on either side
+                Err(_) => Err(format!("invalid number `{}`", num_literal)),
+            };
         }
 
         // Small tokens
@@ -137,12 +236,12 @@ impl Lexer {
             // Delimiters
             '(' => {
                 self.advance();
-                return Ok(Token::OpenDelim(Delim::Paren));
+                Ok(Token::OpenDelim(Delim::Paren))
             }
 
             ')' => {
                 self.advance();
-                return Ok(Token::CloseDelim(Delim::Paren));
+                Ok(Token::CloseDelim(Delim::Paren))
             }
 
             // Expression tokens
@@ -153,7 +252,7 @@ impl Lexer {
                     return Ok(Token::EqEq);
                 }
 
-                return Ok(Token::Eq);
+                Ok(Token::Eq)
             }
 
             '<' => {
@@ -163,7 +262,7 @@ impl Lexer {
                     return Ok(Token::Le)
                 }
 
-                return Ok(Token::Lt)
+                Ok(Token::Lt)
             }
 
             '>' => {
@@ -173,7 +272,7 @@ impl Lexer {
                     return Ok(Token::Ge);
                 }
 
-                return Ok(Token::Gt)
+                Ok(Token::Gt)
             }
 
             '!' => {
@@ -183,7 +282,7 @@ impl Lexer {
                     return Ok(Token::Ne)
                 }
 
-                return Ok(Token::Bang)
+                Ok(Token::Bang)
             }
 
             '&' => {
@@ -193,7 +292,7 @@ impl Lexer {
                     return Ok(Token::AndAnd)
                 }
 
-                return Err("`&` must be followed by `&` to form valid token".to_string())
+                Err("`&` must be followed by `&` to form valid token".to_string())
             }
 
             '|' => {
@@ -203,51 +302,86 @@ impl Lexer {
                     return Ok(Token::OrOr)
                 }
 
-                return Err("`|` must be followed by `|` to form valid token".to_string())
+                Err("`|` must be followed by `|` to form valid token".to_string())
             }
 
             '+' => {
                 self.advance();
-                return Ok(self.binop(Operator::Plus));
+                Ok(self.binop(Operator::Plus))
             }
 
             '-' => {
                 self.advance();
-                return Ok(self.binop(Operator::Minus));
+                Ok(self.binop(Operator::Minus))
             }
 
             '*' => {
                 self.advance();
-                return Ok(self.binop(Operator::Star));
+                Ok(self.binop(Operator::Star))
             }
 
             '/' => {
                 self.advance();
-                return Ok(self.binop(Operator::Slash));
+                Ok(self.binop(Operator::Slash))
             }
 
             '%' => {
                 self.advance();
-                return Ok(self.binop(Operator::Percent));
+                Ok(self.binop(Operator::Percent))
             }
 
             // Seperators
             
             ',' => {
                 self.advance();
-                return Ok(Token::Comma)
+                Ok(Token::Comma)
             }
 
             ';' => {
                 self.advance();
-                return Ok(Token::Semicolon)
+                Ok(Token::Semicolon)
+            }
+
+            // Attributes
+            '@' => {
+                self.advance();
+                Ok(Token::At)
             }
 
             // If no token matches, return error
             _ => {
                 let msg = format!("Character not recognized: `{}`", c);
-                return Err(msg);
+                Err(msg)
             }
         }
     }
 }
+
+/// `text` as a string literal that lexes back to it.
+///
+/// ```
+/// use kaleidescope_rs::lexer::quote;
+/// use kaleidescope_rs::tokens::Token;
+///
+/// let text = "say \"hi\"\n";
+/// assert_eq!(quote(text), r#""say \"hi\"\n""#);
+/// assert_eq!(kaleidescope_rs::tokenize(&quote(text)).unwrap()[0], Token::Str(text.to_string()));
+/// ```
+pub fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+
+    for c in text.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\0' => quoted.push_str("\\0"),
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,197 @@
+//! Lexing, parsing, checking and evaluation of Kaleidoscope programs.
+//!
+//! Each stage a program goes through is a module: `lexer` turns source into
+//! `tokens`, `parser` builds an `ast::File` from them, `check` finds mistakes
+//! without running it, and `interp` evaluates it. The functions here run those
+//! stages with their defaults; the modules give finer control, and an `Engine`
+//! embeds scripts in a program that gives them its own functions.
+//!
+//! ```
+//! use kaleidescope_rs::Value;
+//!
+//! let file = kaleidescope_rs::parse("def square(x) x * x  square(3) + 1").unwrap();
+//! assert!(kaleidescope_rs::check(&file).is_ok());
+//! assert_eq!(kaleidescope_rs::run(&file), Ok(vec![Value::from(10.0)]));
+//! ```
+
+// The ast boxes every node, including the ones it keeps in vectors
+#![allow(clippy::vec_box)]
+
+extern crate libc;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+
+pub mod ast;
+pub mod callgraph;
+pub mod check;
+mod cse;
+pub mod debug;
+pub mod doc;
+pub mod engine;
+pub mod ffi;
+pub mod format;
+mod inline;
+pub mod interp;
+pub mod lexer;
+pub mod limits;
+pub mod lint;
+pub mod modules;
+pub mod opt;
+pub mod parser;
+mod precedence;
+pub mod prelude;
+pub mod profile;
+mod purity;
+pub mod schema;
+pub mod symbols;
+pub mod testing;
+pub mod tokens;
+pub mod trace;
+pub mod value;
+pub mod visualize;
+
+pub use engine::Engine;
+pub use interp::{EvalError, Interpreter};
+pub use value::Value;
+
+use ast::{File, Item};
+use callgraph::CallGraph;
+use lexer::Lexer;
+use parser::Parser;
+use tokens::Token;
+
+/// Split `source` into tokens, leaving out whitespace and comments. The last
+/// token is always `Token::Eof`.
+///
+/// ```
+/// use kaleidescope_rs::tokens::Token;
+///
+/// let tokens = kaleidescope_rs::tokenize("sin(x) # the sine").unwrap();
+/// assert_eq!(tokens.len(), 5);
+/// assert_eq!(tokens[0], Token::Ident("sin".to_string()));
+/// assert_eq!(tokens[4], Token::Eof);
+///
+/// assert!(kaleidescope_rs::tokenize("a & b").is_err());
+/// assert!(kaleidescope_rs::tokenize(".").is_err());
+/// ```
+pub fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    Lexer::new(source.to_string()).tokenize()
+}
+
+/// Parse `source` as a whole program.
+///
+/// ```
+/// use kaleidescope_rs::ast::Item;
+///
+/// let file = kaleidescope_rs::parse("extern sin(x)  def f(x) sin(x) * 2  f(1)").unwrap();
+/// assert_eq!(file.0.len(), 3);
+/// assert!(matches!(*file.0[1], Item::Function(..)));
+///
+/// assert!(kaleidescope_rs::parse("def f(x x").is_err());
+/// assert!(kaleidescope_rs::parse(".").is_err());
+/// ```
+pub fn parse(source: &str) -> Result<File, String> {
+    let mut parser = Parser::new(Lexer::new(source.to_string()))?;
+    parser.parse().map(|file| *file)
+}
+
+/// Parse each named input on its own, so that one can't run into the next,
+/// and concatenate their items into one program. Errors start with the name
+/// of the input they are in.
+///
+/// ```
+/// let inputs = vec![
+///     ("lib.k".to_string(), "def double(x) x * 2".to_string()),
+///     ("main.k".to_string(), "double(4)".to_string()),
+/// ];
+/// assert_eq!(kaleidescope_rs::parse_inputs(inputs).unwrap().0.len(), 2);
+///
+/// let inputs = vec![("main.k".to_string(), "double(".to_string())];
+/// assert!(kaleidescope_rs::parse_inputs(inputs).unwrap_err().starts_with("main.k: "));
+/// ```
+pub fn parse_inputs(inputs: Vec<(String, String)>) -> Result<File, String> {
+    let mut items: Vec<Box<Item>> = Vec::new();
+
+    for (name, source) in inputs {
+        let file = parse(&source).map_err(|e| format!("{}: {}", name, e))?;
+        items.extend(file.0);
+    }
+
+    Ok(File(items))
+}
+
+/// Find the mistakes `check::check` does, failing with all of them if there
+/// are any.
+///
+/// ```
+/// let file = kaleidescope_rs::parse("def f(x) g(x, y)").unwrap();
+/// assert_eq!(kaleidescope_rs::check(&file), Err(vec![
+///     "call to undefined function `g`".to_string(),
+///     "unknown variable `y`".to_string(),
+/// ]));
+/// ```
+pub fn check(file: &File) -> Result<(), Vec<String>> {
+    let errors = check::check(file);
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+/// Define every function and extern of `file` in a new interpreter, and
+/// evaluate its top-level expressions in order, with the default limits.
+/// Output from externs such as `printd` goes to standard output.
+///
+/// Evaluation stops at the first error. To evaluate with other limits, or to
+/// keep the definitions around, use an `Interpreter` directly.
+///
+/// ```
+/// use kaleidescope_rs::{EvalError, Value};
+///
+/// let file = kaleidescope_rs::parse("def f(x) x + 1  f(1)  f(f(1))").unwrap();
+/// assert_eq!(kaleidescope_rs::run(&file), Ok(vec![Value::from(2.0), Value::from(3.0)]));
+///
+/// let file = kaleidescope_rs::parse("\"kaleido\" + \"scope\"").unwrap();
+/// assert_eq!(kaleidescope_rs::run(&file), Ok(vec![Value::from("kaleidoscope")]));
+///
+/// let file = kaleidescope_rs::parse("extern f(x)  f(1)").unwrap();
+/// assert_eq!(kaleidescope_rs::run(&file), Err(EvalError::Runtime("extern `f` is not available".to_string())));
+/// ```
+pub fn run(file: &File) -> Result<Vec<Value>, EvalError> {
+    let mut interpreter = Interpreter::new();
+
+    for item in &file.0 {
+        interpreter.define(item);
+    }
+
+    let mut values: Vec<Value> = Vec::new();
+
+    for item in &file.0 {
+        if let Item::Expr(ref expr) = **item {
+            values.push(interpreter.eval_top_level(expr)?);
+        }
+    }
+
+    Ok(values)
+}
+
+/// Write `file` out as `kind` of output: `json` for `schema::to_json`,
+/// `sexpr` or `ast-dot` for those of `visualize`, or `callgraph-dot` for the
+/// graph of its calls.
+///
+/// ```
+/// let file = kaleidescope_rs::parse("def f(x) x + 1").unwrap();
+///
+/// assert_eq!(kaleidescope_rs::emit(&file, "sexpr"), Ok("(def (f x) (+ x 1))\n".to_string()));
+/// assert!(kaleidescope_rs::emit(&file, "json").unwrap().ends_with("}\n"));
+/// assert!(kaleidescope_rs::emit(&file, "callgraph-dot").unwrap().starts_with("digraph callgraph {"));
+/// assert_eq!(kaleidescope_rs::emit(&file, "svg"), Err("unknown kind of output `svg`".to_string()));
+/// ```
+pub fn emit(file: &File, kind: &str) -> Result<String, String> {
+    match kind {
+        "json" => Ok(format!("{}\n", schema::to_json(file))),
+        "sexpr" => Ok(visualize::to_sexpr(file)),
+        "ast-dot" => Ok(visualize::to_dot(file)),
+        "callgraph-dot" => Ok(CallGraph::build(file).to_dot()),
+        _ => Err(format!("unknown kind of output `{}`", kind)),
+    }
+}
@@ -0,0 +1,54 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Deepest calls may nest unless configured otherwise: few enough to fit in
+/// an 8 MiB stack, as a main thread has, in a debug build and with bodies that
+/// nest several expressions around their calls. Programs run on a larger
+/// stack can allow more.
+pub const DEFAULT_MAX_DEPTH: usize = 200;
+
+/// How much a program may do before its evaluation is stopped, for running
+/// programs that can't be trusted to finish.
+///
+/// Each top-level expression is evaluated with the whole budget.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Most expressions evaluated.
+    pub fuel: Option<u64>,
+
+    /// Most calls of defined functions in progress at once.
+    pub max_depth: Option<usize>,
+
+    pub timeout: Option<Duration>,
+
+    /// The only externs that may be called, if not all of them.
+    pub externs: Option<Vec<String>>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits { fuel: None, max_depth: Some(DEFAULT_MAX_DEPTH), timeout: None, externs: None }
+    }
+}
+
+/// The limit an evaluation ran into, with the value it was configured with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Limit {
+    Fuel(u64),
+    Depth(usize),
+    Time(Duration),
+
+    // An extern that isn't allowed
+    Extern(String),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Limit::Fuel(fuel) => write!(f, "ran out of fuel after evaluating {} expressions", fuel),
+            Limit::Depth(depth) => write!(f, "calls nested more than {} deep", depth),
+            Limit::Time(timeout) => write!(f, "ran for longer than {}ms", timeout.as_millis()),
+            Limit::Extern(ref name) => write!(f, "extern `{}` is not allowed", name),
+        }
+    }
+}
@@ -0,0 +1,413 @@
+use std::collections::HashMap;
+
+use ast::*;
+use format::{comments, flat, line_of, line_starts};
+use interp::Interpreter;
+use lexer::Lexer;
+use parser::Parser;
+use value::Value;
+
+/// How the findings of a rule are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Allow,  // not reported at all
+    Warn,
+    Deny,   // reported as an error, failing the lint
+}
+
+impl Severity {
+    /// The name used to configure it, as in `--format json`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Severity::Allow => "allow",
+            Severity::Warn => "warn",
+            Severity::Deny => "deny",
+        }
+    }
+
+    /// How findings of this severity are labelled in text output.
+    pub fn label(&self) -> &'static str {
+        match *self {
+            Severity::Allow => "allowed",
+            Severity::Warn => "warning",
+            Severity::Deny => "error",
+        }
+    }
+}
+
+/// A lint rule, checking one item at a time with the whole file for context.
+pub struct Rule {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub default: Severity,
+    check: fn(&File, &Item, &mut Vec<String>),
+}
+
+/// Every rule, in the order their findings on an item are reported.
+pub const RULES: &[Rule] = &[
+    Rule {
+        name: "unused-parameter",
+        description: "a parameter that the function body never uses",
+        default: Severity::Warn,
+        check: unused_parameter,
+    },
+    Rule {
+        name: "self-comparison",
+        description: "an expression compared with itself, such as `x == x`",
+        default: Severity::Warn,
+        check: self_comparison,
+    },
+    Rule {
+        name: "constant-condition",
+        description: "an `if` whose condition does not depend on any variable or call",
+        default: Severity::Warn,
+        check: constant_condition,
+    },
+    Rule {
+        name: "remainder-by-zero",
+        description: "a remainder by zero, which is always NaN",
+        default: Severity::Deny,
+        check: remainder_by_zero,
+    },
+    Rule {
+        name: "float-equality",
+        description: "`==` or `!=` on the result of a division, which is rarely exact",
+        default: Severity::Warn,
+        check: float_equality,
+    },
+    Rule {
+        name: "shadowed-extern",
+        description: "a function defined with the name of an extern declared in the same file",
+        default: Severity::Warn,
+        check: shadowed_extern,
+    },
+];
+
+/// Reported for a suppression comment naming a rule that doesn't exist.
+pub const UNKNOWN_RULE: &str = "unknown-rule";
+
+pub fn rule(name: &str) -> Option<&'static Rule> {
+    RULES.iter().find(|rule| rule.name == name)
+}
+
+/// The severity of each rule, starting from their defaults.
+pub struct Config {
+    severities: HashMap<&'static str, Severity>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config::new()
+    }
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config { severities: RULES.iter().map(|rule| (rule.name, rule.default)).collect() }
+    }
+
+    /// Set the severity of the rule `name`, or of every rule for `all`.
+    pub fn set(&mut self, name: &str, severity: Severity) -> Result<(), String> {
+        if name == "all" {
+            for severity_of in self.severities.values_mut() {
+                *severity_of = severity;
+            }
+
+            return Ok(())
+        }
+
+        match rule(name) {
+            Some(rule) => {
+                self.severities.insert(rule.name, severity);
+                Ok(())
+            }
+
+            None => Err(format!("unknown lint rule `{}`", name)),
+        }
+    }
+
+    pub fn severity(&self, name: &str) -> Severity {
+        self.severities[name]
+    }
+}
+
+/// A finding, positioned at the start of the item it was found in, or at
+/// the comment for unknown rules. Lines and columns count from 1.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Run every rule that `config` doesn't allow over the items of `source`.
+///
+/// A comment `# lint: allow(rule, ...)` suppresses the rules it names for the
+/// item on the same line, or else for the next item.
+pub fn lint_source(source: &str, config: &Config) -> Result<Vec<Diagnostic>, String> {
+    let lines = line_starts(source);
+    let position = |offset: usize| {
+        let line = line_of(&lines, offset);
+        (line + 1, source[lines[line]..offset].chars().count() + 1)
+    };
+
+    let mut parser = Parser::new(Lexer::new(source.to_string()))?;
+    let mut file = File(Vec::new());
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+
+    while let Some((item, start, end)) = parser.parse_spanned_item()? {
+        file.0.push(item);
+        spans.push((start, end));
+    }
+
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut allowed: Vec<Vec<String>> = vec![Vec::new(); spans.len()];
+
+    for (offset, text) in comments(source)? {
+        let names = match suppressed_rules(&text) {
+            Some(names) => names,
+            None => continue,
+        };
+
+        let line = line_of(&lines, offset);
+        let target = spans.iter()
+            .position(|&(start, end)| line_of(&lines, start) <= line && line <= line_of(&lines, end))
+            .or_else(|| spans.iter().position(|&(start, _)| start > offset));
+
+        for name in &names {
+            if rule(name).is_none() {
+                let (line, column) = position(offset);
+                let message = format!("unknown lint rule `{}`", name);
+                diagnostics.push(Diagnostic { rule: UNKNOWN_RULE, severity: Severity::Warn, message, line, column });
+            }
+        }
+
+        if let Some(target) = target {
+            allowed[target].extend(names);
+        }
+    }
+
+    for (i, item) in file.0.iter().enumerate() {
+        let (line, column) = position(spans[i].0);
+
+        for rule in RULES {
+            let severity = config.severity(rule.name);
+            if severity == Severity::Allow || allowed[i].iter().any(|name| name == rule.name) {
+                continue
+            }
+
+            let mut messages: Vec<String> = Vec::new();
+            (rule.check)(&file, item, &mut messages);
+
+            for message in messages {
+                diagnostics.push(Diagnostic { rule: rule.name, severity, message, line, column });
+            }
+        }
+    }
+
+    // Stable, so findings on an item keep the order of the rules
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+    Ok(diagnostics)
+}
+
+/// The rules named by a suppression comment, given the text after its `#`.
+fn suppressed_rules(comment: &str) -> Option<Vec<String>> {
+    let rest = comment.trim().strip_prefix("lint:")?.trim_start();
+    let list = rest.strip_prefix("allow(")?.trim_end().strip_suffix(')')?;
+
+    Some(list.split(',').map(|name| name.trim().to_string()).filter(|name| !name.is_empty()).collect())
+}
+
+/// Call `f` on every expression of `item`, outermost first.
+fn each_expr<F: FnMut(&Expr)>(item: &Item, mut f: F) {
+    match *item {
+        Item::Function(_, _, _, ref body) => walk(body, &mut f),
+        Item::Expr(ref expr) | Item::Test(_, ref expr) => walk(expr, &mut f),
+        Item::Extern(..) | Item::Import(..) => {}
+    }
+}
+
+fn walk<F: FnMut(&Expr)>(expr: &Expr, f: &mut F) {
+    f(expr);
+
+    match *expr {
+        Expr::Number(_) | Expr::Str(_) | Expr::Name(_) => {}
+
+        Expr::Binary(_, ref lhs, ref rhs) | Expr::Let(_, ref lhs, ref rhs) => {
+            walk(lhs, f);
+            walk(rhs, f);
+        }
+
+        Expr::Unary(_, ref operand) | Expr::Paren(ref operand) => walk(operand, f),
+
+        Expr::Call(_, ref args) => {
+            for arg in args {
+                walk(arg, f);
+            }
+        }
+
+        Expr::If(ref cond, ref then, ref otherwise) => {
+            walk(cond, f);
+            walk(then, f);
+            walk(otherwise, f);
+        }
+    }
+}
+
+fn unparen(mut expr: &Expr) -> &Expr {
+    while let Expr::Paren(ref inner) = *expr {
+        expr = inner;
+    }
+
+    expr
+}
+
+fn uses(expr: &Expr, name: &str) -> bool {
+    match *expr {
+        Expr::Number(_) | Expr::Str(_) => false,
+        Expr::Name(ref used) => used == name,
+        Expr::Binary(_, ref lhs, ref rhs) => uses(lhs, name) || uses(rhs, name),
+        Expr::Unary(_, ref operand) | Expr::Paren(ref operand) => uses(operand, name),
+        Expr::Call(_, ref args) => args.iter().any(|arg| uses(arg, name)),
+        Expr::If(ref cond, ref then, ref otherwise) => {
+            uses(cond, name) || uses(then, name) || uses(otherwise, name)
+        }
+
+        // The binding hides the name in the body
+        Expr::Let(ref bound, ref value, ref body) => uses(value, name) || (bound != name && uses(body, name)),
+    }
+}
+
+/// Whether `a` and `b` are the same expression, ignoring parentheses.
+fn same_expr(a: &Expr, b: &Expr) -> bool {
+    match (unparen(a), unparen(b)) {
+        (&Expr::Number(a), &Expr::Number(b)) => a.to_bits() == b.to_bits(),
+        (Expr::Str(a), Expr::Str(b)) => a == b,
+        (Expr::Name(a), Expr::Name(b)) => a == b,
+
+        (Expr::Binary(op_a, lhs_a, rhs_a), Expr::Binary(op_b, lhs_b, rhs_b)) => {
+            op_a == op_b && same_expr(lhs_a, lhs_b) && same_expr(rhs_a, rhs_b)
+        }
+
+        (Expr::Unary(op_a, a), Expr::Unary(op_b, b)) => op_a == op_b && same_expr(a, b),
+
+        (Expr::Call(name_a, args_a), Expr::Call(name_b, args_b)) => {
+            name_a == name_b && args_a.len() == args_b.len()
+                && args_a.iter().zip(args_b).all(|(a, b)| same_expr(a, b))
+        }
+
+        (Expr::If(cond_a, then_a, else_a), Expr::If(cond_b, then_b, else_b)) => {
+            same_expr(cond_a, cond_b) && same_expr(then_a, then_b) && same_expr(else_a, else_b)
+        }
+
+        _ => false,
+    }
+}
+
+fn has_calls(expr: &Expr) -> bool {
+    let mut calls = false;
+    walk(expr, &mut |expr| calls |= matches!(*expr, Expr::Call(..)));
+    calls
+}
+
+/// The value of `expr` if it depends on no variable or call.
+fn constant(expr: &Expr) -> Option<Value> {
+    let mut constant = true;
+    walk(expr, &mut |expr| constant &= !matches!(*expr, Expr::Name(_) | Expr::Call(..) | Expr::Let(..)));
+
+    if !constant {
+        return None
+    }
+
+    Interpreter::new().eval_top_level(expr).ok()
+}
+
+fn unused_parameter(_: &File, item: &Item, out: &mut Vec<String>) {
+    if let Item::Function(_, _, ref proto, ref body) = *item {
+        for param in &proto.1 {
+            if !uses(body, param) {
+                out.push(format!("parameter `{}` of `{}` is never used", param, proto.0));
+            }
+        }
+    }
+}
+
+fn self_comparison(_: &File, item: &Item, out: &mut Vec<String>) {
+    each_expr(item, |expr| {
+        if let Expr::Binary(op, ref lhs, ref rhs) = *expr {
+            // Calls may give a different result each time
+            if !same_expr(lhs, rhs) || has_calls(lhs) {
+                return
+            }
+
+            let outcome = match op {
+                BinOp::Eq | BinOp::Ge | BinOp::Le => "always true, unless it is NaN",
+                BinOp::Ne => "always false, unless it is NaN",
+                BinOp::Gt | BinOp::Lt => "always false",
+                _ => return,
+            };
+
+            out.push(format!("`{}` compares `{}` with itself, which is {}", flat(expr), flat(lhs), outcome));
+        }
+    });
+}
+
+fn constant_condition(_: &File, item: &Item, out: &mut Vec<String>) {
+    each_expr(item, |expr| {
+        if let Expr::If(ref cond, ..) = *expr {
+            if let Some(value) = constant(cond) {
+                let (truth, unused) = if value.is_true() { ("true", "else") } else { ("false", "then") };
+                out.push(format!("condition `{}` is always {}, so the `{}` branch never runs", flat(cond), truth, unused));
+            }
+        }
+    });
+}
+
+fn remainder_by_zero(_: &File, item: &Item, out: &mut Vec<String>) {
+    each_expr(item, |expr| {
+        if let Expr::Binary(BinOp::Rem, _, ref rhs) = *expr {
+            if constant(rhs) == Some(Value::Number(0.0)) {
+                out.push(format!("`{}` is a remainder by zero, which is always NaN", flat(expr)));
+            }
+        }
+    });
+}
+
+/// Whether `expr` computes its value by dividing, outside of any call.
+fn divides(expr: &Expr) -> bool {
+    match *expr {
+        Expr::Binary(BinOp::Div, ..) => true,
+        Expr::Binary(_, ref lhs, ref rhs) => divides(lhs) || divides(rhs),
+        Expr::Unary(_, ref operand) | Expr::Paren(ref operand) => divides(operand),
+        _ => false,
+    }
+}
+
+fn float_equality(_: &File, item: &Item, out: &mut Vec<String>) {
+    each_expr(item, |expr| {
+        if let Expr::Binary(BinOp::Eq, ref lhs, ref rhs) | Expr::Binary(BinOp::Ne, ref lhs, ref rhs) = *expr {
+            if divides(lhs) || divides(rhs) {
+                out.push(format!(
+                    "`{}` compares the result of a division exactly; compare the difference with a tolerance instead",
+                    flat(expr)
+                ));
+            }
+        }
+    });
+}
+
+fn shadowed_extern(file: &File, item: &Item, out: &mut Vec<String>) {
+    if let Item::Function(_, _, ref proto, _) = *item {
+        let declared = file.0.iter().any(|other| match **other {
+            Item::Extern(_, _, ref ext) => ext.0 == proto.0,
+            _ => false,
+        });
+
+        if declared {
+            out.push(format!("`{}` is also declared `extern`, and calls to it run this definition instead", proto.0));
+        }
+    }
+}
@@ -0,0 +1,460 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::prelude::*;
+use std::io;
+
+use serde_json::Value;
+
+use ast::*;
+use symbols::{Occurrence, SymbolIndex};
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// LSP enumerations
+const SYNC_FULL: u64 = 1;
+const SEVERITY_ERROR: u64 = 1;
+const SYMBOL_FUNCTION: u64 = 12;
+const COMPLETION_FUNCTION: u64 = 3;
+
+/// Serve the Language Server Protocol over stdin and stdout until the client
+/// sends `exit`, returning the exit status it should cause.
+///
+/// Documents are synchronized in full on every change, and each one is
+/// reindexed and its diagnostics published as it changes.
+pub fn run() -> Result<i32, String> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut server = Server { documents: HashMap::new(), shutdown: false, outbox: Vec::new() };
+
+    loop {
+        let exit = match read_message(&mut input) {
+            Ok(Some(message)) => server.handle(&message),
+
+            // The client went away without asking us to exit
+            Ok(None) => return Ok(1),
+
+            // The id of a message that couldn't be read is unknown
+            Err(ReadError::Malformed(message)) => {
+                server.outbox.push(json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": { "code": PARSE_ERROR, "message": message },
+                }));
+                false
+            }
+
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let stdout = io::stdout();
+        let mut output = stdout.lock();
+        for message in server.outbox.drain(..) {
+            write_message(&mut output, &message)?;
+        }
+
+        if exit {
+            return Ok(if server.shutdown { 0 } else { 1 })
+        }
+    }
+}
+
+/// An open document and what is known about its identifiers.
+struct Document {
+    text: String,
+    lines: LineIndex,
+    index: SymbolIndex,
+}
+
+impl Document {
+    fn new(text: String) -> Document {
+        Document { lines: LineIndex::new(&text), index: SymbolIndex::of(&text), text }
+    }
+}
+
+struct Server {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+
+    // Responses and notifications waiting to be written
+    outbox: Vec<Value>,
+}
+
+impl Server {
+    /// Handle one message from the client, returning whether to exit.
+    fn handle(&mut self, message: &Value) -> bool {
+        let method = message.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+
+            // Notifications get no response
+            None => {
+                match method {
+                    "exit" => return true,
+                    "textDocument/didOpen" => self.did_open(&params),
+                    "textDocument/didChange" => self.did_change(&params),
+                    "textDocument/didClose" => self.did_close(&params),
+                    _ => {}
+                }
+
+                return false
+            }
+        };
+
+        let result = match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+
+            "textDocument/hover" => self.hover(&params),
+            "textDocument/definition" => self.definition(&params),
+            "textDocument/references" => self.references(&params),
+            "textDocument/documentSymbol" => self.document_symbols(&params),
+            "textDocument/completion" => self.completion(&params),
+            _ => Err((METHOD_NOT_FOUND, format!("unsupported method `{}`", method))),
+        };
+
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        };
+
+        self.outbox.push(response);
+        false
+    }
+
+    fn did_open(&mut self, params: &Value) {
+        let uri = params.pointer("/textDocument/uri").and_then(Value::as_str);
+        let text = params.pointer("/textDocument/text").and_then(Value::as_str);
+
+        if let (Some(uri), Some(text)) = (uri, text) {
+            self.update(uri, text.to_string());
+        }
+    }
+
+    fn did_change(&mut self, params: &Value) {
+        let uri = params.pointer("/textDocument/uri").and_then(Value::as_str);
+
+        // With full synchronization, the last change holds the whole text
+        let text = params.get("contentChanges")
+            .and_then(Value::as_array)
+            .and_then(|changes| changes.last())
+            .and_then(|change| change.get("text"))
+            .and_then(Value::as_str);
+
+        if let (Some(uri), Some(text)) = (uri, text) {
+            self.update(uri, text.to_string());
+        }
+    }
+
+    fn did_close(&mut self, params: &Value) {
+        if let Some(uri) = params.pointer("/textDocument/uri").and_then(Value::as_str) {
+            self.documents.remove(uri);
+            self.publish_diagnostics(uri, Vec::new());
+        }
+    }
+
+    fn update(&mut self, uri: &str, text: String) {
+        let document = Document::new(text);
+
+        let diagnostics = document.index.problems().into_iter().map(|(start, end, message)| {
+            json!({
+                "range": document.lines.range(&document.text, start, end),
+                "severity": SEVERITY_ERROR,
+                "source": "kaleidescope",
+                "message": message,
+            })
+        }).collect();
+
+        self.documents.insert(uri.to_string(), document);
+        self.publish_diagnostics(uri, diagnostics);
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str, diagnostics: Vec<Value>) {
+        self.outbox.push(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }));
+    }
+
+    fn document(&self, params: &Value) -> Result<(&str, &Document), (i64, String)> {
+        let uri = params.pointer("/textDocument/uri").and_then(Value::as_str)
+            .ok_or((INVALID_PARAMS, "missing `textDocument.uri`".to_string()))?;
+
+        match self.documents.get_key_value(uri) {
+            Some((uri, document)) => Ok((uri, document)),
+            None => Err((INVALID_PARAMS, format!("`{}` is not open", uri))),
+        }
+    }
+
+    /// The document named by `params` and the identifier at its `position`, if any.
+    fn occurrence<'a>(&'a self, params: &Value) -> Result<(&'a str, &'a Document, Option<&'a Occurrence>), (i64, String)> {
+        let (uri, document) = self.document(params)?;
+
+        let line = params.pointer("/position/line").and_then(Value::as_u64);
+        let character = params.pointer("/position/character").and_then(Value::as_u64);
+
+        let offset = match (line, character) {
+            (Some(line), Some(character)) => document.lines.offset(&document.text, line as usize, character as usize),
+            _ => return Err((INVALID_PARAMS, "missing `position`".to_string())),
+        };
+
+        Ok((uri, document, document.index.at(offset)))
+    }
+
+    fn hover(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (_, document, occ) = self.occurrence(params)?;
+        let index = &document.index;
+
+        let occ = match occ {
+            Some(occ) => occ,
+            None => return Ok(Value::Null),
+        };
+
+        let contents = if occ.is_function() {
+            match index.declaration(&occ.name) {
+                // Followed by the doc comment, which is Markdown
+                Some(item) => match *item.item {
+                    Item::Function(Some(ref doc), ..) | Item::Extern(Some(ref doc), ..) => {
+                        format!("```\n{}\n```\n{}", signature(&item.item), doc)
+                    }
+
+                    _ => format!("```\n{}\n```", signature(&item.item)),
+                },
+
+                None => return Ok(Value::Null),
+            }
+        } else {
+            match index.definition(occ) {
+                Some(param) => format!("```\n{}\n```\nparameter of `{}`", param.name, signature(&index.items[param.item].item)),
+                None => return Ok(Value::Null),
+            }
+        };
+
+        Ok(json!({
+            "contents": { "kind": "markdown", "value": contents },
+            "range": document.lines.range(&document.text, occ.start, occ.end),
+        }))
+    }
+
+    fn definition(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, document, occ) = self.occurrence(params)?;
+
+        match occ.and_then(|occ| document.index.definition(occ)) {
+            Some(def) => Ok(location(uri, document, def.start, def.end)),
+            None => Ok(Value::Null),
+        }
+    }
+
+    fn references(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (uri, document, occ) = self.occurrence(params)?;
+        let include_declaration = params.pointer("/context/includeDeclaration")
+            .and_then(Value::as_bool)
+            .unwrap_or(true);
+
+        let occ = match occ {
+            Some(occ) => occ,
+            None => return Ok(Value::Null),
+        };
+
+        let references = document.index.references(occ).into_iter()
+            .filter(|other| include_declaration || !is_declaration(other))
+            .map(|other| location(uri, document, other.start, other.end))
+            .collect();
+
+        Ok(Value::Array(references))
+    }
+
+    fn document_symbols(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (_, document) = self.document(params)?;
+        let index = &document.index;
+
+        let symbols = index.occurrences.iter().filter(|occ| is_declaration(occ) && occ.is_function()).map(|occ| {
+            let item = &index.items[occ.item];
+
+            json!({
+                "name": occ.name,
+                "detail": signature(&item.item),
+                "kind": SYMBOL_FUNCTION,
+                "range": document.lines.range(&document.text, item.start, item.end),
+                "selectionRange": document.lines.range(&document.text, occ.start, occ.end),
+            })
+        }).collect();
+
+        Ok(Value::Array(symbols))
+    }
+
+    fn completion(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let (_, document) = self.document(params)?;
+        let index = &document.index;
+
+        let mut names: Vec<&str> = index.occurrences.iter()
+            .filter(|occ| is_declaration(occ) && occ.is_function())
+            .map(|occ| occ.name.as_str())
+            .collect();
+
+        names.sort();
+        names.dedup();
+
+        let items = names.into_iter().filter_map(|name| {
+            index.declaration(name).map(|item| json!({
+                "label": name,
+                "kind": COMPLETION_FUNCTION,
+                "detail": signature(&item.item),
+            }))
+        }).collect();
+
+        Ok(Value::Array(items))
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            "textDocumentSync": SYNC_FULL,
+            "hoverProvider": true,
+            "definitionProvider": true,
+            "referencesProvider": true,
+            "documentSymbolProvider": true,
+            "completionProvider": {},
+        },
+        "serverInfo": { "name": "kaleidescope-rs" },
+    })
+}
+
+fn is_declaration(occ: &Occurrence) -> bool {
+    use symbols::Role;
+    matches!(occ.role, Role::FunctionDef | Role::ExternDecl | Role::Param)
+}
+
+/// How a function or extern is declared, as shown on hover.
+fn signature(item: &Item) -> String {
+    match *item {
+        Item::Function(_, ref attrs, ref proto, _) => {
+            let mut signature: Vec<String> = attrs.iter().map(|attr| attr.to_string()).collect();
+            signature.push(format!("def {}", proto));
+            signature.join(" ")
+        }
+
+        Item::Extern(_, Purity::Pure, ref proto) => format!("pure extern {}", proto),
+        Item::Extern(_, Purity::Effectful, ref proto) => format!("extern {}", proto),
+        Item::Expr(_) | Item::Test(..) | Item::Import(..) => String::new(),
+    }
+}
+
+fn location(uri: &str, document: &Document, start: usize, end: usize) -> Value {
+    json!({ "uri": uri, "range": document.lines.range(&document.text, start, end) })
+}
+
+/// Converts between byte offsets and LSP positions, which count lines and
+/// UTF-16 code units within a line.
+struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(text: &str) -> LineIndex {
+        let mut starts = vec![0];
+        starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        LineIndex { starts }
+    }
+
+    fn position(&self, text: &str, offset: usize) -> Value {
+        let line = match self.starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+
+        let offset = offset.min(text.len());
+        let character: usize = text[self.starts[line]..offset].chars().map(char::len_utf16).sum();
+        json!({ "line": line, "character": character })
+    }
+
+    fn range(&self, text: &str, start: usize, end: usize) -> Value {
+        json!({ "start": self.position(text, start), "end": self.position(text, end) })
+    }
+
+    fn offset(&self, text: &str, line: usize, character: usize) -> usize {
+        let start = match self.starts.get(line) {
+            Some(&start) => start,
+            None => return text.len(),
+        };
+
+        let mut units = 0;
+
+        for (i, c) in text[start..].char_indices() {
+            if units >= character || c == '\n' {
+                return start + i
+            }
+
+            units += c.len_utf16();
+        }
+
+        text.len()
+    }
+}
+
+/// Why a message couldn't be read.
+pub enum ReadError {
+    Io(String),
+
+    // A message whose header or body is invalid; the input can still be
+    // read from the next message on
+    Malformed(String),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReadError::Io(ref message) | ReadError::Malformed(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Read one message framed by a `Content-Length` header, or `None` at end of input.
+pub fn read_message<R: BufRead>(input: &mut R) -> Result<Option<Value>, ReadError> {
+    let mut length: Result<usize, String> = Err("message without a `Content-Length` header".to_string());
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).map_err(|e| ReadError::Io(e.to_string()))? == 0 {
+            return Ok(None)
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break
+        }
+
+        // The body of a message with no usable length is left unread, and
+        // runs into the first header of the next one
+        if let Some(n) = header.find("Content-Length:") {
+            let value = &header[n + "Content-Length:".len()..];
+            length = value.trim().parse().map_err(|_| format!("invalid header `{}`", &header[n..]));
+        }
+    }
+
+    let mut body = vec![0; length.map_err(ReadError::Malformed)?];
+    input.read_exact(&mut body).map_err(|e| ReadError::Io(e.to_string()))?;
+
+    let body = String::from_utf8(body).map_err(|e| ReadError::Malformed(format!("invalid message: {}", e)))?;
+    serde_json::from_str(&body).map(Some).map_err(|e| ReadError::Malformed(format!("invalid message: {}", e)))
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Value) -> Result<(), String> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| output.flush())
+        .map_err(|e| e.to_string())
+}
@@ -1,102 +1,784 @@
 extern crate getopts;
-extern crate rustc_serialize;
+extern crate kaleidescope_rs;
+extern crate rustyline;
+#[macro_use]
+extern crate serde_json;
 
-use getopts::Options;
-use rustc_serialize::json;
+use getopts::{Matches, Options};
 
 use std::env;
-use std::fs::File;
+use std::fs;
 use std::io::prelude::*;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
 use std::process::exit;
+use std::thread;
+use std::time::Duration;
 
-mod ast;
-mod lexer;
-mod parser;
-mod precedence;
-mod tokens;
+// The frontends; everything they build on is in the library
+mod dap;
+mod debugger;
+mod lsp;
+mod repl;
 
+// Modules used by the frontends as well, which refer to them from the root
+use kaleidescope_rs::{ast, check, debug, doc, format, interp, lexer, lint, modules, opt, parser, prelude, schema, symbols, testing, tokens, value};
+
+use ast::Item;
+use interp::{EvalError, Interpreter};
+use kaleidescope_rs::ffi::Linker;
+use kaleidescope_rs::limits::Limits;
+use kaleidescope_rs::trace::{Trace, TraceOptions};
 use lexer::Lexer;
-use parser::Parser;
+use lint::Severity;
+use modules::Loader;
+use opt::OptOptions;
+use repl::Session;
 use tokens::Token;
 
-fn print_usage(program: &str, opts: Options) -> ! {
-    let usage = format!("Usage: {} [options] filename", program);
-    println!("{}", opts.usage(&usage));
-    exit(1);
+// Exit statuses; `fmt --check`, `lint` and `test` exit with 1 when they find problems
+const EXIT_USAGE: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_INVALID: i32 = 4;  // syntax errors, and errors found by `check`
+const EXIT_RUNTIME: i32 = 5;
+const EXIT_LIMIT: i32 = 6;
+
+// Calls in the program nest as calls in the interpreter, so programs run on a
+// thread with room for `MAX_DEPTH` of them, far more than the library allows
+// by default
+const STACK_SIZE: usize = 1 << 30;
+const MAX_DEPTH: usize = 10_000;
+
+/// Every subcommand, with the summary printed in usage messages.
+const COMMANDS: &[(&str, &str)] = &[
+    ("tokens", "Print the tokens of the program"),
+    ("parse", "Print the ast of the program"),
+    ("check", "Check the program for errors and describe its functions"),
+    ("run", "Run the program; the default when no command is given"),
+    ("build", "Optimize the program and write its ast as json"),
+    ("test", "Run the `test` items of the program"),
+    ("debug", "Run the program under a debugger that reads commands from the terminal"),
+    ("lint", "Report suspicious code, with configurable rules"),
+    ("doc", "Write HTML and Markdown pages documenting the program's functions"),
+    ("fmt", "Format source files in place"),
+    ("repl", "Start an interactive session, after running any input given"),
+    ("lsp", "Start a language server on standard input and output"),
+    ("dap", "Start a debug adapter on standard input and output"),
+];
+
+fn usage(program: &str, command: &str, opts: &Options) -> String {
+    let operands = match command {
+        "lsp" | "dap" => "",
+        "fmt" => " [FILE ...]",
+        _ => " [FILE ...] [-e SOURCE ...]",
+    };
+
+    let mut text = format!("Usage: {} {} [options]{}\n\n", program, command, operands);
+
+    text.push_str(match command {
+        "lsp" => "Speaks the language server protocol over standard input and output.",
+        "dap" => "Speaks the debug adapter protocol over standard input and output. The program\n\
+                  is the file named by the `program` argument of `launch`.",
+        "fmt" => "Formats standard input to standard output when no files are given, or for `-`.",
+        "repl" => "Inputs are run before the session starts.",
+        "test" => "Inputs are read as one program, as for `run`, and each `test NAME EXPR` in it\n\
+                   passes when EXPR is true. Tests run on their own, with every function and\n\
+                   extern defined but no top-level expression run. The prelude declares the\n\
+                   assertions `assert_eq(a, b)` and `assert_close(a, b, eps)`.",
+        "debug" => "Inputs are read in order, files then `-e` snippets, as one program. Commands\n\
+                    are read from standard input, so the program can't be; enter `help` at the\n\
+                    prompt for the list of commands.",
+        "doc" => "Inputs are read in order, files then `-e` snippets, as one program. A comment\n\
+                  starting with `##` documents the `def` or `extern` below it. The pages are\n\
+                  written as `index.html` and `index.md`.",
+        "lint" => "Each input is linted on its own; `-` names standard input, which is also read\n\
+                   when there are no other inputs. A comment `# lint: allow(RULE, ...)` allows\n\
+                   rules for the item on its line, or else the next item.",
+        _ => "Inputs are read in order, files then `-e` snippets, as one program. `-` names\n\
+              standard input, which is also read when there are no other inputs.",
+    });
+
+    text.push_str("\n\nCommands:");
+    for &(name, summary) in COMMANDS {
+        text.push_str(&format!("\n    {:<8}{}", name, summary));
+    }
+
+    opts.usage(&text)
+}
+
+fn print_help(program: &str, command: &str, opts: &Options) -> ! {
+    println!("{}", usage(program, command, opts));
+    exit(0);
+}
+
+fn usage_error(program: &str, command: &str, opts: &Options, message: &str) -> ! {
+    eprintln!("error: {}\n", message);
+    eprintln!("{}", usage(program, command, opts));
+    exit(EXIT_USAGE);
+}
+
+fn fail(status: i32, message: &str) -> ! {
+    eprintln!("error: {}", message);
+    exit(status);
+}
+
+fn options(command: &str) -> Options {
+    let mut opts = Options::new();
+
+    if command != "fmt" && command != "lsp" && command != "dap" {
+        opts.optmulti("e", "", "Source to run after the files, may be repeated", "SOURCE");
+    }
+
+    if matches!(command, "check" | "run" | "build" | "repl") {
+        opts.optopt("", "from-ast", "Read the program from json written by `parse` or `build` instead", "FILE");
+    }
+
+    if matches!(command, "check" | "run" | "build" | "test" | "debug" | "repl") {
+        opts.optflag("", "no-prelude", "Don't declare the math, string, assertion and output functions for the program");
+        opts.optmulti("I", "module-path", "Look for modules imported by name in DIR too, may be repeated", "DIR");
+    }
+
+    if matches!(command, "run" | "build") {
+        opts.optopt("O", "", "Optimization level, from 0 (default) to 3", "LEVEL");
+        opts.optopt("", "inline-threshold", "Largest function body, in ast nodes, to inline", "N");
+    }
+
+    if matches!(command, "run" | "repl") {
+        opts.optflag("", "memo-auto", "Memoize every pure recursive function, not only `@memo` ones");
+        opts.optopt("", "memo-capacity", "Most results cached per memoized function (default 100000)", "N");
+    }
+
+    match command {
+        "parse" => {
+            opts.optopt("", "emit", "Print the ast as json (default), sexpr, ast-dot or callgraph-dot", "KIND");
+        }
+
+        "build" => {
+            opts.optopt("o", "", "Write the output to FILE instead of standard output", "FILE");
+            opts.optopt("", "emit", "Write the ast as json (default), sexpr, ast-dot or callgraph-dot", "KIND");
+        }
+
+        "run" => {
+            opts.optmulti("", "link", "Look up externs in the shared library LIB too, may be repeated", "LIB");
+            opts.optflag("", "profile", "Print the calls and time of each function when the program ends");
+            opts.optopt("", "folded-stacks", "Profile, and write the time of each stack of calls to FILE for flamegraph tools", "FILE");
+            opts.optflag("", "trace", "Log each call and return to standard error, indented by depth");
+            opts.optflag("", "trace-binary", "Trace, logging binary operations as well");
+            opts.optopt("", "fuel", "Stop each top-level expression after evaluating N expressions", "N");
+            opts.optopt("", "max-depth", "Most calls in progress at once (default 10000)", "N");
+            opts.optopt("", "timeout", "Stop each top-level expression after MS milliseconds", "MS");
+            opts.optopt("", "allow-externs", "Only let the program call these externs, separated by commas", "NAMES");
+            opts.optmulti("", "trace-filter", "Trace only calls of FUNCTION and what its body does, may be repeated", "FUNCTION");
+            opts.optopt("", "trace-limit", "Trace, stopping after N events", "N");
+        }
+
+        "test" => {
+            opts.optmulti("", "filter", "Only run tests whose name contains TEXT, may be repeated", "TEXT");
+        }
+
+        "lint" => {
+            opts.optmulti("A", "allow", "Don't report RULE, or any rule for `all`", "RULE");
+            opts.optmulti("W", "warn", "Report RULE as a warning", "RULE");
+            opts.optmulti("D", "deny", "Report RULE as an error", "RULE");
+            opts.optopt("", "format", "Print findings as text (default) or json", "FORMAT");
+            opts.optflag("", "rules", "List the rules with their default severity and halt");
+        }
+
+        "doc" => {
+            opts.optopt("o", "", "Write the pages to DIR (default `doc`)", "DIR");
+            opts.optopt("", "title", "Heading of the pages (default `Documentation`)", "TEXT");
+        }
+
+        "fmt" => {
+            opts.optflag("", "check", "Report unformatted input and exit with an error instead of rewriting it");
+            opts.optopt("", "width", "Widest line to aim for (default 80)", "N");
+        }
+
+        _ => {}
+    }
+
+    opts.optflag("h", "help", "Print this help");
+    opts
 }
 
 fn main() {
+    let command = thread::Builder::new().stack_size(STACK_SIZE).spawn(run_command);
+
+    match command.map(|command| command.join()) {
+        Ok(Ok(())) => {}
+
+        // The panic has been reported already
+        Ok(Err(_)) => exit(101),
+        Err(e) => fail(EXIT_IO, &format!("unable to start: {}", e)),
+    }
+}
+
+fn run_command() {
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
 
-    let mut opts = Options::new();
-    opts.optflag("t", "tokens", "Print tokens output by lexer and halt");
-    opts.optflag("", "ast", "Print json representation of the ast and halt");
-    opts.optflag("h", "help", "Print this help");
+    let (command, args) = match args.get(1) {
+        Some(arg) if COMMANDS.iter().any(|&(name, _)| name == arg) => (arg.as_str(), &args[2..]),
+
+        // With nothing to run, an interactive session is the most useful thing to start
+        None if io::stdin().is_terminal() => ("repl", &args[1..]),
+        _ => ("run", &args[1..]),
+    };
 
-    let matches = match opts.parse(&args[1..]) {
+    let opts = options(command);
+
+    let matches = match opts.parse(args) {
         Ok(m) => m,
-        Err(e) => panic!(e.to_string()),
+        Err(e) => usage_error(&program, command, &opts, &e.to_string()),
     };
 
-    // Print help if requested
     if matches.opt_present("h") {
-        print_usage(&program, opts);
+        print_help(&program, command, &opts);
     }
 
-    // Get filename argument
-    let fname = if !matches.free.is_empty() {
-        matches.free[0].clone()
-    } else {
-        print_usage(&program, opts);
+    match command {
+        "tokens" => run_tokens(&matches),
+        "parse" => run_parse(&program, &opts, &matches),
+        "check" => run_check(&program, &opts, &matches),
+        "build" => run_build(&program, &opts, &matches),
+        "lint" => run_lint(&program, &opts, &matches),
+        "doc" => run_doc(&matches),
+        "test" => run_tests(&matches),
+        "debug" => run_debug(&program, &opts, &matches),
+        "fmt" => run_fmt(&program, &opts, &matches),
+        "repl" => run_repl(&program, &opts, &matches),
+        "lsp" => run_lsp(&program, &opts, &matches),
+        "dap" => run_dap(&program, &opts, &matches),
+        _ => run_program(&program, &opts, &matches),
+    }
+}
+
+fn run_tokens(matches: &Matches) -> ! {
+    for (name, source) in read_inputs_or_exit(matches) {
+        let tokens = match Lexer::new(source).tokenize() {
+            Ok(tokens) => tokens,
+            Err(e) => fail(EXIT_INVALID, &format!("{}: {}", name, e)),
+        };
+
+        for token in tokens {
+            // Only the end of the last input ends the program
+            if token != Token::Eof {
+                println!("{}", token);
+            }
+        }
+    }
+
+    println!("{}", Token::Eof);
+    exit(0);
+}
+
+fn run_parse(program: &str, opts: &Options, matches: &Matches) -> ! {
+    let ast = parse_or_exit(read_inputs_or_exit(matches));
+
+    print!("{}", emit_or_exit(program, "parse", opts, matches, &ast));
+    exit(0);
+}
+
+fn run_check(program: &str, opts: &Options, matches: &Matches) -> ! {
+    let mut ast = load_program_or_exit(program, "check", opts, matches);
+    let prelude = include_prelude(&mut ast, matches);
+    let errors = check::check(&ast);
+
+    for error in &errors {
+        eprintln!("error: {}", error);
+    }
+
+    for warning in check::warnings(&ast) {
+        eprintln!("warning: {}", warning);
+    }
+
+    // The prelude only declares externs, each summarized on a line of its own
+    for line in check::summarize(&ast).into_iter().skip(prelude) {
+        println!("{}", line);
+    }
+
+    exit(if errors.is_empty() { 0 } else { EXIT_INVALID });
+}
+
+fn run_build(program: &str, opts: &Options, matches: &Matches) -> ! {
+    let mut ast = load_program_or_exit(program, "build", opts, matches);
+
+    // The prelude tells the passes which functions are pure, and is declared
+    // again when the output is run, so it is left out of the output. The passes
+    // keep externs where they are.
+    let prelude = include_prelude(&mut ast, matches);
+    opt::optimize(&mut ast, &opt_options_or_exit(program, "build", opts, matches));
+    ast.0.drain(..prelude);
+
+    let output = emit_or_exit(program, "build", opts, matches, &ast);
+
+    match matches.opt_str("o") {
+        Some(fname) => {
+            if let Err(e) = fs::write(&fname, output) {
+                fail(EXIT_IO, &format!("unable to write `{}`: {}", fname, e));
+            }
+        }
+
+        None => print!("{}", output),
+    }
+
+    exit(0);
+}
+
+fn run_tests(matches: &Matches) -> ! {
+    let mut suite = match testing::Suite::load(read_inputs_or_exit(matches), !matches.opt_present("no-prelude"), &mut module_loader(matches)) {
+        Ok(suite) => suite,
+        Err(e) => fail(EXIT_INVALID, &e),
     };
+    suite.set_limits(default_limits());
+
+    match suite.report(&matches.opt_strs("filter"), &mut io::stdout()) {
+        Ok(true) => exit(0),
+        Ok(false) => exit(1),
+        Err(e) => fail(EXIT_IO, &format!("unable to write the results: {}", e)),
+    }
+}
+
+fn run_lint(program: &str, opts: &Options, matches: &Matches) -> ! {
+    if matches.opt_present("rules") {
+        for rule in lint::RULES {
+            println!("{:<20}{:<6}{}", rule.name, rule.default.name(), rule.description);
+        }
+
+        exit(0);
+    }
+
+    let json = match matches.opt_str("format").as_deref() {
+        None | Some("text") => false,
+        Some("json") => true,
+        Some(format) => usage_error(program, "lint", opts, &format!("unknown `--format` `{}`", format)),
+    };
+
+    // Later flags override earlier ones, as with `-A all -W unused-parameter`
+    let mut levels: Vec<(usize, String, Severity)> = Vec::new();
+    for &(flag, severity) in &[("A", Severity::Allow), ("W", Severity::Warn), ("D", Severity::Deny)] {
+        levels.extend(matches.opt_strs_pos(flag).into_iter().map(|(pos, rule)| (pos, rule, severity)));
+    }
+    levels.sort_by_key(|&(pos, _, _)| pos);
+
+    let mut config = lint::Config::new();
+    for (_, rule, severity) in levels {
+        if let Err(e) = config.set(&rule, severity) {
+            usage_error(program, "lint", opts, &e);
+        }
+    }
+
+    let mut findings: Vec<serde_json::Value> = Vec::new();
+    let mut status = 0;
+
+    for (name, source) in read_inputs_or_exit(matches) {
+        let diagnostics = match lint::lint_source(&source, &config) {
+            Ok(diagnostics) => diagnostics,
+            Err(e) => fail(EXIT_INVALID, &format!("{}: {}", name, e)),
+        };
+
+        for diagnostic in diagnostics {
+            if diagnostic.severity == Severity::Deny {
+                status = 1;
+            }
+
+            if json {
+                findings.push(json!({
+                    "file": name,
+                    "line": diagnostic.line,
+                    "column": diagnostic.column,
+                    "severity": diagnostic.severity,
+                    "rule": diagnostic.rule,
+                    "message": diagnostic.message,
+                }));
+            } else {
+                println!(
+                    "{}:{}:{}: {}[{}]: {}",
+                    name, diagnostic.line, diagnostic.column, diagnostic.severity.label(), diagnostic.rule, diagnostic.message
+                );
+            }
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&findings).unwrap());
+    }
+
+    exit(status);
+}
+
+fn run_program(program: &str, opts: &Options, matches: &Matches) -> ! {
+    let (mut ast, inputs) = load_program_and_inputs_or_exit(program, "run", opts, matches);
+    include_prelude(&mut ast, matches);
+    opt::optimize(&mut ast, &opt_options_or_exit(program, "run", opts, matches));
+
+    for warning in check::warnings(&ast) {
+        eprintln!("warning: {}", warning);
+    }
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_memo_capacity(memo_capacity_or_exit(program, "run", opts, matches));
 
-    let contents = read_file(fname);
-    let mut lexer = Lexer::new(contents);
+    for item in &ast.0 {
+        interpreter.define(item);
+    }
+
+    if matches.opt_present("memo-auto") {
+        interpreter.memoize_pure_recursive(&ast);
+    }
+
+    let mut linker = Linker::new();
+    for path in matches.opt_strs("link") {
+        if let Err(e) = linker.link(&path) {
+            fail(EXIT_IO, &e);
+        }
+    }
+
+    let unresolved = linker.link_externs(&mut interpreter, &ast);
+    for &(proto, ref e) in &unresolved {
+        match symbols::extern_location(&inputs, &proto.0) {
+            Some(location) => eprintln!("error: {}: {}", location, e),
+            None => eprintln!("error: {}", e),
+        }
+    }
+
+    if !unresolved.is_empty() {
+        exit(EXIT_INVALID);
+    }
+
+    interpreter.set_limits(limits_or_exit(program, opts, matches));
+
+    if matches.opt_present("profile") || matches.opt_present("folded-stacks") {
+        interpreter.enable_profiling();
+    }
+
+    if ["trace", "trace-binary", "trace-filter", "trace-limit"].iter().any(|&flag| matches.opt_present(flag)) {
+        let options = TraceOptions {
+            binary: matches.opt_present("trace-binary"),
+            functions: matches.opt_strs("trace-filter"),
+            limit: matches.opt_str("trace-limit").map(|limit| parse_number_arg(program, "run", opts, "--trace-limit", &limit)),
+        };
+
+        interpreter.set_trace(Trace::new(options, Box::new(io::stderr())));
+    }
+
+    let mut status = 0;
 
-    if matches.opt_present("t") {
-        print_tokens(&mut lexer);
-        return
+    for item in &ast.0 {
+        if let Item::Expr(ref expr) = **item {
+            match interpreter.eval_top_level(expr) {
+                Ok(value) => println!("{}", value),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+
+                    status = match e {
+                        EvalError::LimitExceeded(_) => EXIT_LIMIT,
+                        EvalError::Runtime(_) => EXIT_RUNTIME,
+                    };
+
+                    break
+                }
+            }
+        }
     }
 
-    let mut parser = Parser::new(lexer);
-    let ast = parser.parse();
+    eprint!("{}", interpreter.memo_report());
+
+    if let Some(profile) = interpreter.profile() {
+        if matches.opt_present("profile") {
+            eprint!("profile:\n{}", profile.report());
+        }
+
+        if let Some(path) = matches.opt_str("folded-stacks") {
+            if let Err(e) = fs::write(&path, profile.folded_stacks()) {
+                fail(EXIT_IO, &format!("unable to write `{}`: {}", path, e));
+            }
+        }
+    }
+
+    exit(status);
+}
+
+fn run_doc(matches: &Matches) -> ! {
+    let ast = parse_or_exit(read_inputs_or_exit(matches));
+    let dir = PathBuf::from(matches.opt_str("o").unwrap_or_else(|| "doc".to_string()));
+    let title = matches.opt_str("title").unwrap_or_else(|| "Documentation".to_string());
 
-    if matches.opt_present("ast") {
-        let out = json::encode(&ast).unwrap();
-        println!("{}", out);
-        return
+    match doc::write(&ast, &title, &dir) {
+        Ok(()) => exit(0),
+        Err(e) => fail(EXIT_IO, &e),
     }
 }
 
-fn print_tokens(lexer: &mut Lexer) {
-    loop {
-        let token = match lexer.next_token() {
-            Ok(tok) => tok,
-            Err(e) => panic!("Error ocurred in lexing: {}", e.to_string()),
+fn run_debug(program: &str, opts: &Options, matches: &Matches) -> ! {
+    if matches.free.is_empty() && !matches.opt_present("e") || matches.free.iter().any(|fname| fname == "-") {
+        usage_error(program, "debug", opts, "`debug` reads commands from standard input, so the program must be in files or `-e`");
+    }
+
+    let debugged = debug::Program::load(read_inputs_or_exit(matches), !matches.opt_present("no-prelude"), &mut module_loader(matches))
+        .map(|mut program| {
+            program.set_limits(default_limits());
+            debugger::run(program)
+        });
+
+    match debugged {
+        Ok(Ok(())) => exit(0),
+        Ok(Err(e)) => fail(EXIT_RUNTIME, &e),
+        Err(e) => fail(EXIT_INVALID, &e),
+    }
+}
+
+fn run_repl(program: &str, opts: &Options, matches: &Matches) -> ! {
+    let capacity = memo_capacity_or_exit(program, "repl", opts, matches);
+    let mut session = Session::new(matches.opt_present("memo-auto"), capacity, !matches.opt_present("no-prelude"), module_paths(matches));
+    session.set_limits(default_limits());
+
+    // Unlike the other commands, a session doesn't wait for standard input by default
+    if !matches.free.is_empty() || matches.opt_present("e") || matches.opt_present("from-ast") {
+        let ast = load_program_or_exit(program, "repl", opts, matches);
+
+        if let Err(e) = session.enter(*ast) {
+            eprintln!("error: {}", e);
+        }
+    }
+
+    match repl::run(&mut session) {
+        Ok(()) => exit(0),
+        Err(e) => fail(EXIT_IO, &e),
+    }
+}
+
+fn run_lsp(program: &str, opts: &Options, matches: &Matches) -> ! {
+    if !matches.free.is_empty() {
+        usage_error(program, "lsp", opts, "`lsp` takes no arguments");
+    }
+
+    match lsp::run() {
+        Ok(status) => exit(status),
+        Err(e) => fail(EXIT_IO, &e),
+    }
+}
+
+fn run_dap(program: &str, opts: &Options, matches: &Matches) -> ! {
+    if !matches.free.is_empty() {
+        usage_error(program, "dap", opts, "`dap` takes no arguments");
+    }
+
+    match dap::run(default_limits()) {
+        Ok(status) => exit(status),
+        Err(e) => fail(EXIT_IO, &e),
+    }
+}
+
+fn run_fmt(program: &str, opts: &Options, matches: &Matches) -> ! {
+    let width = match matches.opt_str("width").map(|width| width.parse()) {
+        Some(Ok(width)) => width,
+        Some(Err(_)) => usage_error(program, "fmt", opts, "invalid value for `--width`"),
+        None => format::DEFAULT_WIDTH,
+    };
+
+    let check = matches.opt_present("check");
+
+    let mut fnames = matches.free.clone();
+    if fnames.is_empty() {
+        fnames.push("-".to_string());
+    }
+
+    let mut status = 0;
+
+    for fname in &fnames {
+        let source = match read_input(fname) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                status = status.max(EXIT_IO);
+                continue
+            }
         };
 
+        let formatted = match format::format_source(&source, width) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("error: {}: {}", input_name(fname), e);
+                status = status.max(EXIT_INVALID);
+                continue
+            }
+        };
 
-        if token != Token::Whitespace && token != Token::Comment {
-            println!("{}", token);
+        if check {
+            if formatted != source {
+                eprintln!("{} is not formatted", input_name(fname));
+                status = status.max(1);
+            }
+        } else if fname == "-" {
+            print!("{}", formatted);
+        } else if formatted != source {
+            if let Err(e) = fs::write(fname, formatted) {
+                eprintln!("error: unable to write `{}`: {}", fname, e);
+                status = status.max(EXIT_IO);
+            }
         }
+    }
+
+    exit(status);
+}
+
+/// Every input named on the command line with its source: the files in
+/// order, `-` standing for standard input, then each `-e` snippet. Standard
+/// input is read when nothing else is given.
+fn read_inputs_or_exit(matches: &Matches) -> Vec<(String, String)> {
+    let mut fnames = matches.free.clone();
+    let snippets = matches.opt_strs("e");
+
+    if fnames.is_empty() && snippets.is_empty() {
+        fnames.push("-".to_string());
+    }
+
+    let mut inputs: Vec<(String, String)> = Vec::new();
+
+    for fname in fnames {
+        match read_input(&fname) {
+            Ok(source) => inputs.push((input_name(&fname), source)),
+            Err(e) => fail(EXIT_IO, &e),
+        }
+    }
+
+    for (i, snippet) in snippets.into_iter().enumerate() {
+        inputs.push((format!("-e #{}", i + 1), snippet));
+    }
+
+    inputs
+}
+
+fn read_input(fname: &str) -> Result<String, String> {
+    if fname == "-" {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source).map_err(|e| format!("unable to read standard input: {}", e))?;
+        return Ok(source)
+    }
+
+    fs::read_to_string(fname).map_err(|e| format!("unable to read `{}`: {}", fname, e))
+}
+
+fn input_name(fname: &str) -> String {
+    if fname == "-" { "standard input".to_string() } else { fname.to_string() }
+}
+
+fn parse_or_exit(inputs: Vec<(String, String)>) -> Box<ast::File> {
+    match kaleidescope_rs::parse_inputs(inputs) {
+        Ok(ast) => Box::new(ast),
+        Err(e) => fail(EXIT_INVALID, &e),
+    }
+}
+
+/// The program given by `--from-ast`, or else parsed from the inputs, linked
+/// with the modules it imports.
+fn load_program_or_exit(program: &str, command: &str, opts: &Options, matches: &Matches) -> Box<ast::File> {
+    load_program_and_inputs_or_exit(program, command, opts, matches).0
+}
+
+/// The program as `load_program_or_exit` loads it, with the inputs it was
+/// parsed from, of which there are none for `--from-ast`.
+fn load_program_and_inputs_or_exit(
+    program: &str, command: &str, opts: &Options, matches: &Matches
+) -> (Box<ast::File>, Vec<(String, String)>) {
+    let mut loader = module_loader(matches);
+
+    let fname = match matches.opt_str("from-ast") {
+        Some(fname) => fname,
+        None => {
+            let inputs = read_inputs_or_exit(matches);
 
-        if token == Token::Eof {
-            break;
+            return match loader.load(inputs.clone()) {
+                Ok(ast) => (Box::new(ast), inputs),
+                Err(e) => fail(EXIT_INVALID, &e),
+            }
         }
+    };
+
+    if !matches.free.is_empty() || matches.opt_present("e") {
+        usage_error(program, command, opts, "`--from-ast` can't be combined with other inputs");
+    }
+
+    let json = match read_input(&fname) {
+        Ok(json) => json,
+        Err(e) => fail(EXIT_IO, &e),
+    };
+
+    match loader.load_json(input_name(&fname), &json) {
+        Ok(ast) => (Box::new(ast), Vec::new()),
+        Err(e) => fail(EXIT_INVALID, &e),
     }
 }
 
-fn read_file(fname: String) -> String {
-    let mut file = match File::open(&fname) {
-        Ok(f) => f,
-        Err(e) => panic!("Unable to open file `{}`: {}", fname, e.to_string()),
+/// `ast` as the kind of output `--emit` asks for, json by default.
+fn emit_or_exit(program: &str, command: &str, opts: &Options, matches: &Matches, ast: &ast::File) -> String {
+    let kind = matches.opt_str("emit").unwrap_or_else(|| "json".to_string());
+
+    match kaleidescope_rs::emit(ast, &kind) {
+        Ok(output) => output,
+        Err(_) => usage_error(program, command, opts, &format!("unknown `--emit` kind `{}`", kind)),
+    }
+}
+
+/// The directories given by `-I`.
+fn module_paths(matches: &Matches) -> Vec<PathBuf> {
+    matches.opt_strs("module-path").into_iter().map(PathBuf::from).collect()
+}
+
+fn module_loader(matches: &Matches) -> Loader {
+    Loader::new(module_paths(matches))
+}
+
+/// Put the prelude before the items of `ast` unless `--no-prelude` is given,
+/// returning how many items that added.
+fn include_prelude(ast: &mut ast::File, matches: &Matches) -> usize {
+    if matches.opt_present("no-prelude") { 0 } else { prelude::include(ast) }
+}
+
+fn opt_options_or_exit(program: &str, command: &str, opts: &Options, matches: &Matches) -> OptOptions {
+    let level = match matches.opt_str("O") {
+        Some(level) => parse_number_arg(program, command, opts, "-O", &level),
+        None => 0,
     };
 
-    let mut buf = String::new();
+    let mut opt_opts = OptOptions::for_level(level);
+
+    if let Some(threshold) = matches.opt_str("inline-threshold") {
+        opt_opts.inline.threshold = parse_number_arg(program, command, opts, "--inline-threshold", &threshold);
+    }
+
+    opt_opts
+}
+
+fn memo_capacity_or_exit(program: &str, command: &str, opts: &Options, matches: &Matches) -> usize {
+    match matches.opt_str("memo-capacity") {
+        Some(capacity) => parse_number_arg(program, command, opts, "--memo-capacity", &capacity),
+        None => interp::DEFAULT_MEMO_CAPACITY,
+    }
+}
+
+/// The limits programs run with unless flags say otherwise.
+fn default_limits() -> Limits {
+    Limits { max_depth: Some(MAX_DEPTH), ..Limits::default() }
+}
+
+fn limits_or_exit(program: &str, opts: &Options, matches: &Matches) -> Limits {
+    let number = |flag: &str| matches.opt_str(flag).map(|value| parse_number_arg(program, "run", opts, &format!("--{}", flag), &value));
+
+    Limits {
+        fuel: number("fuel").map(|fuel| fuel as u64),
+        max_depth: number("max-depth").or(Some(MAX_DEPTH)),
+        timeout: number("timeout").map(|ms| Duration::from_millis(ms as u64)),
+        externs: matches.opt_str("allow-externs").map(|names| {
+            names.split(',').map(str::trim).filter(|name| !name.is_empty()).map(str::to_string).collect()
+        }),
+    }
+}
 
-    match file.read_to_string(&mut buf) {
-        Ok(_) => buf,
-        Err(e) => panic!("Unable to read from file `{}`: {}", fname, e.to_string()),
+fn parse_number_arg(program: &str, command: &str, opts: &Options, flag: &str, value: &str) -> usize {
+    match value.parse() {
+        Ok(n) => n,
+        Err(_) => usage_error(program, command, opts, &format!("invalid value for `{}`: `{}`", flag, value)),
     }
 }
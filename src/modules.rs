@@ -0,0 +1,444 @@
+//! Modules: other `.k` files whose functions a program imports.
+//!
+//! `import "lib/util.k"` brings the functions of a file into scope as they
+//! are, while `import "lib/util.k" as u` and `import util.math` only make
+//! them callable qualified, as `u.f(x)` or `util.math.f(x)`. A dotted name is
+//! looked up as a path, `util/math.k`, next to the importing file and then in
+//! each directory of the search path.
+//!
+//! Every module has a namespace of its own, so two modules may define
+//! functions of the same name. Linking renames the functions of each module
+//! to `prefix.name`, with a prefix unique to the module, and rewrites calls to
+//! name them that way, which leaves a program that needs no imports to run.
+//! Externs are shared by every module, and an imported module only
+//! contributes its functions and externs, never its top-level expressions or
+//! tests.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::iter;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use ast::*;
+use lexer::Lexer;
+use parse;
+use parser::Parser;
+use schema;
+
+/// A module that has been loaded, by its canonical path.
+struct Module {
+    // Prepended to the name of each of its functions
+    prefix: String,
+
+    // The names its functions are called by inside the module
+    functions: HashSet<String>,
+}
+
+/// The names a file or the root of the program can call.
+#[derive(Default)]
+struct Scope {
+    // Functions defined by the file itself
+    defs: HashSet<String>,
+
+    // Modules imported with a name, which qualifies calls to them
+    aliases: HashMap<String, PathBuf>,
+
+    // Modules imported without one, in the order they were imported
+    globs: Vec<PathBuf>,
+}
+
+/// An input of a program with its source and the byte range of each item,
+/// for reporting where in it something is.
+pub struct SpannedInput {
+    pub name: String,
+    pub source: String,
+    pub items: Vec<(Box<Item>, usize, usize)>,
+}
+
+/// Loads imported modules once each, and links the items of a program to
+/// the functions they call.
+///
+/// ```
+/// use kaleidescope_rs::modules::Loader;
+///
+/// let dir = std::env::temp_dir().join("kaleidescope-loader-doctest");
+/// std::fs::create_dir_all(dir.join("geometry")).unwrap();
+/// std::fs::write(dir.join("geometry/area.k"), "def square(x) x * x  def circle(r) 3 * square(r)").unwrap();
+///
+/// let mut loader = Loader::new(vec![dir]);
+/// let inputs = vec![("main.k".to_string(), "import geometry.area as a  def square(x) x  a.circle(2) + square(2)".to_string())];
+/// let file = loader.load(inputs).unwrap();
+///
+/// assert_eq!(kaleidescope_rs::run(&file), Ok(vec![kaleidescope_rs::Value::from(14.0)]));
+/// ```
+pub struct Loader {
+    search_paths: Vec<PathBuf>,
+    modules: HashMap<PathBuf, Module>,
+    prefixes: HashSet<String>,
+
+    // Files being linked, innermost last, to find imports that lead back to one
+    loading: Vec<PathBuf>,
+
+    // What the program's own inputs can call, kept between links
+    root: Scope,
+
+    // Items of the modules loaded by the current link, dependencies first
+    items: Vec<Box<Item>>,
+}
+
+impl Loader {
+    /// A loader looking for modules imported by name next to the importing
+    /// file, then in each of `search_paths` in order.
+    pub fn new(search_paths: Vec<PathBuf>) -> Loader {
+        Loader {
+            search_paths,
+            modules: HashMap::new(),
+            prefixes: HashSet::new(),
+            loading: Vec::new(),
+            root: Scope::default(),
+            items: Vec::new(),
+        }
+    }
+
+    /// Parse each of the named `inputs` and link them as one program, which
+    /// is the functions and externs of every module it imports, followed by
+    /// its own items without the imports. Errors start with the name of the
+    /// input or module they are in.
+    pub fn load(&mut self, inputs: Vec<(String, String)>) -> Result<File, String> {
+        let mut files: Vec<(String, File)> = Vec::new();
+
+        for (name, source) in inputs {
+            let file = parse(&source).map_err(|e| format!("{}: {}", name, e))?;
+            files.push((name, file));
+        }
+
+        self.link_files(files)
+    }
+
+    /// Read the program named `name` from `json`, as written by
+    /// `schema::to_json`, and link it as `load` does.
+    ///
+    /// ```
+    /// use kaleidescope_rs::modules::Loader;
+    /// use kaleidescope_rs::schema;
+    ///
+    /// let json = schema::to_json(&kaleidescope_rs::parse("def f(x) x + 1  f(1)").unwrap());
+    /// let file = Loader::new(Vec::new()).load_json("f.json".to_string(), &json).unwrap();
+    /// assert_eq!(kaleidescope_rs::run(&file), Ok(vec![kaleidescope_rs::Value::from(2.0)]));
+    ///
+    /// assert!(Loader::new(Vec::new()).load_json("f.json".to_string(), "{").unwrap_err().starts_with("f.json: "));
+    /// ```
+    pub fn load_json(&mut self, name: String, json: &str) -> Result<File, String> {
+        let file = schema::from_json(json).map_err(|e| format!("{}: {}", name, e))?;
+        self.link_files(vec![(name, file)])
+    }
+
+    /// Link the named `files` as `load` does.
+    fn link_files(&mut self, mut files: Vec<(String, File)>) -> Result<File, String> {
+        let mut items = self.link(files.iter_mut().map(|&mut (ref name, ref mut file)| {
+            (name.as_str(), file.0.iter_mut().map(|item| &mut **item).collect())
+        }).collect())?;
+
+        for (_, file) in files {
+            items.extend(file.0.into_iter().filter(|item| !matches!(**item, Item::Import(..))));
+        }
+
+        Ok(File(items))
+    }
+
+    /// Parse each of the named `inputs` with the byte range of every item,
+    /// failing with the name of the first one that has a syntax error, and
+    /// link them as `link` does. The inputs keep their imports, and are
+    /// returned with the items of the modules they import.
+    pub fn load_spanned(&mut self, inputs: Vec<(String, String)>) -> Result<(Vec<SpannedInput>, Vec<Box<Item>>), String> {
+        let mut spanned: Vec<SpannedInput> = Vec::new();
+
+        for (name, source) in inputs {
+            let mut items: Vec<(Box<Item>, usize, usize)> = Vec::new();
+
+            let parsed = Parser::new(Lexer::new(source.clone())).and_then(|mut parser| {
+                while let Some(item) = parser.parse_spanned_item()? {
+                    items.push(item);
+                }
+
+                Ok(())
+            });
+
+            if let Err(e) = parsed {
+                return Err(format!("{}: {}", name, e))
+            }
+
+            spanned.push(SpannedInput { name, source, items });
+        }
+
+        let modules = self.link(spanned.iter_mut().map(|input| {
+            (input.name.as_str(), input.items.iter_mut().map(|&mut (ref mut item, _, _)| &mut **item).collect())
+        }).collect())?;
+
+        Ok((spanned, modules))
+    }
+
+    /// Load the modules that the items of the named inputs import, and
+    /// rewrite the calls of the items to the functions they name. The imports
+    /// are left in place, and the items of the modules loaded for the first
+    /// time are returned, to be defined before the inputs' own.
+    ///
+    /// The imports and functions of earlier links stay in scope, and linking
+    /// items again leaves them as they are.
+    pub fn link(&mut self, inputs: Vec<(&str, Vec<&mut Item>)>) -> Result<Vec<Box<Item>>, String> {
+        let mut root = mem::take(&mut self.root);
+        let linked = self.link_root(&mut root, inputs);
+        self.root = root;
+
+        let items = mem::take(&mut self.items);
+        linked.map(|()| items)
+    }
+
+    fn link_root(&mut self, root: &mut Scope, mut inputs: Vec<(&str, Vec<&mut Item>)>) -> Result<(), String> {
+        for &mut (name, ref items) in inputs.iter_mut() {
+            for item in items {
+                if let Item::Function(_, _, ref proto, _) = **item {
+                    root.defs.insert(proto.0.clone());
+                }
+            }
+
+            // Relative imports of a file are found next to it
+            let path = Path::new(name);
+            let dir = match path.parent() {
+                Some(parent) if path.is_file() => parent.to_path_buf(),
+                _ => PathBuf::new(),
+            };
+
+            let canonical = path.canonicalize().ok().filter(|_| path.is_file());
+            if let Some(ref canonical) = canonical {
+                self.loading.push(canonical.clone());
+            }
+
+            let imported = self.import_all(root, name, &dir, items);
+
+            if canonical.is_some() {
+                self.loading.pop();
+            }
+
+            imported?;
+        }
+
+        for (name, items) in inputs {
+            for item in items {
+                self.rewrite_item(root, "", item).map_err(|e| format!("{}: {}", name, e))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Bring the modules imported by `items`, of the file `name` in `dir`,
+    /// into `scope`.
+    fn import_all(&mut self, scope: &mut Scope, name: &str, dir: &Path, items: &[&mut Item]) -> Result<(), String> {
+        for item in items {
+            if let Item::Import(ref module, ref alias) = **item {
+                let path = self.find(module, dir).map_err(|e| format!("{}: {}", name, e))?;
+                let canonical = path.canonicalize().map_err(|e| format!("{}: unable to read `{}`: {}", name, path.display(), e))?;
+
+                if let Some(start) = self.loading.iter().position(|loading| *loading == canonical) {
+                    let mut cycle: Vec<String> = self.loading[start..].iter().map(|path| file_name(path)).collect();
+                    cycle.push(file_name(&canonical));
+                    return Err(format!("{}: import cycle: {}", name, cycle.join(" -> ")))
+                }
+
+                if !self.modules.contains_key(&canonical) {
+                    self.load_module(module, &path, &canonical)?;
+                }
+
+                let path = canonical;
+
+                let alias = match (module, alias.as_ref()) {
+                    (_, Some(alias)) => alias.clone(),
+                    (ModulePath::Name(module_name), None) => module_name.clone(),
+
+                    (ModulePath::File(_), None) => {
+                        if !scope.globs.contains(&path) {
+                            scope.globs.push(path);
+                        }
+
+                        continue
+                    }
+                };
+
+                match scope.aliases.get(&alias) {
+                    Some(other) if *other != path => return Err(format!("{}: `{}` names two modules", name, alias)),
+                    _ => scope.aliases.insert(alias, path),
+                };
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The file `module` is in, for an import in a file in `dir`.
+    fn find(&self, module: &ModulePath, dir: &Path) -> Result<PathBuf, String> {
+        match *module {
+            ModulePath::File(ref path) => Ok(dir.join(path)),
+
+            ModulePath::Name(ref name) => {
+                let relative: PathBuf = format!("{}.k", name.replace('.', "/")).into();
+
+                iter::once(dir).chain(self.search_paths.iter().map(|path| path.as_path()))
+                    .map(|dir| dir.join(&relative))
+                    .find(|path| path.is_file())
+                    .ok_or_else(|| format!("module `{}` is not in any directory of the search path", name))
+            }
+        }
+    }
+
+    /// Load, link and keep the items of the module at `path`, which is
+    /// `canonical` once resolved.
+    fn load_module(&mut self, module: &ModulePath, path: &Path, canonical: &Path) -> Result<(), String> {
+        let name = path.display().to_string();
+        let source = fs::read_to_string(path).map_err(|e| format!("unable to read `{}`: {}", name, e))?;
+        let mut file = parse(&source).map_err(|e| format!("{}: {}", name, e))?;
+
+        let mut scope = Scope::default();
+
+        for item in &file.0 {
+            if let Item::Function(_, _, ref proto, _) = **item {
+                scope.defs.insert(proto.0.clone());
+            }
+        }
+
+        self.loading.push(canonical.to_path_buf());
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let imported = self.import_all(&mut scope, &name, dir, &file.0.iter_mut().map(|item| &mut **item).collect::<Vec<&mut Item>>());
+        self.loading.pop();
+        imported?;
+
+        let prefix = self.prefix(module);
+
+        for item in file.0.iter_mut() {
+            self.rewrite_item(&scope, &prefix, item).map_err(|e| format!("{}: {}", name, e))?;
+
+            if let Item::Function(_, _, ref mut proto, _) = **item {
+                proto.0 = format!("{}.{}", prefix, proto.0);
+            }
+        }
+
+        self.modules.insert(canonical.to_path_buf(), Module { prefix, functions: scope.defs });
+        self.items.extend(file.0.into_iter().filter(|item| matches!(**item, Item::Function(..) | Item::Extern(..))));
+        Ok(())
+    }
+
+    /// A prefix for the functions of `module` that no other module has, and
+    /// that reads back as part of a name.
+    fn prefix(&mut self, module: &ModulePath) -> String {
+        let base = match *module {
+            ModulePath::Name(ref name) => name.clone(),
+            ModulePath::File(ref path) => {
+                let stem = Path::new(path).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+                let mut base: String = stem.chars().map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' }).collect();
+
+                if !base.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+                    base.insert(0, '_');
+                }
+
+                base
+            }
+        };
+
+        let mut prefix = base.clone();
+        let mut n = 1;
+
+        while self.prefixes.contains(&prefix) {
+            n += 1;
+            prefix = format!("{}_{}", base, n);
+        }
+
+        self.prefixes.insert(prefix.clone());
+        prefix
+    }
+
+    fn rewrite_item(&self, scope: &Scope, prefix: &str, item: &mut Item) -> Result<(), String> {
+        match *item {
+            Item::Function(_, _, _, ref mut body) | Item::Expr(ref mut body) | Item::Test(_, ref mut body) => {
+                self.rewrite(scope, prefix, body)
+            }
+
+            Item::Extern(..) | Item::Import(..) => Ok(()),
+        }
+    }
+
+    fn rewrite(&self, scope: &Scope, prefix: &str, expr: &mut Expr) -> Result<(), String> {
+        match *expr {
+            Expr::Number(_) | Expr::Str(_) | Expr::Name(_) => Ok(()),
+
+            Expr::Binary(_, ref mut lhs, ref mut rhs) | Expr::Let(_, ref mut lhs, ref mut rhs) => {
+                self.rewrite(scope, prefix, lhs)?;
+                self.rewrite(scope, prefix, rhs)
+            }
+
+            Expr::Unary(_, ref mut operand) | Expr::Paren(ref mut operand) => self.rewrite(scope, prefix, operand),
+
+            Expr::If(ref mut cond, ref mut then, ref mut otherwise) => {
+                self.rewrite(scope, prefix, cond)?;
+                self.rewrite(scope, prefix, then)?;
+                self.rewrite(scope, prefix, otherwise)
+            }
+
+            Expr::Call(ref mut name, ref mut args) => {
+                if let Some(resolved) = self.resolve(scope, prefix, name)? {
+                    *name = resolved;
+                }
+
+                for arg in args.iter_mut() {
+                    self.rewrite(scope, prefix, arg)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    /// What a call to `name` in `scope` calls, if not a function of that
+    /// name outside of every module: the file's own function, one of a module
+    /// it qualifies, or else one of the modules imported without a name.
+    fn resolve(&self, scope: &Scope, prefix: &str, name: &str) -> Result<Option<String>, String> {
+        if scope.defs.contains(name) {
+            return Ok(if prefix.is_empty() { None } else { Some(format!("{}.{}", prefix, name)) })
+        }
+
+        if let Some(dot) = name.rfind('.') {
+            let (alias, function) = (&name[..dot], &name[dot + 1..]);
+
+            let module = match scope.aliases.get(alias) {
+                Some(path) => &self.modules[path],
+                None => return Err(format!("no module is imported as `{}`", alias)),
+            };
+
+            if !module.functions.contains(function) {
+                return Err(format!("module `{}` has no function `{}`", alias, function))
+            }
+
+            return Ok(Some(format!("{}.{}", module.prefix, function)))
+        }
+
+        let mut found: Option<&Module> = None;
+
+        for path in &scope.globs {
+            let module = &self.modules[path];
+
+            if module.functions.contains(name) {
+                if let Some(other) = found {
+                    return Err(format!("`{}` is defined by both modules `{}` and `{}`", name, other.prefix, module.prefix))
+                }
+
+                found = Some(module);
+            }
+        }
+
+        Ok(found.map(|module| format!("{}.{}", module.prefix, name)))
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned())
+}
@@ -0,0 +1,28 @@
+use ast::File;
+use callgraph;
+use cse;
+use inline::{self, InlineOptions};
+
+/// Which optimizations run over a file, and how aggressively.
+pub struct OptOptions {
+    pub level: usize,
+    pub inline: InlineOptions,
+}
+
+impl OptOptions {
+    pub fn for_level(level: usize) -> OptOptions {
+        OptOptions { level, inline: InlineOptions::for_level(level) }
+    }
+}
+
+/// Run every enabled pass over `file`, in order.
+pub fn optimize(file: &mut File, opts: &OptOptions) {
+    inline::inline(file, &opts.inline);
+
+    if opts.level >= 1 {
+        cse::eliminate_common_subexpressions(file);
+
+        // Inlining can leave helpers without callers, so prune afterwards
+        callgraph::eliminate_dead_functions(file);
+    }
+}
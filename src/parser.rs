@@ -5,107 +5,162 @@ use tokens::{Token, Delim, Operator};
 
 pub struct Parser {
     token: Token,
-    lexer: Lexer
+    lexer: Lexer,
+
+    // Byte offsets where `token` starts and where the token before it ended
+    token_start: usize,
+    prev_end: usize,
+
+    // Lines of the doc comment directly before `token`
+    doc: Option<String>,
 }
 
 impl Parser {
-    pub fn new(lexer: Lexer) -> Parser {
-        let mut p = Parser { lexer: lexer, token: Token::Eof };
-        p.next_token();
-        p
+    pub fn new(lexer: Lexer) -> Result<Parser, String> {
+        let mut p = Parser { lexer, token: Token::Eof, token_start: 0, prev_end: 0, doc: None };
+        p.next_token()?;
+        Ok(p)
     }
 
-    pub fn parse(&mut self) -> Box<File> {
+    /// Parse the rest of the input as a program.
+    ///
+    /// ```
+    /// use kaleidescope_rs::lexer::Lexer;
+    /// use kaleidescope_rs::parser::Parser;
+    ///
+    /// let mut parser = Parser::new(Lexer::new("def one() 1  one()".to_string())).unwrap();
+    /// assert_eq!(parser.parse().unwrap().0.len(), 2);
+    /// ```
+    pub fn parse(&mut self) -> Result<Box<File>, String> {
         self.parse_file()
     }
 
-    fn next_token(&mut self) {
+    /// Whether the input ran out, so a failed parse may succeed given more of it.
+    pub fn at_eof(&self) -> bool {
+        self.token == Token::Eof
+    }
+
+    /// Byte offset of the current token, or of the text that failed to lex.
+    pub fn offset(&self) -> usize {
+        self.token_start
+    }
+
+    fn next_token(&mut self) -> Result<(), String> {
+        self.prev_end = self.lexer.offset();
+        let mut doc: Vec<String> = Vec::new();
+
         loop {
-            let token = self.lexer.next_token();
-            match token {
-                Ok(Token::Whitespace) | Ok(Token::Comment) => continue,
+            self.token_start = self.lexer.offset();
 
-                Ok(tok) => {
-                    self.token = tok;
-                    break
+            match self.lexer.next_token()? {
+                Token::Whitespace | Token::Comment(_) => continue,
+
+                // Written as `## text`, with the space left out of the text
+                Token::DocComment(text) => {
+                    doc.push(text.strip_prefix(' ').unwrap_or(&text).trim_end().to_string());
                 }
 
-                Err(e) => panic!(e)
+                tok => {
+                    self.token = tok;
+                    self.doc = if doc.is_empty() { None } else { Some(doc.join("\n")) };
+                    return Ok(())
+                }
             }
         }
     }
 
-    fn expect(&mut self, t: Token) -> Option<String> {
+    fn expect(&mut self, t: Token) -> Result<(), String> {
         if self.token == t {
-            self.next_token();
-            None
+            self.next_token()
         } else {
-            panic!("expected {}, not {}", t, self.token);
+            Err(format!("expected {}, not {}", t, self.token))
         }
     }
 
     /// NUM_EXPR := NUM
-    fn parse_number_expr(&mut self) -> Box<Expr> {
+    fn parse_number_expr(&mut self) -> Result<Box<Expr>, String> {
         let value = match self.token {
             Token::Number(val) => val,
             _ => unreachable!()
         };
 
-        self.next_token();
-        Box::new(Expr::Number(value))
+        self.next_token()?;
+        Ok(Box::new(Expr::Number(value)))
+    }
+
+    /// STR_EXPR ::= STRING
+    fn parse_str_expr(&mut self) -> Result<Box<Expr>, String> {
+        let text = match self.token {
+            Token::Str(ref text) => text.clone(),
+            _ => unreachable!()
+        };
+
+        self.next_token()?;
+        Ok(Box::new(Expr::Str(text)))
     }
 
     /// PAREN_EXPR ::= '(' EXPR ')'
-    fn parse_paren_expr(&mut self) -> Box<Expr> {
-        self.next_token();
-        let expr = self.parse_expr();
-        self.expect(Token::CloseDelim(Delim::Paren));
-        Box::new(Expr::Paren(expr))
+    fn parse_paren_expr(&mut self) -> Result<Box<Expr>, String> {
+        self.next_token()?;
+        let expr = self.parse_expr()?;
+        self.expect(Token::CloseDelim(Delim::Paren))?;
+        Ok(Box::new(Expr::Paren(expr)))
     }
  
-    /// IDENT_EXPR := IDENT | IDENT '(' EXPR [ ','  EXPR ] * ')'
-    fn parse_ident_expr(&mut self) -> Box<Expr> {
+    /// IDENT_EXPR := IDENT | IDENT '(' [ EXPR [ ','  EXPR ] * ] ? ')'
+    fn parse_ident_expr(&mut self) -> Result<Box<Expr>, String> {
         let ident_name = match self.token {
             Token::Ident(ref name) => name.clone(),
             _ => unreachable!()
         };
 
-        self.next_token();
+        self.next_token()?;
 
         if self.token != Token::OpenDelim(Delim::Paren) {
-            return Box::new(Expr::Name(ident_name))
+            return Ok(Box::new(Expr::Name(ident_name)))
         }
 
-        self.next_token();
+        self.next_token()?;
 
         let mut args: Vec<Box<Expr>> = Vec::new();
 
-        loop {
-            let arg = self.parse_expr();
+        while self.token != Token::CloseDelim(Delim::Paren) {
+            let arg = self.parse_expr()?;
             args.push(arg);
 
             if self.token == Token::CloseDelim(Delim::Paren) {
                 break
             }
 
-            self.expect(Token::Comma);
+            self.expect(Token::Comma)?;
         }
 
-        self.next_token();
+        self.next_token()?;
+
+        Ok(Box::new(Expr::Call(ident_name, args)))
+    }
 
-        Box::new(Expr::Call(ident_name, args))
+    /// IF_EXPR ::= 'if' EXPR 'then' EXPR 'else' EXPR
+    fn parse_if_expr(&mut self) -> Result<Box<Expr>, String> {
+        self.next_token()?;
+        let cond = self.parse_expr()?;
+        self.expect(Token::Then)?;
+        let then = self.parse_expr()?;
+        self.expect(Token::Else)?;
+        let otherwise = self.parse_expr()?;
+        Ok(Box::new(Expr::If(cond, then, otherwise)))
     }
 
-    /// PRIMARY_EXPR ::= [ '-' | '!' ] ? [ IDENT_EXPR | NUMBER_EXPR | PAREN_EXPR ]
-    fn parse_primary(&mut self) -> Box<Expr> {
+    /// PRIMARY_EXPR ::= [ '-' | '!' ] ? [ IDENT_EXPR | NUMBER_EXPR | STR_EXPR | PAREN_EXPR | IF_EXPR ]
+    fn parse_primary(&mut self) -> Result<Box<Expr>, String> {
         let unary_op = match self.token {
             Token::BinOp(Operator::Minus) => {
-                self.next_token();
+                self.next_token()?;
                 Some(UnOp::Neg)
             }
 
             Token::Bang => {
-                self.next_token();
+                self.next_token()?;
                 Some(UnOp::Not)
             }
 
@@ -114,44 +169,47 @@ impl Parser {
 
 
         let expr = match self.token {
-            Token::Ident(_) => self.parse_ident_expr(),
-            Token::Number(_) => self.parse_number_expr(),
-            Token::OpenDelim(Delim::Paren) => self.parse_paren_expr(),
-            ref t => panic!("Unexpected token, `{}`", t)
+            Token::Ident(_) => self.parse_ident_expr()?,
+            Token::Number(_) => self.parse_number_expr()?,
+            Token::Str(_) => self.parse_str_expr()?,
+            Token::OpenDelim(Delim::Paren) => self.parse_paren_expr()?,
+            Token::If => self.parse_if_expr()?,
+            ref t => return Err(format!("Unexpected token, `{}`", t))
         };
 
         if let Some(op) = unary_op {
-            Box::new(Expr::Unary(op, expr))
+            Ok(Box::new(Expr::Unary(op, expr)))
         } else {
-            expr
+            Ok(expr)
         }
     }
 
     /// BINOP_RHS ::= [ BINOP PRIMARY ]*
     /// BINOP ::= '+' | '-' | '*' | '/' | '%' | '&&' | '||'
     ///         | '==' | '!=' | '>' | '>=' | '<' | '<='
-    fn parse_binop_rhs(&mut self, min_precedence: usize, mut lhs: Box<Expr>) -> Box<Expr> {
+    fn parse_binop_rhs(&mut self, min_precedence: usize, mut lhs: Box<Expr>) -> Result<Box<Expr>, String> {
         loop {
             let op = match Op::from_token(&self.token) {
                 Some(op) => op,
-                None => return lhs
+                None => return Ok(lhs)
             };
 
             if op.precedence() < min_precedence {
-                return lhs
+                return Ok(lhs)
             }
 
-            self.next_token();
+            self.next_token()?;
 
-            let mut rhs = self.parse_primary();
+            let mut rhs = self.parse_primary()?;
 
             let next_precedence = match Op::from_token(&self.token) {
                 Some(op) => op.precedence(),
                 None => 0
             };
 
-            if next_precedence > min_precedence {
-                rhs = self.parse_binop_rhs(min_precedence + 1, rhs);
+            // Let tighter-binding operators take `rhs` first; equal ones group left
+            if next_precedence > op.precedence() {
+                rhs = self.parse_binop_rhs(op.precedence() + 1, rhs)?;
             }
 
             lhs = Box::new(Expr::Binary(op.to_ast_binop(), lhs, rhs))
@@ -159,79 +217,170 @@ impl Parser {
     }
 
     /// EXPR ::= PRIMARY BINOP_RHS ?
-    fn parse_expr(&mut self) -> Box<Expr> {
-        let lhs = self.parse_primary();
+    fn parse_expr(&mut self) -> Result<Box<Expr>, String> {
+        let lhs = self.parse_primary()?;
         self.parse_binop_rhs(0, lhs)
     }
 
-    /// PROTOTYPE ::= IDENT '(' IDENT [ ',' IDENT ] * ')'
-    fn parse_proto(&mut self) -> Box<FuncProto> {
+    /// PROTOTYPE ::= IDENT '(' [ IDENT [ ',' IDENT ] * ] ? ')'
+    fn parse_proto(&mut self) -> Result<Box<FuncProto>, String> {
         let name = match self.token {
             Token::Ident(ref name) => name.clone(),
-            _ => unreachable!()
+            ref t => return Err(format!("Expected function name, not {}", t))
         };
 
-        self.next_token();
-        self.expect(Token::OpenDelim(Delim::Paren));
+        self.next_token()?;
+        self.expect(Token::OpenDelim(Delim::Paren))?;
 
         let mut args: Vec<String> = Vec::new();
 
-        loop {
+        while self.token != Token::CloseDelim(Delim::Paren) {
             let arg_name = match self.token {
+                Token::Ident(ref name) if name.contains('.') => {
+                    return Err(format!("Expected parameter name, not qualified name `{}`", name))
+                }
+
                 Token::Ident(ref name) => name.clone(),
-                ref t => panic!("Expected identifier, not {}", t)
+                ref t => return Err(format!("Expected identifier, not {}", t))
             };
 
-            self.next_token();
+            self.next_token()?;
             args.push(arg_name);
 
             if let Token::CloseDelim(Delim::Paren) = self.token {
                 break;
             }
 
-            self.expect(Token::Comma);
+            self.expect(Token::Comma)?;
+        }
+
+        self.next_token()?;
+        Ok(Box::new(FuncProto(name, args)))
+    }
+
+    /// ATTRS ::= [ '@' IDENT ]*
+    fn parse_attrs(&mut self) -> Result<Vec<Attr>, String> {
+        let mut attrs: Vec<Attr> = Vec::new();
+
+        while self.token == Token::At {
+            self.next_token()?;
+
+            let attr = match self.token {
+                Token::Ident(ref name) if name == "noinline" => Attr::NoInline,
+                Token::Ident(ref name) if name == "export" => Attr::Export,
+                Token::Ident(ref name) if name == "memo" => Attr::Memo,
+                Token::Ident(ref name) => return Err(format!("Unknown attribute `@{}`", name)),
+                ref t => return Err(format!("Expected attribute name, not {}", t))
+            };
+
+            self.next_token()?;
+            attrs.push(attr);
         }
 
-        self.next_token();
-        Box::new(FuncProto(name, args))
+        Ok(attrs)
     }
 
-    /// FUNC_DEF ::= 'def' PROTOTYPE EXPR
-    fn parse_def(&mut self) -> Box<Item> {
-        self.next_token();
-        let proto = self.parse_proto();
-        let expr = self.parse_expr();
-        Box::new(Item::Function(proto, expr))
+    /// FUNC_DEF ::= DOC ? ATTRS 'def' PROTOTYPE EXPR
+    fn parse_def(&mut self) -> Result<Box<Item>, String> {
+        let doc = self.doc.take();
+        let attrs = self.parse_attrs()?;
+        self.expect(Token::Def)?;
+        let proto = self.parse_proto()?;
+        let expr = self.parse_expr()?;
+        Ok(Box::new(Item::Function(doc, attrs, proto, expr)))
     }
 
-    /// EXTERN ::= 'extern' PROTOTYPE
-    fn parse_extern(&mut self) -> Box<Item> {
-        self.next_token();
-        let proto = self.parse_proto();
-        Box::new(Item::Extern(proto))
+    /// EXTERN ::= DOC ? 'pure' ? 'extern' PROTOTYPE
+    fn parse_extern(&mut self) -> Result<Box<Item>, String> {
+        let doc = self.doc.take();
+        let purity = if self.token == Token::Pure {
+            self.next_token()?;
+            Purity::Pure
+        } else {
+            Purity::Effectful
+        };
+
+        self.expect(Token::Extern)?;
+        let proto = self.parse_proto()?;
+        Ok(Box::new(Item::Extern(doc, purity, proto)))
+    }
+
+    /// TEST ::= 'test' IDENT EXPR
+    fn parse_test(&mut self) -> Result<Box<Item>, String> {
+        self.expect(Token::Test)?;
+
+        let name = match self.token {
+            Token::Ident(ref name) => name.clone(),
+            ref t => return Err(format!("Expected test name, not {}", t))
+        };
+
+        self.next_token()?;
+        let expr = self.parse_expr()?;
+        Ok(Box::new(Item::Test(name, expr)))
+    }
+
+    /// IMPORT ::= 'import' ( STRING | IDENT ) [ 'as' IDENT ] ?
+    fn parse_import(&mut self) -> Result<Box<Item>, String> {
+        self.expect(Token::Import)?;
+
+        let path = match self.token {
+            Token::Str(ref path) => ModulePath::File(path.clone()),
+            Token::Ident(ref name) => ModulePath::Name(name.clone()),
+            ref t => return Err(format!("Expected module name or path, not {}", t))
+        };
+
+        self.next_token()?;
+
+        // `as` is only a keyword here
+        if self.token != Token::Ident("as".to_string()) {
+            return Ok(Box::new(Item::Import(path, None)))
+        }
+
+        self.next_token()?;
+
+        let alias = match self.token {
+            Token::Ident(ref name) if !name.contains('.') => name.clone(),
+            ref t => return Err(format!("Expected module alias, not {}", t))
+        };
+
+        self.next_token()?;
+        Ok(Box::new(Item::Import(path, Some(alias))))
     }
 
     /// TOP_LEVEL_EXPR ::= EXPR
-    fn parse_top_level_expr(&mut self) -> Box<Item> {
-        let expr = self.parse_expr();
-        Box::new(Item::Expr(expr))
+    fn parse_top_level_expr(&mut self) -> Result<Box<Item>, String> {
+        let expr = self.parse_expr()?;
+        Ok(Box::new(Item::Expr(expr)))
     }
 
-    fn parse_file(&mut self) -> Box<File> {
-        let mut items : Vec<Box<Item>> = Vec::new();
+    /// Parse the next item along with the byte offsets where it starts and
+    /// ends in the source, or return `None` at the end of the input.
+    pub fn parse_spanned_item(&mut self) -> Result<Option<(Box<Item>, usize, usize)>, String> {
+        while self.token == Token::Semicolon {
+            self.next_token()?;
+        }
 
-        loop {
-            let item = match self.token {
-                Token::Def => self.parse_def(),
-                Token::Extern => self.parse_extern(),
-                Token::Semicolon => continue,
-                Token::Eof => break,
-                _ => self.parse_top_level_expr()
-            };
+        let start = self.token_start;
+
+        let item = match self.token {
+            Token::Def | Token::At => self.parse_def()?,
+            Token::Extern | Token::Pure => self.parse_extern()?,
+            Token::Test => self.parse_test()?,
+            Token::Import => self.parse_import()?,
+            Token::Eof => return Ok(None),
+            _ => self.parse_top_level_expr()?
+        };
+
+        Ok(Some((item, start, self.prev_end)))
+    }
+
+    fn parse_file(&mut self) -> Result<Box<File>, String> {
+        let mut items : Vec<Box<Item>> = Vec::new();
 
+        while let Some((item, _, _)) = self.parse_spanned_item()? {
             items.push(item);
         }
 
-        Box::new(File(items))
+        Ok(Box::new(File(items)))
     }
 }
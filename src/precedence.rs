@@ -77,6 +77,45 @@ impl Op {
         }
     }
 
+    pub fn from_ast_binop(op: BinOp) -> Op {
+        use self::Op::*;
+        match op {
+            BinOp::Add => Add,
+            BinOp::Sub => Subtract,
+            BinOp::Mul => Multiply,
+            BinOp::Div => Divide,
+            BinOp::Rem => Modulus,
+            BinOp::And => And,
+            BinOp::Or => Or,
+            BinOp::Eq => Equal,
+            BinOp::Ne => NotEqual,
+            BinOp::Gt => Greater,
+            BinOp::Ge => GreaterEqual,
+            BinOp::Lt => Less,
+            BinOp::Le => LessEqual
+        }
+    }
+
+    /// How the operator is written in source.
+    pub fn symbol(&self) -> &'static str {
+        use self::Op::*;
+        match *self {
+            Add => "+",
+            Subtract => "-",
+            Multiply => "*",
+            Divide => "/",
+            Modulus => "%",
+            And => "&&",
+            Or => "||",
+            Equal => "==",
+            NotEqual => "!=",
+            Greater => ">",
+            GreaterEqual => ">=",
+            Less => "<",
+            LessEqual => "<="
+        }
+    }
+
     pub fn to_ast_binop(&self) -> BinOp {
         use self::Op::*;
         match *self {
@@ -0,0 +1,74 @@
+use std::collections::HashSet;
+
+use ast::*;
+use interp::Interpreter;
+use parse;
+
+/// The declarations of the math, string, assertion and output natives that
+/// programs can use without declaring them. The constants `pi` and `e` are
+/// among them as functions, called as `pi()` and `e()`, since a program has no
+/// names outside of its functions' parameters and `let` bindings.
+pub const SOURCE: &str = include_str!("prelude.k");
+
+/// The items of the prelude, in order.
+pub fn items() -> Vec<Box<Item>> {
+    // The prelude is part of the crate, so it always parses
+    parse(SOURCE).unwrap().0
+}
+
+/// Whether the prelude declares the function `name`.
+///
+/// ```
+/// assert!(kaleidescope_rs::prelude::declares("sqrt"));
+/// assert!(!kaleidescope_rs::prelude::declares("fmod"));
+/// ```
+pub fn declares(name: &str) -> bool {
+    items().iter().any(|item| match **item {
+        Item::Extern(_, _, ref proto) | Item::Function(_, _, ref proto, _) => proto.0 == name,
+        Item::Expr(_) | Item::Test(..) | Item::Import(..) => false,
+    })
+}
+
+/// Put the prelude items for the names `file` doesn't declare or define
+/// itself before its own items, returning how many there are.
+///
+/// ```
+/// let mut file = kaleidescope_rs::parse("def min(x, y, z) x  min(1, 2, 3) + pi()").unwrap();
+/// let added = kaleidescope_rs::prelude::include(&mut file);
+///
+/// assert_eq!(file.0.len(), added + 2);
+/// assert!(kaleidescope_rs::check(&file).is_ok());
+/// ```
+pub fn include(file: &mut File) -> usize {
+    let declared: HashSet<String> = file.0.iter().filter_map(|item| match **item {
+        Item::Extern(_, _, ref proto) | Item::Function(_, _, ref proto, _) => Some(proto.0.clone()),
+        Item::Expr(_) | Item::Test(..) | Item::Import(..) => None,
+    }).collect();
+
+    let mut items: Vec<Box<Item>> = items().into_iter().filter(|item| match **item {
+        Item::Extern(_, _, ref proto) | Item::Function(_, _, ref proto, _) => !declared.contains(&proto.0),
+        Item::Expr(_) | Item::Test(..) | Item::Import(..) => true,
+    }).collect();
+
+    let added = items.len();
+    items.append(&mut file.0);
+    file.0 = items;
+    added
+}
+
+/// Define the prelude in `interpreter`, so that the definitions of a program
+/// defined afterwards replace it.
+///
+/// ```
+/// use kaleidescope_rs::{Interpreter, Value};
+///
+/// let mut interpreter = Interpreter::new();
+/// kaleidescope_rs::prelude::define(&mut interpreter);
+/// assert_eq!(interpreter.call("max", &[Value::from(2.0), Value::from(3.0)]), Ok(Value::from(3.0)));
+/// assert_eq!(interpreter.call("len", &[Value::from("four")]), Ok(Value::from(4.0)));
+/// ```
+pub fn define(interpreter: &mut Interpreter) {
+    for item in items() {
+        interpreter.define(&item);
+    }
+}
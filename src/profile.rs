@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// What was measured for one function.
+#[derive(Debug, Clone, Default)]
+pub struct FunctionStats {
+    pub name: String,
+    pub calls: u64,
+
+    // Time from entering the outermost call to returning from it, so
+    // recursive calls are not counted twice
+    pub inclusive: Duration,
+
+    // Time spent in the function's own body, not in the functions it calls
+    pub exclusive: Duration,
+
+    pub max_depth: usize,
+
+    // Calls in progress
+    active: usize,
+}
+
+/// A call in progress.
+struct Entry {
+    function: usize,
+    node: usize,
+    start: Instant,
+
+    // Time spent in the calls it made so far
+    children: Duration,
+}
+
+/// Call counts and times of each function called while profiling, and the
+/// time spent in each distinct stack of calls.
+///
+/// Calls answered from a memoization cache run no body, so they are not counted.
+///
+/// ```
+/// use std::thread;
+/// use std::time::Duration;
+/// use kaleidescope_rs::profile::Profile;
+///
+/// let mut profile = Profile::new();
+/// profile.enter("outer");
+/// thread::sleep(Duration::from_millis(20));
+///
+/// // A recursive call of `inner`
+/// profile.enter("inner");
+/// profile.enter("inner");
+/// thread::sleep(Duration::from_millis(20));
+/// profile.exit();
+/// profile.exit();
+/// profile.exit();
+///
+/// let stats = |name: &str| profile.functions().into_iter().find(|stats| stats.name == name).unwrap().clone();
+/// let (outer, inner) = (stats("outer"), stats("inner"));
+///
+/// // `outer` spent about half of its time in its own body
+/// assert!(outer.inclusive >= Duration::from_millis(40));
+/// assert!(outer.exclusive >= Duration::from_millis(20) && outer.exclusive < outer.inclusive);
+///
+/// // Time in the nested call of `inner` counts once towards its inclusive time
+/// assert_eq!((inner.calls, inner.max_depth), (2, 2));
+/// assert!(inner.inclusive >= Duration::from_millis(20) && inner.inclusive <= outer.inclusive - outer.exclusive);
+/// assert!(inner.exclusive <= inner.inclusive);
+/// ```
+#[derive(Default)]
+pub struct Profile {
+    functions: Vec<FunctionStats>,
+    index: HashMap<String, usize>,
+
+    // A tree of the stacks seen: the parent and function of each node, and
+    // the exclusive time of the function when called through that stack
+    nodes: Vec<(Option<usize>, usize, Duration)>,
+    children: HashMap<(Option<usize>, usize), usize>,
+
+    stack: Vec<Entry>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    /// Start timing a call of the function `name`.
+    pub fn enter(&mut self, name: &str) {
+        let function = match self.index.get(name) {
+            Some(&function) => function,
+            None => {
+                self.functions.push(FunctionStats { name: name.to_string(), ..FunctionStats::default() });
+                self.index.insert(name.to_string(), self.functions.len() - 1);
+                self.functions.len() - 1
+            }
+        };
+
+        let parent = self.stack.last().map(|entry| entry.node);
+        let nodes = &mut self.nodes;
+        let node = *self.children.entry((parent, function)).or_insert_with(|| {
+            nodes.push((parent, function, Duration::default()));
+            nodes.len() - 1
+        });
+
+        let stats = &mut self.functions[function];
+        stats.calls += 1;
+        stats.active += 1;
+        stats.max_depth = stats.max_depth.max(stats.active);
+
+        self.stack.push(Entry { function, node, start: Instant::now(), children: Duration::default() });
+    }
+
+    /// Stop timing the innermost call, whether it returned or failed.
+    pub fn exit(&mut self) {
+        let entry = match self.stack.pop() {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        let elapsed = entry.start.elapsed();
+        let exclusive = elapsed.saturating_sub(entry.children);
+
+        let stats = &mut self.functions[entry.function];
+        stats.active -= 1;
+        stats.exclusive += exclusive;
+        if stats.active == 0 {
+            stats.inclusive += elapsed;
+        }
+
+        self.nodes[entry.node].2 += exclusive;
+
+        if let Some(caller) = self.stack.last_mut() {
+            caller.children += elapsed;
+        }
+    }
+
+    /// Every function called, the one that took the most exclusive time first.
+    pub fn functions(&self) -> Vec<&FunctionStats> {
+        let mut functions: Vec<&FunctionStats> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then_with(|| a.name.cmp(&b.name)));
+        functions
+    }
+
+    /// A table of `functions`, for people.
+    pub fn report(&self) -> String {
+        let functions = self.functions();
+        let width = functions.iter().map(|stats| stats.name.len()).chain(Some("function".len())).max().unwrap();
+        let mut out = String::new();
+
+        writeln!(out, "{:<width$}  {:>10}  {:>12}  {:>12}  {:>9}", "function", "calls", "inclusive", "exclusive", "max depth", width = width).unwrap();
+
+        for stats in functions {
+            writeln!(
+                out, "{:<width$}  {:>10}  {:>12}  {:>12}  {:>9}",
+                stats.name, stats.calls, milliseconds(stats.inclusive), milliseconds(stats.exclusive), stats.max_depth,
+                width = width
+            ).unwrap();
+        }
+
+        out
+    }
+
+    /// The exclusive time of each stack of calls, one `outer;inner nanoseconds`
+    /// line per stack, as read by flamegraph tools.
+    pub fn folded_stacks(&self) -> String {
+        let mut lines: Vec<String> = Vec::new();
+
+        for &(parent, function, time) in &self.nodes {
+            let mut names = vec![self.functions[function].name.as_str()];
+            let mut next = parent;
+
+            while let Some(node) = next {
+                names.push(self.functions[self.nodes[node].1].name.as_str());
+                next = self.nodes[node].0;
+            }
+
+            names.reverse();
+            lines.push(format!("{} {}", names.join(";"), time.as_nanos()));
+        }
+
+        lines.sort();
+
+        let mut out = String::new();
+        for line in lines {
+            writeln!(out, "{}", line).unwrap();
+        }
+
+        out
+    }
+}
+
+fn milliseconds(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
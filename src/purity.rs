@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use ast::*;
+use callgraph::{CallGraph, NodeKind};
+
+/// Which functions in a file can be called without observable effects.
+///
+/// Externs are effectful unless declared `pure extern`, and calling anything
+/// undefined is assumed to be effectful. A function is pure when everything it
+/// calls, transitively, is pure; recursion alone does not make it effectful.
+pub struct PurityAnalysis {
+    graph: CallGraph,
+    purity: Vec<Purity>,
+
+    // The callee responsible for each effectful node, if it isn't effectful itself
+    cause: Vec<Option<usize>>,
+}
+
+impl PurityAnalysis {
+    pub fn of(file: &File) -> PurityAnalysis {
+        let graph = CallGraph::build(file);
+        let mut declared: HashMap<&str, Purity> = HashMap::new();
+
+        // Conflicting declarations of the same extern are resolved pessimistically
+        for item in &file.0 {
+            if let Item::Extern(_, purity, ref proto) = **item {
+                let entry = declared.entry(&proto.0).or_insert(purity);
+                if purity == Purity::Effectful {
+                    *entry = Purity::Effectful;
+                }
+            }
+        }
+
+        let mut purity = vec![Purity::Pure; graph.nodes.len()];
+        let mut cause = vec![None; graph.nodes.len()];
+
+        // Components come callees-first, so everything called from outside one is already known
+        for scc in graph.sccs() {
+            let mut effect: Option<Option<usize>> = None;
+
+            for &node in &scc {
+                let own = match graph.nodes[node].kind {
+                    NodeKind::Function => Purity::Pure,
+                    NodeKind::Extern => declared[graph.nodes[node].name.as_str()],
+                    NodeKind::Undefined => Purity::Effectful,
+                };
+
+                if own == Purity::Effectful {
+                    effect = Some(None);
+                    break
+                }
+
+                let callee = graph.edges[node].iter().find(|&&callee| {
+                    !scc.contains(&callee) && purity[callee] == Purity::Effectful
+                });
+
+                if let Some(&callee) = callee {
+                    effect = Some(Some(callee));
+                    break
+                }
+            }
+
+            if let Some(effect) = effect {
+                for &node in &scc {
+                    purity[node] = Purity::Effectful;
+                    cause[node] = effect.filter(|&callee| callee != node);
+                }
+            }
+        }
+
+        PurityAnalysis { graph, purity, cause }
+    }
+
+    /// Purity of the function or extern called `name`; unknown names are effectful.
+    pub fn purity(&self, name: &str) -> Purity {
+        match self.graph.lookup(name) {
+            Some(node) => self.purity[node],
+            None => Purity::Effectful,
+        }
+    }
+
+    /// The function or extern whose call makes `name` effectful, if that isn't `name` itself.
+    pub fn cause(&self, name: &str) -> Option<&str> {
+        self.graph.lookup(name)
+            .and_then(|node| self.cause[node])
+            .map(|callee| self.graph.nodes[callee].name.as_str())
+    }
+
+    /// Whether evaluating `expr` can have no observable effects.
+    pub fn is_pure(&self, expr: &Expr) -> bool {
+        match *expr {
+            Expr::Number(_) | Expr::Str(_) | Expr::Name(_) => true,
+            Expr::Binary(_, ref lhs, ref rhs) => self.is_pure(lhs) && self.is_pure(rhs),
+            Expr::Unary(_, ref operand) | Expr::Paren(ref operand) => self.is_pure(operand),
+            Expr::Let(_, ref value, ref body) => self.is_pure(value) && self.is_pure(body),
+            Expr::If(ref cond, ref then, ref otherwise) => {
+                self.is_pure(cond) && self.is_pure(then) && self.is_pure(otherwise)
+            }
+            Expr::Call(ref name, ref args) => {
+                self.purity(name) == Purity::Pure && args.iter().all(|arg| self.is_pure(arg))
+            }
+        }
+    }
+}
+
+/// Whether `expr` evaluates to a number whenever it evaluates at all. Only
+/// `+` can make a string, from two strings, and calls and names may be
+/// anything.
+pub fn is_number(expr: &Expr) -> bool {
+    match *expr {
+        Expr::Number(_) => true,
+        Expr::Str(_) | Expr::Name(_) | Expr::Call(..) => false,
+        Expr::Binary(BinOp::Add, ref lhs, ref rhs) => is_number(lhs) && is_number(rhs),
+        Expr::Binary(..) | Expr::Unary(..) => true,
+        Expr::Paren(ref inner) => is_number(inner),
+        Expr::Let(_, _, ref body) => is_number(body),
+        Expr::If(_, ref then, ref otherwise) => is_number(then) && is_number(otherwise),
+    }
+}
+
+/// Whether the operation at the root of `expr`, leaving its operands aside,
+/// can fail: arithmetic on or ordering of what may be a string, negating
+/// one, and any call, which may run into a limit or be a native that fails.
+pub fn fails_itself(expr: &Expr) -> bool {
+    match *expr {
+        Expr::Binary(BinOp::And, ..) | Expr::Binary(BinOp::Or, ..) => false,
+        Expr::Binary(BinOp::Eq, ..) | Expr::Binary(BinOp::Ne, ..) => false,
+        Expr::Binary(_, ref lhs, ref rhs) => !(is_number(lhs) && is_number(rhs)),
+        Expr::Unary(UnOp::Neg, ref operand) => !is_number(operand),
+        Expr::Call(..) => true,
+        Expr::Number(_) | Expr::Str(_) | Expr::Name(_) | Expr::Unary(UnOp::Not, _) => false,
+        Expr::Paren(_) | Expr::Let(..) | Expr::If(..) => false,
+    }
+}
+
+/// Whether evaluating `expr` can fail, which optimizations must not hide
+/// or move past effects or other failures, even when it is pure.
+pub fn can_fail(expr: &Expr) -> bool {
+    fails_itself(expr) || match *expr {
+        Expr::Number(_) | Expr::Str(_) | Expr::Name(_) => false,
+        Expr::Binary(_, ref lhs, ref rhs) => can_fail(lhs) || can_fail(rhs),
+        Expr::Unary(_, ref operand) | Expr::Paren(ref operand) => can_fail(operand),
+        Expr::Call(_, ref args) => args.iter().any(|arg| can_fail(arg)),
+        Expr::Let(_, ref value, ref body) => can_fail(value) || can_fail(body),
+        Expr::If(ref cond, ref then, ref otherwise) => can_fail(cond) || can_fail(then) || can_fail(otherwise),
+    }
+}
@@ -0,0 +1,264 @@
+use std::env;
+use std::path::PathBuf;
+
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+use ast::*;
+use check;
+use interp::Interpreter;
+use kaleidescope_rs::limits::Limits;
+use lexer::Lexer;
+use modules::Loader;
+use parser::Parser;
+use prelude;
+use schema;
+use tokens::{Delim, Token};
+
+const HELP: &str = "\
+Enter definitions, externs, imports and expressions; expressions are evaluated at once.
+Input continues on the next line while parentheses are open or an item is
+unfinished, and an empty line ends it regardless.
+
+    :tokens SOURCE   print the tokens of SOURCE
+    :ast SOURCE      print the ast of SOURCE as json
+    :defs            list the functions and externs defined so far
+    :reset           forget every definition
+    :help            print this help
+    :quit            leave (as does ctrl-d)";
+
+/// The definitions entered so far, and an interpreter that can call them.
+pub struct Session {
+    defs: File,
+    interpreter: Interpreter,
+    loader: Loader,
+    memo_auto: bool,
+    memo_capacity: usize,
+    prelude: bool,
+    limits: Limits,
+    module_paths: Vec<PathBuf>,
+}
+
+impl Session {
+    /// A session with nothing entered yet, and the prelude defined if `prelude`
+    /// is set. Modules imported by name are looked for in `module_paths`.
+    pub fn new(memo_auto: bool, memo_capacity: usize, prelude: bool, module_paths: Vec<PathBuf>) -> Session {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_memo_capacity(memo_capacity);
+
+        if prelude {
+            prelude::define(&mut interpreter);
+        }
+
+        let loader = Loader::new(module_paths.clone());
+        let limits = Limits::default();
+        Session { defs: File(Vec::new()), interpreter, loader, memo_auto, memo_capacity, prelude, limits, module_paths }
+    }
+
+    /// Stop evaluations that go beyond `limits`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.interpreter.set_limits(limits.clone());
+        self.limits = limits;
+    }
+
+    /// Forget every definition and import, keeping the options and limits of the session.
+    pub fn reset(&mut self) {
+        let limits = self.limits.clone();
+        *self = Session::new(self.memo_auto, self.memo_capacity, self.prelude, self.module_paths.clone());
+        self.set_limits(limits);
+    }
+
+    /// Define the functions and externs in `file` and the modules it imports,
+    /// then evaluate and print its top-level expressions and the outcome of
+    /// its tests in order, stopping at the first error.
+    pub fn enter(&mut self, mut file: File) -> Result<(), String> {
+        let modules = self.loader.link(vec![("input", file.0.iter_mut().map(|item| &mut **item).collect())])?;
+
+        for item in modules.into_iter().chain(file.0) {
+            match *item {
+                Item::Import(..) => continue,
+
+                Item::Expr(ref expr) => {
+                    println!("{}", self.interpreter.eval_top_level(expr).map_err(|e| e.to_string())?);
+                    continue
+                }
+
+                // Tests run at once, against the definitions so far
+                Item::Test(ref name, ref expr) => {
+                    match self.interpreter.eval_top_level(expr) {
+                        Ok(ref value) if value.is_true() => println!("test {} ... ok", name),
+                        Ok(value) => println!("test {} ... FAILED: evaluated to {}", name, value),
+                        Err(e) => println!("test {} ... FAILED: {}", name, e),
+                    }
+
+                    continue
+                }
+
+                _ => {}
+            }
+
+            self.interpreter.define(&item);
+
+            // A new definition replaces the listing of an earlier one
+            self.defs.0.retain(|def| !same_declaration(def, &item));
+            self.defs.0.push(item);
+
+            if self.memo_auto {
+                self.interpreter.memoize_pure_recursive(&self.defs);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn same_declaration(a: &Item, b: &Item) -> bool {
+    match (a, b) {
+        (Item::Function(_, _, a, _), Item::Function(_, _, b, _)) => a.0 == b.0,
+        (Item::Extern(_, _, a), Item::Extern(_, _, b)) => a.0 == b.0,
+        _ => false,
+    }
+}
+
+/// Read input from the terminal and enter it into `session` until the user quits.
+pub fn run(session: &mut Session) -> Result<(), String> {
+    let mut editor = DefaultEditor::new().map_err(|e| e.to_string())?;
+    let history = history_path();
+
+    if let Some(ref path) = history {
+        // There is no history the first time round
+        let _ = editor.load_history(path);
+    }
+
+    let mut buffer = String::new();
+
+    loop {
+        let prompt = if buffer.is_empty() { "ready> " } else { "...> " };
+
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+
+            // Ctrl-c abandons the current input, not the session
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue
+            }
+
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        if buffer.is_empty() && line.trim_start().starts_with(':') {
+            let _ = editor.add_history_entry(line.trim());
+
+            if !command(session, line.trim()) {
+                break
+            }
+
+            continue
+        }
+
+        let forced = line.trim().is_empty();
+        if forced && buffer.is_empty() {
+            continue
+        }
+
+        buffer.push_str(&line);
+        buffer.push('\n');
+
+        let file = match read_input(&buffer) {
+            Input::Incomplete(_) if !forced => continue,
+            Input::Complete(file) => Ok(file),
+            Input::Incomplete(e) | Input::Invalid(e) => Err(e),
+        };
+
+        let _ = editor.add_history_entry(buffer.trim());
+        buffer.clear();
+
+        if let Err(e) = file.and_then(|file| session.enter(*file)) {
+            eprintln!("error: {}", e);
+        }
+    }
+
+    if let Some(ref path) = history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("warning: unable to save history to `{}`: {}", path.display(), e);
+        }
+    }
+
+    Ok(())
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".kaleidescope_history"))
+}
+
+enum Input {
+    Complete(Box<File>),
+
+    // Could still become valid with more lines
+    Incomplete(String),
+    Invalid(String),
+}
+
+fn read_input(source: &str) -> Input {
+    let tokens = match Lexer::new(source.to_string()).tokenize() {
+        Ok(tokens) => tokens,
+        Err(e) => return Input::Invalid(e),
+    };
+
+    let open = tokens.iter().filter(|&tok| *tok == Token::OpenDelim(Delim::Paren)).count();
+    let closed = tokens.iter().filter(|&tok| *tok == Token::CloseDelim(Delim::Paren)).count();
+
+    if open > closed {
+        return Input::Incomplete("unclosed `(`".to_string())
+    }
+
+    let mut parser = match Parser::new(Lexer::new(source.to_string())) {
+        Ok(parser) => parser,
+        Err(e) => return Input::Invalid(e),
+    };
+
+    match parser.parse() {
+        Ok(file) => Input::Complete(file),
+        Err(e) if parser.at_eof() => Input::Incomplete(e),
+        Err(e) => Input::Invalid(e),
+    }
+}
+
+/// Run the meta-command `line`, returning whether the session should go on.
+fn command(session: &mut Session, line: &str) -> bool {
+    let (name, rest) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line, ""),
+    };
+
+    match name {
+        ":tokens" => match Lexer::new(rest.to_string()).tokenize() {
+            Ok(tokens) => {
+                for token in tokens {
+                    println!("{}", token);
+                }
+            }
+            Err(e) => eprintln!("error: {}", e),
+        },
+
+        ":ast" => match Parser::new(Lexer::new(rest.to_string())).and_then(|mut parser| parser.parse()) {
+            Ok(ast) => println!("{}", schema::to_json(&ast)),
+            Err(e) => eprintln!("error: {}", e),
+        },
+
+        ":defs" => {
+            for line in check::summarize(&session.defs) {
+                println!("{}", line);
+            }
+        }
+
+        ":reset" => session.reset(),
+        ":help" => println!("{}", HELP),
+        ":quit" | ":q" => return false,
+        _ => eprintln!("error: unknown command `{}`, see `:help`", name),
+    }
+
+    true
+}
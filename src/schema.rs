@@ -0,0 +1,314 @@
+//! The JSON form of the ast, as printed by `parse` and `build --emit json`
+//! and read by `--from-ast`.
+//!
+//! A document is an object holding the schema version and the items of a file:
+//!
+//! ```text
+//! { "version": 1, "items": [ITEM, ...] }
+//! ```
+//!
+//! Items and expressions are objects whose `kind` field says which node they
+//! are, with the node's children in named fields:
+//!
+//! ```text
+//! ITEM  ::= { "kind": "function", "doc"?: STRING, "attrs": [ATTR, ...], "proto": PROTO, "body": EXPR }
+//!         | { "kind": "extern", "doc"?: STRING, "purity": "pure" | "effectful", "proto": PROTO }
+//!         | { "kind": "expr", "expr": EXPR }
+//!         | { "kind": "test", "name": STRING, "expr": EXPR }
+//!         | { "kind": "import", "module": MODULE, "alias": STRING | null }
+//! ATTR  ::= "noinline" | "export" | "memo"
+//! PROTO ::= { "name": STRING, "params": [STRING, ...] }
+//! MODULE ::= { "file": STRING } | { "name": STRING }
+//!
+//! EXPR  ::= { "kind": "number", "value": NUMBER }
+//!         | { "kind": "string", "value": STRING }
+//!         | { "kind": "name", "name": STRING }
+//!         | { "kind": "binary", "op": BINOP, "lhs": EXPR, "rhs": EXPR }
+//!         | { "kind": "unary", "op": "-" | "!", "operand": EXPR }
+//!         | { "kind": "call", "callee": STRING, "args": [EXPR, ...] }
+//!         | { "kind": "paren", "expr": EXPR }
+//!         | { "kind": "if", "cond": EXPR, "then": EXPR, "else": EXPR }
+//!         | { "kind": "let", "name": STRING, "value": EXPR, "body": EXPR }
+//! BINOP ::= "+" | "-" | "*" | "/" | "%" | "&&" | "||"
+//!         | "==" | "!=" | ">" | ">=" | "<" | "<="
+//! ```
+//!
+//! A `doc` field is only written for items with a doc comment, and an item
+//! without one reads as undocumented.
+//!
+//! Numbers are written so that they read back as exactly the same `f64`, so
+//! serializing a file and reading it back always gives the same ast. The
+//! version is bumped whenever a change would make existing documents read
+//! differently, and documents of any other version are rejected.
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use ast::*;
+
+/// Version of the schema written by this build, and the only one it reads.
+pub const AST_VERSION: u32 = 1;
+
+/// Deepest that arrays and objects may nest in a document that is read:
+/// enough for chains of a thousand operators, and few enough to read on an
+/// 8 MiB stack.
+pub const MAX_NESTING: usize = 2048;
+
+/// Serialize `file` as a pretty-printed document.
+pub fn to_json(file: &File) -> String {
+    serde_json::to_string_pretty(file).expect("an ast always serializes")
+}
+
+/// Read a document back into the file it was serialized from. Documents that
+/// nest deeper than `MAX_NESTING` are rejected rather than read.
+pub fn from_json(json: &str) -> Result<File, String> {
+    if nesting(json) > MAX_NESTING {
+        return Err(format!("the ast nests more than {} deep", MAX_NESTING))
+    }
+
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+
+    // Long operator chains nest deeper than serde_json allows by default,
+    // and the depth has been checked above
+    deserializer.disable_recursion_limit();
+
+    let file = File::deserialize(&mut deserializer).map_err(|e| e.to_string())?;
+    deserializer.end().map_err(|e| e.to_string())?;
+    Ok(file)
+}
+
+/// How deep the arrays and objects of `json` nest, ignoring brackets in
+/// strings. Malformed documents are left for the deserializer to reject.
+fn nesting(json: &str) -> usize {
+    let (mut depth, mut deepest) = (0usize, 0);
+    let (mut in_string, mut escaped) = (false, false);
+
+    for byte in json.bytes() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+
+            continue
+        }
+
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                deepest = deepest.max(depth);
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    deepest
+}
+
+#[derive(Serialize)]
+struct FileRef<'a> {
+    version: u32,
+    items: &'a [Box<Item>],
+}
+
+#[derive(Deserialize)]
+struct FileNode {
+    version: u32,
+    items: Vec<Box<Item>>,
+}
+
+impl Serialize for File {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FileRef { version: AST_VERSION, items: &self.0 }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for File {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<File, D::Error> {
+        let node = FileNode::deserialize(deserializer)?;
+
+        if node.version != AST_VERSION {
+            return Err(D::Error::custom(format!(
+                "unsupported ast version {}, expected {}",
+                node.version, AST_VERSION
+            )))
+        }
+
+        Ok(File(node.items))
+    }
+}
+
+#[derive(Serialize)]
+struct ProtoRef<'a> {
+    name: &'a str,
+    params: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct ProtoNode {
+    name: String,
+    params: Vec<String>,
+}
+
+impl Serialize for FuncProto {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ProtoRef { name: &self.0, params: &self.1 }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FuncProto {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<FuncProto, D::Error> {
+        let node = ProtoNode::deserialize(deserializer)?;
+        Ok(FuncProto(node.name, node.params))
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum ItemRef<'a> {
+    Function {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        doc: &'a Option<String>,
+        attrs: &'a [Attr],
+        proto: &'a FuncProto,
+        body: &'a Expr,
+    },
+    Extern {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        doc: &'a Option<String>,
+        purity: Purity,
+        proto: &'a FuncProto,
+    },
+    Expr { expr: &'a Expr },
+    Test { name: &'a str, expr: &'a Expr },
+    Import { module: &'a ModulePath, alias: &'a Option<String> },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum ItemNode {
+    Function {
+        #[serde(default)]
+        doc: Option<String>,
+        attrs: Vec<Attr>,
+        proto: Box<FuncProto>,
+        body: Box<Expr>,
+    },
+    Extern {
+        #[serde(default)]
+        doc: Option<String>,
+        purity: Purity,
+        proto: Box<FuncProto>,
+    },
+    Expr { expr: Box<Expr> },
+    Test { name: String, expr: Box<Expr> },
+    Import { module: ModulePath, alias: Option<String> },
+}
+
+impl Serialize for Item {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let node = match *self {
+            Item::Function(ref doc, ref attrs, ref proto, ref body) => ItemRef::Function { doc, attrs, proto, body },
+            Item::Extern(ref doc, purity, ref proto) => ItemRef::Extern { doc, purity, proto },
+            Item::Expr(ref expr) => ItemRef::Expr { expr },
+            Item::Test(ref name, ref expr) => ItemRef::Test { name, expr },
+            Item::Import(ref module, ref alias) => ItemRef::Import { module, alias },
+        };
+
+        node.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Item {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Item, D::Error> {
+        let item = match ItemNode::deserialize(deserializer)? {
+            ItemNode::Function { doc, attrs, proto, body } => Item::Function(doc, attrs, proto, body),
+            ItemNode::Extern { doc, purity, proto } => Item::Extern(doc, purity, proto),
+            ItemNode::Expr { expr } => Item::Expr(expr),
+            ItemNode::Test { name, expr } => Item::Test(name, expr),
+            ItemNode::Import { module, alias } => Item::Import(module, alias),
+        };
+
+        Ok(item)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum ExprRef<'a> {
+    Number { value: f64 },
+    #[serde(rename = "string")]
+    Str { value: &'a str },
+    Name { name: &'a str },
+    Binary { op: BinOp, lhs: &'a Expr, rhs: &'a Expr },
+    Unary { op: UnOp, operand: &'a Expr },
+    Call { callee: &'a str, args: &'a [Box<Expr>] },
+    Paren { expr: &'a Expr },
+    If {
+        cond: &'a Expr,
+        then: &'a Expr,
+        #[serde(rename = "else")]
+        otherwise: &'a Expr,
+    },
+    Let { name: &'a str, value: &'a Expr, body: &'a Expr },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum ExprNode {
+    Number { value: f64 },
+    #[serde(rename = "string")]
+    Str { value: String },
+    Name { name: String },
+    Binary { op: BinOp, lhs: Box<Expr>, rhs: Box<Expr> },
+    Unary { op: UnOp, operand: Box<Expr> },
+    Call { callee: String, args: Vec<Box<Expr>> },
+    Paren { expr: Box<Expr> },
+    If {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        #[serde(rename = "else")]
+        otherwise: Box<Expr>,
+    },
+    Let { name: String, value: Box<Expr>, body: Box<Expr> },
+}
+
+impl Serialize for Expr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let node = match *self {
+            Expr::Number(value) => ExprRef::Number { value },
+            Expr::Str(ref value) => ExprRef::Str { value },
+            Expr::Name(ref name) => ExprRef::Name { name },
+            Expr::Binary(op, ref lhs, ref rhs) => ExprRef::Binary { op, lhs, rhs },
+            Expr::Unary(op, ref operand) => ExprRef::Unary { op, operand },
+            Expr::Call(ref callee, ref args) => ExprRef::Call { callee, args },
+            Expr::Paren(ref expr) => ExprRef::Paren { expr },
+            Expr::If(ref cond, ref then, ref otherwise) => ExprRef::If { cond, then, otherwise },
+            Expr::Let(ref name, ref value, ref body) => ExprRef::Let { name, value, body },
+        };
+
+        node.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Expr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Expr, D::Error> {
+        let expr = match ExprNode::deserialize(deserializer)? {
+            ExprNode::Number { value } => Expr::Number(value),
+            ExprNode::Str { value } => Expr::Str(value),
+            ExprNode::Name { name } => Expr::Name(name),
+            ExprNode::Binary { op, lhs, rhs } => Expr::Binary(op, lhs, rhs),
+            ExprNode::Unary { op, operand } => Expr::Unary(op, operand),
+            ExprNode::Call { callee, args } => Expr::Call(callee, args),
+            ExprNode::Paren { expr } => Expr::Paren(expr),
+            ExprNode::If { cond, then, otherwise } => Expr::If(cond, then, otherwise),
+            ExprNode::Let { name, value, body } => Expr::Let(name, value, body),
+        };
+
+        Ok(expr)
+    }
+}
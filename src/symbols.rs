@@ -0,0 +1,271 @@
+use ast::*;
+use format::{line_of, line_starts};
+use lexer::Lexer;
+use parser::Parser;
+use prelude;
+use tokens::{Delim, Token};
+
+/// What an identifier in the source is doing there.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    FunctionDef,  // name in `def f(x)`
+    ExternDecl,   // name in `extern f(x)`
+    Call,         // `f` in `f(1)`
+    Param,        // `x` in `def f(x)`
+    Variable,     // `x` in `def f(x) x + 1`
+}
+
+/// An identifier in the source, with its byte range.
+#[derive(Debug)]
+pub struct Occurrence {
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+    pub role: Role,
+
+    // Index of the item containing it, in `SymbolIndex::items`
+    pub item: usize,
+}
+
+impl Occurrence {
+    pub fn is_function(&self) -> bool {
+        matches!(self.role, Role::FunctionDef | Role::ExternDecl | Role::Call)
+    }
+}
+
+/// A parsed item with the byte range it was parsed from.
+pub struct SpannedItem {
+    pub item: Box<Item>,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Every identifier of a source file, resolved to the function or parameter
+/// it names, for tools that need to point into the source.
+///
+/// Items up to the first syntax error are indexed, so a file that is being
+/// edited still has its earlier items resolved.
+pub struct SymbolIndex {
+    pub items: Vec<SpannedItem>,
+    pub occurrences: Vec<Occurrence>,
+
+    // The first lexer or parser error, with the byte range of the offending token
+    pub error: Option<(usize, usize, String)>,
+}
+
+impl SymbolIndex {
+    pub fn of(source: &str) -> SymbolIndex {
+        let tokens = spanned_tokens(source);
+        let mut index = SymbolIndex { items: Vec::new(), occurrences: Vec::new(), error: None };
+
+        let mut parser = match Parser::new(Lexer::new(source.to_string())) {
+            Ok(parser) => parser,
+            Err(e) => {
+                index.error = Some(error_span(&tokens, 0, e));
+                return index
+            }
+        };
+
+        loop {
+            match parser.parse_spanned_item() {
+                Ok(Some((item, start, end))) => index.items.push(SpannedItem { item, start, end }),
+                Ok(None) => break,
+                Err(e) => {
+                    index.error = Some(error_span(&tokens, parser.offset(), e));
+                    break
+                }
+            }
+        }
+
+        for (i, item) in index.items.iter().enumerate() {
+            let item_tokens: Vec<&(usize, usize, Token)> = tokens.iter()
+                .filter(|&&(start, end, _)| start >= item.start && end <= item.end)
+                .collect();
+
+            index.occurrences.extend(occurrences(i, &item.item, &item_tokens));
+        }
+
+        index
+    }
+
+    /// The identifier at byte `offset`, including just after its last character.
+    pub fn at(&self, offset: usize) -> Option<&Occurrence> {
+        self.occurrences.iter().find(|occ| occ.start <= offset && offset <= occ.end)
+    }
+
+    /// Where the function or parameter named by `occ` is defined. Functions
+    /// defined with `def` are preferred to their `extern` declarations.
+    pub fn definition(&self, occ: &Occurrence) -> Option<&Occurrence> {
+        if occ.is_function() {
+            let defs = self.occurrences.iter().filter(|def| def.name == occ.name);
+
+            defs.clone().find(|def| def.role == Role::FunctionDef)
+                .or_else(|| defs.clone().find(|def| def.role == Role::ExternDecl))
+        } else {
+            self.occurrences.iter().find(|def| def.item == occ.item && def.role == Role::Param && def.name == occ.name)
+        }
+    }
+
+    /// Every occurrence naming the same function or parameter as `occ`, itself and definitions included.
+    pub fn references(&self, occ: &Occurrence) -> Vec<&Occurrence> {
+        self.occurrences.iter().filter(|other| {
+            other.name == occ.name && if occ.is_function() {
+                other.is_function()
+            } else {
+                !other.is_function() && other.item == occ.item
+            }
+        }).collect()
+    }
+
+    /// The item defining the function `name`, or else declaring it `extern`.
+    pub fn declaration(&self, name: &str) -> Option<&SpannedItem> {
+        let named = self.items.iter().filter(|item| match *item.item {
+            Item::Function(_, _, ref proto, _) | Item::Extern(_, _, ref proto) => proto.0 == name,
+            Item::Expr(_) | Item::Test(..) | Item::Import(..) => false,
+        });
+
+        named.clone().find(|item| matches!(*item.item, Item::Function(..)))
+            .or_else(|| named.clone().next())
+    }
+
+    /// Problems that can be pinned to a range of the source: the syntax error,
+    /// calls to undefined functions and unknown variables. Functions of the
+    /// prelude are defined, and so may be any function once the source
+    /// imports a module.
+    pub fn problems(&self) -> Vec<(usize, usize, String)> {
+        let mut problems: Vec<(usize, usize, String)> = Vec::new();
+        let imports = self.items.iter().any(|item| matches!(*item.item, Item::Import(..)));
+
+        for occ in &self.occurrences {
+            match occ.role {
+                Role::Call if self.definition(occ).is_none() && !prelude::declares(&occ.name) && !imports => {
+                    problems.push((occ.start, occ.end, format!("call to undefined function `{}`", occ.name)));
+                }
+
+                Role::Variable if self.definition(occ).is_none() => {
+                    problems.push((occ.start, occ.end, format!("unknown variable `{}`", occ.name)));
+                }
+
+                _ => {}
+            }
+        }
+
+        if let Some((start, end, ref message)) = self.error {
+            problems.push((start, end, message.clone()));
+        }
+
+        problems
+    }
+}
+
+/// `input:line:column` of where the extern `name` is declared, in the first
+/// of the named `inputs` that declares it.
+///
+/// ```
+/// let inputs = vec![
+///     ("lib.k".to_string(), "def f(x) x".to_string()),
+///     ("main.k".to_string(), "def g(x) x\n  extern sin(x)".to_string()),
+/// ];
+///
+/// assert_eq!(kaleidescope_rs::symbols::extern_location(&inputs, "sin"), Some("main.k:2:3".to_string()));
+/// assert_eq!(kaleidescope_rs::symbols::extern_location(&inputs, "cos"), None);
+/// ```
+pub fn extern_location(inputs: &[(String, String)], name: &str) -> Option<String> {
+    for (input, source) in inputs {
+        let index = SymbolIndex::of(source);
+        let declaration = index.items.iter().find(|spanned| match *spanned.item {
+            Item::Extern(_, _, ref proto) => proto.0 == name,
+            _ => false,
+        });
+
+        if let Some(spanned) = declaration {
+            let lines = line_starts(source);
+            let line = line_of(&lines, spanned.start);
+            let column = source[lines[line]..spanned.start].chars().count();
+            return Some(format!("{}:{}:{}", input, line + 1, column + 1))
+        }
+    }
+
+    None
+}
+
+/// Significant tokens of `source` with their byte ranges, up to the first lexer error.
+pub fn spanned_tokens(source: &str) -> Vec<(usize, usize, Token)> {
+    let mut lexer = Lexer::new(source.to_string());
+    let mut tokens: Vec<(usize, usize, Token)> = Vec::new();
+
+    loop {
+        let start = lexer.offset();
+
+        match lexer.next_token() {
+            Ok(Token::Whitespace) | Ok(Token::Comment(_)) | Ok(Token::DocComment(_)) => {}
+            Ok(Token::Eof) | Err(_) => return tokens,
+            Ok(tok) => tokens.push((start, lexer.offset(), tok)),
+        }
+    }
+}
+
+fn error_span(tokens: &[(usize, usize, Token)], offset: usize, message: String) -> (usize, usize, String) {
+    match tokens.iter().find(|&&(start, _, _)| start == offset) {
+        Some(&(start, end, _)) => (start, end, message),
+        None => (offset, offset + 1, message),
+    }
+}
+
+/// Classify the identifiers among the tokens of item number `i`.
+fn occurrences(i: usize, item: &Item, tokens: &[&(usize, usize, Token)]) -> Vec<Occurrence> {
+    let mut occurrences: Vec<Occurrence> = Vec::new();
+
+    let name_role = match *item {
+        Item::Function(..) => Some(Role::FunctionDef),
+        Item::Extern(..) => Some(Role::ExternDecl),
+        Item::Expr(_) | Item::Test(..) => None,
+
+        // The module and its alias aren't functions or variables
+        Item::Import(..) => return occurrences,
+    };
+
+    // The prototype runs from the name to the first `)`, and a test's name
+    // follows the `test` keyword without naming anything
+    let mut body = match *item {
+        Item::Test(..) => 2,
+        _ => 0,
+    };
+
+    if let Some(role) = name_role {
+        // The first identifier that isn't an attribute
+        let name = (0..tokens.len()).find(|&n| {
+            matches!(tokens[n].2, Token::Ident(_)) && (n == 0 || tokens[n - 1].2 != Token::At)
+        });
+
+        let name = match name {
+            Some(name) => name,
+            None => return occurrences
+        };
+
+        for (n, &&(start, end, ref tok)) in tokens.iter().enumerate().skip(name) {
+            if let Token::Ident(ref ident) = *tok {
+                let role = if n == name { role } else { Role::Param };
+                occurrences.push(Occurrence { name: ident.clone(), start, end, role, item: i });
+            }
+
+            if *tok == Token::CloseDelim(Delim::Paren) {
+                body = n + 1;
+                break
+            }
+        }
+    }
+
+    for (n, &&(start, end, ref tok)) in tokens.iter().enumerate().skip(body) {
+        if let Token::Ident(ref ident) = *tok {
+            let role = match tokens.get(n + 1) {
+                Some(&&(_, _, Token::OpenDelim(Delim::Paren))) => Role::Call,
+                _ => Role::Variable,
+            };
+
+            occurrences.push(Occurrence { name: ident.clone(), start, end, role, item: i });
+        }
+    }
+
+    occurrences
+}
@@ -0,0 +1,252 @@
+use std::io::prelude::*;
+use std::io;
+
+use ast::*;
+use format::{line_of, line_starts};
+use interp::{self, AssertionSite, Interpreter};
+use limits::Limits;
+use modules::{Loader, SpannedInput};
+use prelude;
+use symbols::spanned_tokens;
+use tokens::{Delim, Token};
+
+/// A `test` item, by where it is in the suite.
+pub struct Test {
+    pub name: String,
+    input: usize,
+    item: usize,
+}
+
+/// Why a test failed, and which expression failed it.
+pub struct Failure {
+    pub message: String,
+
+    // `input:line:column` of the start of the expression
+    pub location: String,
+    pub source: String,
+}
+
+/// The `test` items of a program spread over several inputs.
+///
+/// Each test runs in a fresh interpreter with every function and extern of
+/// the program defined, so tests can't affect each other, and top-level
+/// expressions are never run.
+pub struct Suite {
+    inputs: Vec<SpannedInput>,
+    prelude: bool,
+    limits: Limits,
+
+    // Functions and externs of the modules the inputs import
+    modules: Vec<Box<Item>>,
+}
+
+impl Suite {
+    /// Load the named `inputs` with `loader`, as `Loader::load_spanned` does.
+    /// Tests run with the prelude defined first if `prelude` is set.
+    pub fn load(inputs: Vec<(String, String)>, prelude: bool, loader: &mut Loader) -> Result<Suite, String> {
+        let (inputs, modules) = loader.load_spanned(inputs)?;
+        Ok(Suite { inputs, prelude, limits: Limits::default(), modules })
+    }
+
+    /// Stop each test that goes beyond `limits`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Every test, in the order of the inputs.
+    pub fn tests(&self) -> Vec<Test> {
+        let mut tests: Vec<Test> = Vec::new();
+
+        for (i, input) in self.inputs.iter().enumerate() {
+            for (n, (item, _, _)) in input.items.iter().enumerate() {
+                if let Item::Test(ref name, _) = **item {
+                    tests.push(Test { name: name.clone(), input: i, item: n });
+                }
+            }
+        }
+
+        tests
+    }
+
+    /// Run `test`, which passes if its expression evaluates to a true value.
+    pub fn run(&self, test: &Test) -> Result<(), Failure> {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(self.limits.clone());
+
+        if self.prelude {
+            prelude::define(&mut interpreter);
+        }
+
+        for item in &self.modules {
+            interpreter.define(item);
+        }
+
+        for input in &self.inputs {
+            for (item, _, _) in &input.items {
+                interpreter.define(item);
+            }
+        }
+
+        let expr = match *self.inputs[test.input].items[test.item].0 {
+            Item::Test(_, ref expr) => expr,
+            _ => unreachable!(),
+        };
+
+        let message = match interpreter.eval_top_level(expr) {
+            Ok(ref value) if value.is_true() => return Ok(()),
+            Ok(value) => format!("evaluated to {}", value),
+            Err(e) => e.to_string(),
+        };
+
+        // Point at the assertion that failed, or else at the whole expression
+        let (input, start, end) = match interpreter.failed_assertion().and_then(|site| self.assertion(test, site)) {
+            Some(span) => span,
+            None => {
+                let (_, start, end) = self.inputs[test.input].items[test.item];
+                let tokens = self.item_tokens(test.input, start, end);
+
+                // After `test` and the name
+                (test.input, tokens[2].0, end)
+            }
+        };
+
+        let input = &self.inputs[input];
+        let lines = line_starts(&input.source);
+        let line = line_of(&lines, start);
+        let column = input.source[lines[line]..start].chars().count() + 1;
+
+        Err(Failure {
+            message,
+            location: format!("{}:{}:{}", input.name, line + 1, column),
+            source: input.source[start..end].to_string(),
+        })
+    }
+
+    /// Run the tests whose name contains any of `filters`, or every test if
+    /// there are none, writing which passed and why the others failed to `out`
+    /// in the style of `cargo test`. Returns whether they all passed.
+    ///
+    /// ```
+    /// use kaleidescope_rs::modules::Loader;
+    /// use kaleidescope_rs::testing::Suite;
+    ///
+    /// let inputs = vec![("main.k".to_string(), "def sq(x) x * x\ntest small sq(2) == 4\ntest large sq(3) == 10".to_string())];
+    /// let suite = Suite::load(inputs, true, &mut Loader::new(Vec::new())).unwrap();
+    ///
+    /// let mut out: Vec<u8> = Vec::new();
+    /// assert_eq!(suite.report(&["small".to_string()], &mut out).unwrap(), true);
+    /// assert!(String::from_utf8(out).unwrap().ends_with("test result: ok. 1 passed; 0 failed; 1 filtered out\n"));
+    ///
+    /// let mut out: Vec<u8> = Vec::new();
+    /// assert_eq!(suite.report(&[], &mut out).unwrap(), false);
+    /// assert!(String::from_utf8(out).unwrap().contains("---- large at main.k:3:12 ----\nsq(3) == 10\nevaluated to 0\n"));
+    /// ```
+    pub fn report(&self, filters: &[String], out: &mut dyn Write) -> io::Result<bool> {
+        let (tests, filtered): (Vec<Test>, Vec<Test>) = self.tests().into_iter()
+            .partition(|test| filters.is_empty() || filters.iter().any(|filter| test.name.contains(filter.as_str())));
+
+        writeln!(out, "running {} test{}", tests.len(), if tests.len() == 1 { "" } else { "s" })?;
+
+        let mut failures: Vec<(&Test, Failure)> = Vec::new();
+
+        for test in &tests {
+            match self.run(test) {
+                Ok(()) => writeln!(out, "test {} ... ok", test.name)?,
+                Err(failure) => {
+                    writeln!(out, "test {} ... FAILED", test.name)?;
+                    failures.push((test, failure));
+                }
+            }
+        }
+
+        if !failures.is_empty() {
+            writeln!(out, "\nfailures:")?;
+
+            for &(test, ref failure) in &failures {
+                writeln!(out, "\n---- {} at {} ----", test.name, failure.location)?;
+                writeln!(out, "{}", failure.source)?;
+                writeln!(out, "{}", failure.message)?;
+            }
+        }
+
+        writeln!(
+            out,
+            "\ntest result: {}. {} passed; {} failed; {} filtered out",
+            if failures.is_empty() { "ok" } else { "FAILED" },
+            tests.len() - failures.len(),
+            failures.len(),
+            filtered.len()
+        )?;
+
+        Ok(failures.is_empty())
+    }
+
+    /// The input and byte range of the assertion call at `site`, in `test`
+    /// or the last definition of the function it names.
+    fn assertion(&self, test: &Test, site: &AssertionSite) -> Option<(usize, usize, usize)> {
+        let (i, n) = match site.function {
+            None => (test.input, test.item),
+            Some(ref function) => self.definition(function)?,
+        };
+
+        let (_, start, end) = self.inputs[i].items[n];
+        let tokens = self.item_tokens(i, start, end);
+        let (start, end) = assertion_spans(&tokens).into_iter().nth(site.index)?;
+        Some((i, start, end))
+    }
+
+    /// The input and item of the last definition of the function `name`,
+    /// which is the one that runs.
+    fn definition(&self, name: &str) -> Option<(usize, usize)> {
+        let mut found = None;
+
+        for (i, input) in self.inputs.iter().enumerate() {
+            for (n, (item, _, _)) in input.items.iter().enumerate() {
+                if let Item::Function(_, _, ref proto, _) = **item {
+                    if proto.0 == name {
+                        found = Some((i, n));
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    fn item_tokens(&self, input: usize, start: usize, end: usize) -> Vec<(usize, usize, Token)> {
+        spanned_tokens(&self.inputs[input].source).into_iter()
+            .filter(|&(token_start, token_end, _)| token_start >= start && token_end <= end)
+            .collect()
+    }
+}
+
+/// The byte ranges of the assertion calls among `tokens`, from the name to the closing `)`.
+fn assertion_spans(tokens: &[(usize, usize, Token)]) -> Vec<(usize, usize)> {
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+
+    for (n, &(start, _, ref tok)) in tokens.iter().enumerate() {
+        let called = matches!(tokens.get(n + 1), Some(&(_, _, Token::OpenDelim(Delim::Paren))));
+
+        match *tok {
+            Token::Ident(ref name) if called && interp::is_assertion(name) => {}
+            _ => continue,
+        }
+
+        let mut depth = 0;
+
+        for &(_, end, ref tok) in &tokens[n + 1..] {
+            match *tok {
+                Token::OpenDelim(Delim::Paren) => depth += 1,
+                Token::CloseDelim(Delim::Paren) => depth -= 1,
+                _ => {}
+            }
+
+            if depth == 0 {
+                spans.push((start, end));
+                break
+            }
+        }
+    }
+
+    spans
+}
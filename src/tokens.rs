@@ -1,5 +1,7 @@
 use std::fmt;
 
+use lexer::quote;
+
 #[derive(Debug, PartialEq)]
 pub enum Delim {
     Paren,
@@ -31,14 +33,22 @@ pub enum Token {
     Eof,
     Def,
     Extern,
-    Ident(String),
+    Pure,
+    If,
+    Then,
+    Else,
+    Test,
+    Import,
+    Ident(String),  // possibly qualified, as in `l.f`
     Number(f64),
+    Str(String),    // text between the quotes, with escapes replaced
 
     // Structural tokens
     OpenDelim(Delim),
     CloseDelim(Delim),
     Comma,
     Semicolon,
+    At,
 
     // Expression tokens
     Eq,
@@ -57,7 +67,8 @@ pub enum Token {
 
     // Useless tokens
     Whitespace,
-    Comment,
+    Comment(String),     // text after the `#`
+    DocComment(String),  // text after the `##`, documenting the item below it
 }
 
 impl fmt::Display for Token {
@@ -66,8 +77,15 @@ impl fmt::Display for Token {
             Token::Eof => write!(f, "Token < End-of-file >"),
             Token::Def => write!(f, "Token < Function Def >"),
             Token::Extern => write!(f, "Token < Extern >"),
+            Token::Pure => write!(f, "Token < Pure >"),
+            Token::If => write!(f, "Token < If >"),
+            Token::Then => write!(f, "Token < Then >"),
+            Token::Else => write!(f, "Token < Else >"),
+            Token::Test => write!(f, "Token < Test >"),
+            Token::Import => write!(f, "Token < Import >"),
             Token::Ident(ref s) => write!(f, "Token < Identifier: `{}` >", s),
             Token::Number(ref val) => write!(f, "Token < Number: `{}` >", val),
+            Token::Str(ref text) => write!(f, "Token < String: `{}` >", quote(text)),
             Token::OpenDelim(_) => write!(f, "Token < Open Delimiter: Paren `(` >"),
             Token::CloseDelim(_) => write!(f, "Token < Closing Delimiter: Paren `)` >"),
             Token::Eq => write!(f, "Token < Eq `=` >"),
@@ -83,9 +101,11 @@ impl fmt::Display for Token {
             Token::BinOp(ref op) => write!(f, "Token < Binop: {} >", op),
             Token::BinOpEq(ref op) => write!(f, "Token < BinopEq: {} >", op),
             Token::Whitespace => write!(f, "Token < Whitespace >"),
-            Token::Comment => write!(f, "Token < Comment >"),
+            Token::Comment(ref text) => write!(f, "Token < Comment: `#{}` >", text),
+            Token::DocComment(ref text) => write!(f, "Token < Doc Comment: `##{}` >", text),
             Token::Semicolon => write!(f, "Token < Semicolon >"),
             Token::Comma => write!(f, "Token < Comma >"),
+            Token::At => write!(f, "Token < At `@` >"),
         }
     }
 }
@@ -0,0 +1,101 @@
+use std::io::prelude::*;
+
+use ast::*;
+use interp::EvalError;
+use precedence::Op;
+use value::Value;
+
+/// What a trace records.
+#[derive(Debug, Clone, Default)]
+pub struct TraceOptions {
+    /// Also record each binary operation with its operands.
+    pub binary: bool,
+
+    /// Only record calls of these functions, and the operations in their
+    /// bodies, unless it is empty.
+    pub functions: Vec<String>,
+
+    /// Stop recording after this many events.
+    pub limit: Option<usize>,
+}
+
+/// A log of the calls a program makes, indented by how deep they are.
+///
+/// Calls answered from a memoization cache run no body, so they are not recorded.
+pub struct Trace {
+    options: TraceOptions,
+    out: Box<dyn Write>,
+    events: usize,
+
+    // Whether each call in progress is recorded
+    stack: Vec<bool>,
+}
+
+impl Trace {
+    pub fn new(options: TraceOptions, out: Box<dyn Write>) -> Trace {
+        Trace { options, out, events: 0, stack: Vec::new() }
+    }
+
+    pub fn enter(&mut self, proto: &FuncProto, args: &[Value]) {
+        let traced = self.options.functions.is_empty() || self.options.functions.contains(&proto.0);
+
+        if traced {
+            let args: Vec<String> = proto.1.iter().zip(args)
+                .map(|(param, arg)| format!("{} = {}", param, arg))
+                .collect();
+
+            self.event(&format!("-> {}({})", proto.0, args.join(", ")));
+        }
+
+        self.stack.push(traced);
+    }
+
+    pub fn exit(&mut self, name: &str, result: &Result<Value, EvalError>) {
+        if self.stack.pop() == Some(true) {
+            match *result {
+                Ok(ref value) => self.event_at(self.stack.len(), &format!("<- {} = {}", name, value)),
+                Err(_) => self.event_at(self.stack.len(), &format!("<- {} failed", name)),
+            }
+        }
+    }
+
+    /// Record `lhs op rhs`, where `rhs` is `None` if `&&` or `||` didn't need it.
+    pub fn binary(&mut self, op: BinOp, lhs: &Value, rhs: Option<&Value>, value: &Value) {
+        if !self.options.binary {
+            return
+        }
+
+        let traced = match self.stack.last() {
+            Some(&traced) => traced,
+            None => self.options.functions.is_empty(),
+        };
+
+        if traced {
+            let rhs = rhs.map_or("_".to_string(), |rhs| rhs.to_string());
+            self.event(&format!("{} {} {} = {}", lhs, Op::from_ast_binop(op).symbol(), rhs, value));
+        }
+    }
+
+    /// Record an event inside the innermost call.
+    fn event(&mut self, text: &str) {
+        let depth = self.stack.len();
+        self.event_at(depth, text);
+    }
+
+    fn event_at(&mut self, depth: usize, text: &str) {
+        match self.options.limit {
+            Some(limit) if self.events > limit => return,
+            Some(limit) if self.events == limit => {
+                let _ = writeln!(self.out, "... stopped tracing after {} events", limit);
+                self.events += 1;
+                return
+            }
+            _ => {}
+        }
+
+        self.events += 1;
+
+        // The trace is a diagnostic, so a closed output doesn't stop the program
+        let _ = writeln!(self.out, "{:indent$}{}", "", text, indent = depth * 2);
+    }
+}
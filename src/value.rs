@@ -0,0 +1,87 @@
+//! The values programs compute with: numbers, as in the tutorial, and strings.
+
+use std::fmt;
+use std::rc::Rc;
+
+use lexer::quote;
+
+/// A value of an expression.
+///
+/// Strings are immutable, so copies of one share its text.
+///
+/// ```
+/// use kaleidescope_rs::value::Value;
+///
+/// assert_eq!(Value::from(2.5).to_string(), "2.5");
+/// assert_eq!(Value::from("tab\t").to_string(), "\"tab\\t\"");
+/// assert!(Value::from("").number().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Str(Rc<str>),
+}
+
+impl Value {
+    /// The number this value is, or an error naming the string it is instead.
+    pub fn number(&self) -> Result<f64, String> {
+        match *self {
+            Value::Number(val) => Ok(val),
+            Value::Str(ref text) => Err(format!("expected a number, found the string {}", quote(text))),
+        }
+    }
+
+    /// What kind of value this is, for error messages.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+        }
+    }
+
+    /// Whether a condition with this value holds: numbers other than `0`, and
+    /// strings other than `""`.
+    pub fn is_true(&self) -> bool {
+        match *self {
+            Value::Number(val) => val != 0.0,
+            Value::Str(ref text) => !text.is_empty(),
+        }
+    }
+
+    /// The text of the value as `print` writes it, which is a string's own
+    /// text rather than its literal.
+    pub fn text(&self) -> String {
+        match *self {
+            Value::Number(val) => val.to_string(),
+            Value::Str(ref text) => text.to_string(),
+        }
+    }
+}
+
+/// Numbers as Rust prints an `f64`, and strings as literals that lex back to them.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Number(val) => write!(f, "{}", val),
+            Value::Str(ref text) => write!(f, "{}", quote(text)),
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(val: f64) -> Value {
+        Value::Number(val)
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(text: &'a str) -> Value {
+        Value::Str(text.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Value {
+        Value::Str(text.into())
+    }
+}
@@ -0,0 +1,217 @@
+use std::fmt::Write;
+
+use ast::*;
+use format::unop_symbol;
+use lexer::quote;
+use precedence::Op;
+
+/// Render `file` as a Graphviz digraph with one node per ast node.
+///
+/// Operators, literals and names label their own nodes, and the children of
+/// each node are laid out left to right in source order, so the shape of the
+/// graph is exactly how the parser grouped the source.
+pub fn to_dot(file: &File) -> String {
+    let mut graph = DotGraph { out: String::new(), next: 0 };
+
+    writeln!(graph.out, "digraph ast {{").unwrap();
+    writeln!(graph.out, "    ordering=out;").unwrap();
+
+    let root = graph.node("file", "shape=box");
+
+    for item in &file.0 {
+        let node = graph.item(item);
+        graph.edge(root, node, None);
+    }
+
+    writeln!(graph.out, "}}").unwrap();
+    graph.out
+}
+
+struct DotGraph {
+    out: String,
+    next: usize,
+}
+
+impl DotGraph {
+    fn node(&mut self, label: &str, attrs: &str) -> usize {
+        let id = self.next;
+        self.next += 1;
+
+        if attrs.is_empty() {
+            writeln!(self.out, "    n{} [label={:?}];", id, label).unwrap();
+        } else {
+            writeln!(self.out, "    n{} [label={:?}, {}];", id, label, attrs).unwrap();
+        }
+
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: usize, label: Option<&str>) {
+        match label {
+            Some(label) => writeln!(self.out, "    n{} -> n{} [label={:?}];", from, to, label).unwrap(),
+            None => writeln!(self.out, "    n{} -> n{};", from, to).unwrap(),
+        }
+    }
+
+    fn item(&mut self, item: &Item) -> usize {
+        match *item {
+            Item::Function(_, ref attrs, ref proto, ref body) => {
+                let mut label = String::new();
+
+                for attr in attrs {
+                    label.push_str(&format!("{} ", attr));
+                }
+
+                label.push_str(&format!("def {}", proto));
+
+                let node = self.node(&label, "shape=box");
+                let body = self.expr(body);
+                self.edge(node, body, None);
+                node
+            }
+
+            Item::Extern(_, Purity::Pure, ref proto) => self.node(&format!("pure extern {}", proto), "shape=box"),
+            Item::Extern(_, Purity::Effectful, ref proto) => self.node(&format!("extern {}", proto), "shape=box"),
+            Item::Expr(ref expr) => self.expr(expr),
+
+            Item::Test(ref name, ref expr) => {
+                let node = self.node(&format!("test {}", name), "shape=box");
+                let expr = self.expr(expr);
+                self.edge(node, expr, None);
+                node
+            }
+
+            Item::Import(ref path, None) => self.node(&format!("import {}", path), "shape=box"),
+            Item::Import(ref path, Some(ref alias)) => self.node(&format!("import {} as {}", path, alias), "shape=box"),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> usize {
+        match *expr {
+            Expr::Number(val) => self.node(&val.to_string(), "shape=plaintext"),
+            Expr::Str(ref text) => self.node(&quote(text), "shape=plaintext"),
+            Expr::Name(ref name) => self.node(name, "shape=plaintext"),
+
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                let node = self.node(Op::from_ast_binop(op).symbol(), "shape=circle");
+                self.children(node, &[(lhs, None), (rhs, None)]);
+                node
+            }
+
+            Expr::Unary(op, ref operand) => {
+                let node = self.node(unop_symbol(op), "shape=circle");
+                self.children(node, &[(operand, None)]);
+                node
+            }
+
+            Expr::Call(ref name, ref args) => {
+                let node = self.node(&format!("{}()", name), "");
+
+                for arg in args {
+                    let arg = self.expr(arg);
+                    self.edge(node, arg, None);
+                }
+
+                node
+            }
+
+            // Kept so the graph shows where the source grouped explicitly
+            Expr::Paren(ref inner) => {
+                let node = self.node("( )", "shape=plaintext");
+                self.children(node, &[(inner, None)]);
+                node
+            }
+
+            Expr::If(ref cond, ref then, ref otherwise) => {
+                let node = self.node("if", "shape=diamond");
+                self.children(node, &[(cond, Some("cond")), (then, Some("then")), (otherwise, Some("else"))]);
+                node
+            }
+
+            Expr::Let(ref name, ref value, ref body) => {
+                let node = self.node(&format!("let {}", name), "");
+                self.children(node, &[(value, Some("value")), (body, Some("body"))]);
+                node
+            }
+        }
+    }
+
+    fn children(&mut self, node: usize, children: &[(&Expr, Option<&str>)]) {
+        for &(child, label) in children {
+            let child = self.expr(child);
+            self.edge(node, child, label);
+        }
+    }
+}
+
+/// Render `file` as Lisp-style s-expressions, one item per line.
+///
+/// Every operator and call is wrapped in its own parentheses, so source
+/// parentheses are left out: `1 + 2 * 3` prints as `(+ 1 (* 2 3))`.
+pub fn to_sexpr(file: &File) -> String {
+    let mut out = String::new();
+
+    for item in &file.0 {
+        writeln!(out, "{}", item_sexpr(item)).unwrap();
+    }
+
+    out
+}
+
+fn item_sexpr(item: &Item) -> String {
+    match *item {
+        Item::Function(_, ref attrs, ref proto, ref body) => {
+            let mut out = String::from("(def ");
+
+            for attr in attrs {
+                out.push_str(&format!("{} ", attr));
+            }
+
+            out.push_str(&format!("{} {})", proto_sexpr(proto), sexpr(body)));
+            out
+        }
+
+        Item::Extern(_, Purity::Pure, ref proto) => format!("(pure extern {})", proto_sexpr(proto)),
+        Item::Extern(_, Purity::Effectful, ref proto) => format!("(extern {})", proto_sexpr(proto)),
+        Item::Expr(ref expr) => sexpr(expr),
+        Item::Test(ref name, ref expr) => format!("(test {} {})", name, sexpr(expr)),
+        Item::Import(ref path, None) => format!("(import {})", path),
+        Item::Import(ref path, Some(ref alias)) => format!("(import {} {})", path, alias),
+    }
+}
+
+fn proto_sexpr(proto: &FuncProto) -> String {
+    let mut words = vec![proto.0.clone()];
+    words.extend(proto.1.iter().cloned());
+    format!("({})", words.join(" "))
+}
+
+fn sexpr(expr: &Expr) -> String {
+    match *expr {
+        Expr::Number(val) => val.to_string(),
+        Expr::Str(ref text) => quote(text),
+        Expr::Name(ref name) => name.clone(),
+
+        Expr::Binary(op, ref lhs, ref rhs) => {
+            format!("({} {} {})", Op::from_ast_binop(op).symbol(), sexpr(lhs), sexpr(rhs))
+        }
+
+        Expr::Unary(op, ref operand) => format!("({} {})", unop_symbol(op), sexpr(operand)),
+
+        Expr::Call(ref name, ref args) => {
+            let mut words = vec![name.clone()];
+            words.extend(args.iter().map(|arg| sexpr(arg)));
+            format!("({})", words.join(" "))
+        }
+
+        Expr::Paren(ref inner) => sexpr(inner),
+
+        Expr::If(ref cond, ref then, ref otherwise) => {
+            format!("(if {} {} {})", sexpr(cond), sexpr(then), sexpr(otherwise))
+        }
+
+        Expr::Let(ref name, ref value, ref body) => {
+            format!("(let ({} {}) {})", name, sexpr(value), sexpr(body))
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn write(name: &str, contents: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("kaleidescope-ast-{}", name));
+    File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+    path
+}
+
+fn kaleidescope(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs")).args(args).output().unwrap()
+}
+
+fn stdout(output: Output) -> String {
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Serialize `source`, read the result back and serialize it again, checking
+/// that nothing changed, and return the serialized ast.
+fn round_trip(name: &str, source: &str, command: &[&str]) -> String {
+    let source = write(&format!("{}.k", name), source);
+
+    let mut args = command.to_vec();
+    args.push(source.to_str().unwrap());
+    let json = stdout(kaleidescope(&args));
+
+    let ast = write(&format!("{}.json", name), &json);
+    let again = stdout(kaleidescope(&["build", "--from-ast", ast.to_str().unwrap()]));
+
+    assert_eq!(json, again);
+    json
+}
+
+#[test]
+fn every_node_round_trips() {
+    let source = "\
+        @memo @noinline @export def f(a, b) if !(a < b) && b >= 2 || a == 1 then -a % b else (a * b / 2)\n\
+        pure extern sin(x)\n\
+        extern putchard(c)\n\
+        f(1, 2) - sin(3) + putchard(65) + (4 != 5) + (6 > 7) + (8 <= 9)\n";
+
+    round_trip("every-node", source, &["parse"]);
+}
+
+#[test]
+fn optimized_programs_round_trip() {
+    let source = "pure extern sin(x)\ndef sq(x) x * x\ndef f(x) sq(sin(x)) + sq(sin(x))\nf(2)\n";
+
+    let json = round_trip("optimized", source, &["build", "-O2"]);
+    assert!(json.contains("\"kind\": \"let\""));
+}
+
+#[test]
+fn numbers_round_trip_exactly() {
+    let source = "0.1 + 0.30000000000000004 + 123456789.12345679 + 0.000001 + 17976931348623157000000000000000000000\n";
+
+    round_trip("numbers", source, &["parse"]);
+}
+
+#[test]
+fn the_largest_numbers_round_trip_and_larger_ones_are_rejected() {
+    let largest = format!("{}\n", "9".repeat(308));
+    let json = round_trip("largest", &largest, &["parse"]);
+    assert!(!json.contains("null"));
+
+    let source = write("too-large.k", &format!("{}\n", "9".repeat(400)));
+    let output = kaleidescope(&["parse", source.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is too large"));
+}
+
+#[test]
+fn deeply_nested_expressions_round_trip() {
+    let source = format!("1{}\n", " + 1".repeat(1000));
+
+    round_trip("deep", &source, &["parse"]);
+}
+
+#[test]
+fn programs_run_from_their_ast() {
+    let source = write("run.k", "def fib(x) if x < 3 then 1 else fib(x - 1) + fib(x - 2)\nfib(10)\n");
+    let json = stdout(kaleidescope(&["parse", source.to_str().unwrap()]));
+    let ast = write("run.json", &json);
+
+    assert_eq!(stdout(kaleidescope(&["run", "--from-ast", ast.to_str().unwrap()])), "55\n");
+}
+
+#[test]
+fn other_versions_are_rejected() {
+    let ast = write("version.json", "{ \"version\": 0, \"items\": [] }");
+    let output = kaleidescope(&["run", "--from-ast", ast.to_str().unwrap()]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unsupported ast version 0"));
+}
+
+#[test]
+fn documents_nested_too_deeply_are_rejected() {
+    let json = format!("{{ \"version\": 1, \"items\": {}{} }}", "[".repeat(100_000), "]".repeat(100_000));
+    let ast = write("nested.json", &json);
+    let output = kaleidescope(&["run", "--from-ast", ast.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("the ast nests more than 2048 deep"));
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use common::kaleidescope;
+
+/// Build `source` with the given flags and return what was written.
+fn build(source: &str, flags: &[&str]) -> String {
+    let output = kaleidescope("build", &[&["--no-prelude"], flags].concat(), &[source]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn dead_functions_are_eliminated() {
+    let source = "@noinline def helper(x) x + 1\n\
+                  @noinline def entry(x) helper(x) * 2\n\
+                  def unused(x) helper(x)\n\
+                  entry(1)\n";
+
+    assert_eq!(
+        build(source, &["-O1", "--emit", "sexpr"]),
+        "(def @noinline (helper x) (+ x 1))\n(def @noinline (entry x) (* (helper x) 2))\n(entry 1)\n"
+    );
+}
+
+#[test]
+fn tests_and_exports_keep_functions_alive() {
+    let source = "@export def api(x) x\n@noinline def checked(x) x\ntest t checked(1)\ndef unused(x) x\n";
+
+    assert_eq!(
+        build(source, &["-O1", "--emit", "sexpr"]),
+        "(def @export (api x) x)\n(def @noinline (checked x) x)\n(test t (checked 1))\n"
+    );
+}
+
+#[test]
+fn mutually_recursive_functions_are_kept_together() {
+    let source = "def even(n) if n == 0 then 1 else odd(n - 1)\n\
+                  def odd(n) if n == 0 then 0 else even(n - 1)\n\
+                  even(4)\n";
+
+    assert_eq!(
+        build(source, &["-O3", "--emit", "sexpr"]),
+        "(def (even n) (if (== n 0) 1 (odd (- n 1))))\n(def (odd n) (if (== n 0) 0 (even (- n 1))))\n(even 4)\n"
+    );
+}
+
+#[test]
+fn dot_output_clusters_recursion_and_greys_out_dead_functions() {
+    let source = "extern sin(x)\ndef f(x) sin(x)\ndef g(x) g(x)\nf(1)\n";
+
+    assert_eq!(
+        build(source, &["--emit", "callgraph-dot"]),
+        "digraph callgraph {\n    \
+         \"<top level>\" [shape=house];\n    \
+         \"sin\" [shape=box];\n    \
+         \"f\";\n    \
+         subgraph cluster_2 {\n        \
+         label=\"recursive\";\n        \
+         style=dashed;\n        \
+         \"g\" [color=grey, fontcolor=grey];\n    \
+         }\n    \
+         \"<top level>\" -> \"f\";\n    \
+         \"f\" -> \"sin\";\n    \
+         \"g\" -> \"g\";\n\
+         }\n"
+    );
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use std::process::Output;
+
+use common::kaleidescope;
+
+fn check(source: &str) -> Output {
+    kaleidescope("check", &["--no-prelude"], &[source])
+}
+
+/// The description `check` gives of each function in `source`, which must be valid.
+fn summary(source: &str) -> String {
+    let output = check(source);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn functions_calling_only_pure_code_are_pure() {
+    assert_eq!(
+        summary("pure extern sin(x)\ndef f(x) sin(x) * 2\ndef g(x) f(x) + 1\n"),
+        "pure extern sin(x): pure\ndef f(x): pure\ndef g(x): pure\n"
+    );
+}
+
+#[test]
+fn effects_are_traced_to_the_callee_that_has_them() {
+    assert_eq!(
+        summary("extern putchard(c)\ndef a(x) putchard(x)\ndef b(x) a(x) + 1\n"),
+        "extern putchard(c): effectful\ndef a(x): effectful, calls `putchard`\ndef b(x): effectful, calls `a`\n"
+    );
+}
+
+#[test]
+fn recursive_components_share_their_purity() {
+    let source = "extern putchard(c)\n\
+                  def ping(n) if n then pong(n - 1) else 0\n\
+                  def pong(n) ping(n)\n\
+                  def loud(n) if n then quiet(n - 1) else putchard(n)\n\
+                  def quiet(n) loud(n)\n";
+
+    assert_eq!(
+        summary(source),
+        "extern putchard(c): effectful\n\
+         def ping(n): pure\n\
+         def pong(n): pure\n\
+         def loud(n): effectful, calls `putchard`\n\
+         def quiet(n): effectful, calls `putchard`\n"
+    );
+}
+
+#[test]
+fn errors_are_reported_and_fail_the_check() {
+    let output = check("def f(x) y\ndef g(x, x) g(1)\nnope(1)\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(
+        stderr,
+        "error: parameter `x` of `g` is declared more than once\n\
+         error: unknown variable `y`\n\
+         error: `g` takes 2 arguments, but 1 were given\n\
+         error: call to undefined function `nope`\n"
+    );
+}
+
+#[test]
+fn memoizing_effectful_functions_is_a_warning() {
+    let output = check("extern printd(x)\n@memo def log(x) printd(x)\n");
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "warning: `log` is effectful, so memoizing it skips its effects when a result is cached\n"
+    );
+}
@@ -0,0 +1,87 @@
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn write(name: &str, contents: &str) -> String {
+    let path: PathBuf = env::temp_dir().join(format!("kaleidescope-cli-{}", name));
+    File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+fn kaleidescope(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn inputs_are_concatenated_in_order() {
+    let defs = write("defs.k", "def double(x) x * 2\ndouble(1)\n");
+    let more = write("more.k", "-double(2)\n");
+
+    let output = kaleidescope(&["run", &defs, "-", &more, "-e", "double(4)"], "double(3)\n");
+
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "2\n6\n-4\n8\n");
+}
+
+#[test]
+fn run_is_the_default_command() {
+    let output = kaleidescope(&["-e", "def f(x) x + 1", "-e", "f(1)"], "");
+
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "2\n");
+}
+
+#[test]
+fn standard_input_is_read_without_other_inputs() {
+    let output = kaleidescope(&["parse", "--emit", "sexpr"], "1 + 2 * 3\n");
+
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "(+ 1 (* 2 3))\n");
+}
+
+#[test]
+fn exit_codes_distinguish_failures() {
+    let status = |args: &[&str]| kaleidescope(args, "").status.code();
+
+    assert_eq!(status(&["run", "--bogus"]), Some(2));
+    assert_eq!(status(&["parse", "--emit", "bogus", "-e", "1"]), Some(2));
+    assert_eq!(status(&["run", "/nonexistent/kaleidescope.k"]), Some(3));
+    assert_eq!(status(&["run", "-e", "def (x) x"]), Some(4));
+    assert_eq!(status(&["run", "-e", "."]), Some(4));
+    assert_eq!(status(&["tokens", "-e", "1 ."]), Some(4));
+    assert_eq!(status(&["check", "-e", "def f(x) y"]), Some(4));
+    assert_eq!(status(&["run", "-e", "g(1)"]), Some(5));
+}
+
+#[test]
+fn syntax_errors_name_their_input() {
+    let output = kaleidescope(&["run", "-e", "1", "-e", "def (x) x"], "");
+
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("error: -e #2: "));
+}
+
+#[test]
+fn build_writes_the_optimized_ast() {
+    let source = write("build.k", "def sq(x) x * x\nsq(3)\n");
+    let ast = write("build.json", "");
+
+    assert!(kaleidescope(&["build", "-O2", "-o", &ast, &source], "").status.success());
+
+    let output = kaleidescope(&["run", "--from-ast", &ast], "");
+    assert_eq!(stdout(&output), "9\n");
+}
@@ -0,0 +1,14 @@
+use std::process::{Command, Output};
+
+/// Run `subcommand` with `flags` on the program made of `sources`, each
+/// given with `-e`.
+pub fn kaleidescope(subcommand: &str, flags: &[&str], sources: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs"));
+    command.arg(subcommand).args(flags);
+
+    for source in sources {
+        command.arg("-e").arg(source);
+    }
+
+    command.output().unwrap()
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use common::kaleidescope;
+
+/// Run `source` with the given flags and return what it printed.
+fn run(source: &str, flags: &[&str]) -> String {
+    let output = kaleidescope("run", flags, &[source]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// The exit status, output and errors of running the program made of
+/// `sources`, which must be the same with `-O0` and `-O1`.
+fn unchanged_by_optimization(sources: &[&str]) -> (Option<i32>, String, String) {
+    let outcomes: Vec<(Option<i32>, String, String)> = ["-O0", "-O1"].iter()
+        .map(|&level| {
+            let output = kaleidescope("run", &[level], sources);
+            (output.status.code(), String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap())
+        })
+        .collect();
+
+    assert_eq!(outcomes[0], outcomes[1]);
+    outcomes[0].clone()
+}
+
+/// How many times `putchard(65)` ran, i.e. how many `A`s were printed.
+fn evaluations(source: &str, flags: &[&str]) -> usize {
+    run(source, flags).matches('A').count()
+}
+
+// `putchard` is declared pure so the optimizer may merge calls, while still
+// letting us observe each evaluation.
+const COUNTER: &str = "pure extern putchard(c)\n";
+
+#[test]
+fn repeated_pure_calls_are_evaluated_once() {
+    let source = format!("{}def f(x) putchard(x) * 2 + putchard(x) * 3\nf(65)\n", COUNTER);
+
+    assert_eq!(evaluations(&source, &[]), 2);
+    assert_eq!(evaluations(&source, &["-O1"]), 1);
+}
+
+#[test]
+fn commutative_operands_are_identified() {
+    let source = format!("{}def f(a, b) putchard(a * b) + putchard(b * a)\nf(13, 5)\n", COUNTER);
+
+    assert_eq!(evaluations(&source, &["-O1"]), 1);
+}
+
+#[test]
+fn joined_strings_are_not_commuted() {
+    let source = "def f(a, b) (a + b) + (b + a)\nf(\"x\", \"y\")\nf(1, 2)\n";
+
+    for flags in &[&["-O0"][..], &["-O1"][..]] {
+        assert_eq!(run(source, flags), "\"xyyx\"\n6\n");
+    }
+}
+
+#[test]
+fn nested_repeats_are_evaluated_once() {
+    let source = format!(
+        "{}def f(x) putchard(putchard(x) * 0 + 65) + putchard(putchard(x) * 0 + 65) + putchard(x)\nf(65)\n",
+        COUNTER
+    );
+
+    assert_eq!(evaluations(&source, &[]), 5);
+    assert_eq!(evaluations(&source, &["-O1"]), 2);
+}
+
+#[test]
+fn effectful_calls_are_not_merged() {
+    let source = "extern putchard(c)\ndef f(x) putchard(x) + putchard(x)\nf(65)\n";
+
+    assert_eq!(evaluations(source, &["-O1"]), 2);
+}
+
+#[test]
+fn conditional_occurrences_are_not_hoisted() {
+    let source = format!(
+        "{}def f(x) (x && putchard(65) + 1) + (x && putchard(65) + 2)\nf(0)\nf(1)\n",
+        COUNTER
+    );
+
+    assert_eq!(run(&source, &["-O1"]), "0\nAA2\n");
+}
+
+#[test]
+fn conditional_occurrences_reuse_unconditional_ones() {
+    let source = format!("{}def f(x) putchard(x) + (1 && putchard(x))\nf(65)\n", COUNTER);
+
+    assert_eq!(evaluations(&source, &[]), 2);
+    assert_eq!(evaluations(&source, &["-O1"]), 1);
+}
+
+#[test]
+fn failures_are_not_moved_before_effects() {
+    let (status, stdout, stderr) = unchanged_by_optimization(&["def g(a) print(\"hi \") + num(a) + num(a)", "g(\"z\")"]);
+    assert_eq!((status, stdout.as_str()), (Some(5), "hi "));
+    assert_eq!(stderr, "error: `num` failed: \"z\" is not a number\n");
+
+    let (status, stdout, _) = unchanged_by_optimization(&["def h(a) print(\"hi \") + (a - 1) * (a - 1)", "h(\"z\")"]);
+    assert_eq!((status, stdout.as_str()), (Some(5), "hi "));
+
+    let (status, stdout, _) = unchanged_by_optimization(&["def h(a) print(\"hi \") + (a - 1) * (a - 1)", "h(4)"]);
+    assert_eq!((status, stdout.as_str()), (Some(0), "hi 9\n"));
+}
+
+#[test]
+fn failures_are_not_reordered() {
+    let (status, _, stderr) = unchanged_by_optimization(&["def f(a, b) num(b) + num(a) + num(a)", "f(\"x\", \"y\")"]);
+    assert_eq!(status, Some(5));
+    assert_eq!(stderr, "error: `num` failed: \"y\" is not a number\n");
+}
+
+#[test]
+fn values_that_can_fail_are_still_shared_when_nothing_comes_before_them() {
+    let output = kaleidescope("build", &["--no-prelude", "--emit", "sexpr", "-O1"], &["@noinline def h(a) (a - 1) * (a - 1)", "h(4)"]);
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "(def @noinline (h a) (let (%0 (- a 1)) (* %0 %0)))\n(h 4)\n");
+}
@@ -0,0 +1,176 @@
+extern crate serde_json;
+
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::process::{Command, Stdio};
+
+use serde_json::Value;
+
+const PROGRAM: &str = "\
+def fib(x)
+    if x < 3 then
+        1
+    else
+        fib(x - 1) + fib(x - 2)
+
+def twice(y) fib(y) * 2
+
+extern printd(x)
+
+printd(7)
+twice(4)
+";
+
+/// Send `requests` to a debug adapter for `PROGRAM`, and read everything it
+/// sends back. A `"SELF"` string in a request stands for the program's path.
+fn session(name: &str, requests: &[Value]) -> Vec<Value> {
+    let path = env::temp_dir().join(format!("kaleidescope-dap-{}.k", name));
+    File::create(&path).unwrap().write_all(PROGRAM.as_bytes()).unwrap();
+
+    let mut input = String::new();
+    let launch = json(&format!(r#"{{"command": "launch", "arguments": {{"program": {:?}}}}}"#, path.to_str().unwrap()));
+
+    for (seq, request) in [json(r#"{"command": "initialize"}"#), launch].iter().chain(requests).enumerate() {
+        let mut request = request.clone();
+        request["seq"] = Value::from(seq + 1);
+        request["type"] = Value::from("request");
+
+        let body = request.to_string().replace("\"SELF\"", &Value::from(path.to_str().unwrap()).to_string());
+        input.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    }
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs"))
+        .arg("dap")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+
+    let mut stdout = String::from_utf8(output.stdout).unwrap();
+    let mut messages: Vec<Value> = Vec::new();
+
+    while let Some(n) = stdout.find("\r\n\r\n") {
+        let length: usize = stdout["Content-Length: ".len()..n].parse().unwrap();
+        messages.push(serde_json::from_str(&stdout[n + 4..n + 4 + length]).unwrap());
+        stdout = stdout[n + 4 + length..].to_string();
+    }
+
+    messages
+}
+
+fn json(text: &str) -> Value {
+    serde_json::from_str(text).unwrap()
+}
+
+fn response<'a>(messages: &'a [Value], command: &str) -> Vec<&'a Value> {
+    messages.iter().filter(|message| message["type"] == "response" && message["command"] == command).collect()
+}
+
+fn events<'a>(messages: &'a [Value], event: &str) -> Vec<&'a Value> {
+    messages.iter().filter(|message| message["type"] == "event" && message["event"] == event).collect()
+}
+
+#[test]
+fn breakpoints_stop_with_a_stack_and_variables() {
+    let messages = session("stack", &[
+        json(r#"{"command": "setBreakpoints", "arguments": {"source": {"path": "elsewhere.k"}, "breakpoints": [{"line": 1}]}}"#),
+        json(r#"{"command": "configurationDone"}"#),
+        json(r#"{"command": "stackTrace", "arguments": {"threadId": 1}}"#),
+        json(r#"{"command": "scopes", "arguments": {"frameId": 4}}"#),
+        json(r#"{"command": "variables", "arguments": {"variablesReference": 4}}"#),
+        json(r#"{"command": "evaluate", "arguments": {"expression": "x * 10", "frameId": 4}}"#),
+        json(r#"{"command": "evaluate", "arguments": {"expression": "y + 1", "frameId": 1}}"#),
+        json(r#"{"command": "continue", "arguments": {"threadId": 1}}"#),
+        json(r#"{"command": "disconnect"}"#),
+    ]);
+
+    // Breakpoints in a source that isn't the program are never hit
+    assert_eq!(response(&messages, "setBreakpoints")[0]["body"]["breakpoints"][0]["verified"], false);
+    assert!(events(&messages, "stopped").is_empty());
+
+    assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+    assert_eq!(events(&messages, "terminated").len(), 1);
+    assert!(response(&messages, "stackTrace").iter().all(|response| response["success"] == false));
+
+    // The program's output and the values of its top-level expressions are output events
+    let output: String = events(&messages, "output").iter()
+        .map(|event| event["body"]["output"].as_str().unwrap())
+        .collect();
+    assert_eq!(output, "7\n0\n6\n");
+}
+
+#[test]
+fn stopped_programs_can_be_inspected() {
+    let messages = session("inspect", &[
+        json(r#"{"command": "setBreakpoints", "arguments": {"source": {"path": "SELF"}, "breakpoints": [{"line": 3}]}}"#),
+        json(r#"{"command": "configurationDone"}"#),
+        json(r#"{"command": "stackTrace", "arguments": {"threadId": 1}}"#),
+        json(r#"{"command": "scopes", "arguments": {"frameId": 4}}"#),
+        json(r#"{"command": "variables", "arguments": {"variablesReference": 4}}"#),
+        json(r#"{"command": "evaluate", "arguments": {"expression": "x * 10", "frameId": 4}}"#),
+        json(r#"{"command": "evaluate", "arguments": {"expression": "y + 1", "frameId": 1}}"#),
+        json(r#"{"command": "disconnect"}"#),
+    ]);
+
+    let stopped = events(&messages, "stopped");
+    assert_eq!(stopped.len(), 1);
+    assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+
+    let frames = &response(&messages, "stackTrace")[0]["body"]["stackFrames"];
+    let names: Vec<&str> = frames.as_array().unwrap().iter().map(|frame| frame["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["fib", "fib", "fib", "twice", "<top level>"]);
+    assert_eq!(frames[0]["line"], 3);
+    assert_eq!(frames[0]["column"], 9);
+    assert_eq!(frames[3]["line"], 7);
+
+    assert_eq!(response(&messages, "scopes")[0]["body"]["scopes"][0]["variablesReference"], 4);
+
+    let variables = &response(&messages, "variables")[0]["body"]["variables"];
+    assert_eq!(variables[0]["name"], "x");
+    assert_eq!(variables[0]["value"], "2");
+
+    let evaluated = response(&messages, "evaluate");
+    assert_eq!(evaluated[0]["body"]["result"], "20");
+    assert_eq!(evaluated[1]["body"]["result"], "5");
+
+    // Disconnecting ends the program where it stopped
+    assert!(events(&messages, "exited").is_empty());
+}
+
+#[test]
+fn stepping_moves_breakpoints_to_code() {
+    let messages = session("step", &[
+        json(r#"{"command": "setBreakpoints", "arguments": {"source": {"path": "SELF"}, "breakpoints": [{"line": 4}]}}"#),
+        json(r#"{"command": "configurationDone"}"#),
+        json(r#"{"command": "setBreakpoints", "arguments": {"source": {"path": "SELF"}, "breakpoints": []}}"#),
+        json(r#"{"command": "stepIn", "arguments": {"threadId": 1}}"#),
+        json(r#"{"command": "stepIn", "arguments": {"threadId": 1}}"#),
+        json(r#"{"command": "stackTrace", "arguments": {"threadId": 1}}"#),
+        json(r#"{"command": "stepOut", "arguments": {"threadId": 1}}"#),
+        json(r#"{"command": "stackTrace", "arguments": {"threadId": 1}}"#),
+        json(r#"{"command": "continue", "arguments": {"threadId": 1}}"#),
+        json(r#"{"command": "disconnect"}"#),
+    ]);
+
+    // The `else` line has no code of its own, so the breakpoint moves to the next line
+    let breakpoint = &response(&messages, "setBreakpoints")[0]["body"]["breakpoints"][0];
+    assert_eq!(breakpoint["verified"], true);
+    assert_eq!(breakpoint["line"], 5);
+
+    let stopped = events(&messages, "stopped");
+    let reasons: Vec<&str> = stopped.iter().map(|event| event["body"]["reason"].as_str().unwrap()).collect();
+    assert_eq!(reasons, ["breakpoint", "step", "step", "step"]);
+
+    // Into `fib(3)` by way of its call, then back out to the next call in `fib(4)`
+    let traces = response(&messages, "stackTrace");
+    assert_eq!(traces[0]["body"]["stackFrames"][1]["name"], "fib");
+    assert_eq!(traces[0]["body"]["totalFrames"], 4);
+    assert_eq!(traces[1]["body"]["totalFrames"], 3);
+
+    assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+}
@@ -0,0 +1,79 @@
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::process::{Command, Output, Stdio};
+
+const PROGRAM: &str = "\
+def fib(x)
+    if x < 3 then
+        1
+    else
+        fib(x - 1) + fib(x - 2)
+
+def twice(y) fib(y) * 2
+
+twice(4)
+";
+
+fn debug(name: &str, commands: &str) -> Output {
+    let path = env::temp_dir().join(format!("kaleidescope-debug-{}.k", name));
+    File::create(&path).unwrap().write_all(PROGRAM.as_bytes()).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs"))
+        .arg("debug")
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(commands.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn function_breakpoints_show_the_call_stack() {
+    let output = debug("stack", "break fib\ncontinue\nbacktrace\nprint x * 10\ncontinue\nquit\n");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout.contains("breakpoint 1, fib(x = 4) at "));
+    assert!(stdout.contains("#0  fib(x = 4) at "));
+    assert!(stdout.contains("#1  twice(y = 4) at "));
+    assert!(stdout.contains("#2  <top level> at "));
+    assert!(stdout.contains("\n40\n"));
+    assert!(stdout.contains("breakpoint 1, fib(x = 3) at "));
+
+    // Quitting doesn't finish the program
+    assert!(!stdout.contains("\n6\n"));
+}
+
+#[test]
+fn line_breakpoints_and_stepping() {
+    let output = debug("lines", "break 3\nc\nfinish\nstep\nstep\ndelete 1\nc\n");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stops: Vec<&str> = stdout.lines().filter(|line| line.contains(".k:") && !line.contains("breakpoint 1 at")).collect();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stops[1].starts_with("breakpoint 1, fib(x = 2) at "), "{}", stdout);
+    assert!(stops[1].ends_with(":3"));
+
+    // Out to the caller's next call, then into it
+    assert!(stops[2].starts_with("fib(x = 3) at "));
+    assert!(stops[2].ends_with(":5"));
+    assert!(stops[3].starts_with("fib(x = 1) at "));
+    assert!(stops[3].ends_with(":2"));
+    assert!(stops[4].starts_with("breakpoint 1, fib(x = 1) at "));
+
+    // Runs to the end once the breakpoint is gone
+    assert!(stdout.ends_with("6\n"));
+}
+
+#[test]
+fn unknown_breakpoints_are_rejected() {
+    let stdout = String::from_utf8(debug("unknown", "break nope\nbreak 4\nquit\n").stdout).unwrap();
+
+    assert!(stdout.contains("error: no function named `nope`"));
+    assert!(stdout.contains("error: no code to stop at on line 4 of "));
+}
@@ -0,0 +1,49 @@
+use std::env;
+use std::fs;
+use std::process::{Command, Output};
+
+fn kaleidescope(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs")).args(args).output().unwrap()
+}
+
+const SOURCE: &str = "\
+## The square of `x`.
+def square(x) x * x
+
+# An ordinary comment
+## Sum of the squares,
+## of `a` and `b`.
+def sumsq(a, b) square(a) + square(b) + cube(a)
+
+### A banner, not documentation
+extern cube(x)
+";
+
+#[test]
+fn doc_writes_pages_with_doc_comments_and_links() {
+    let dir = env::temp_dir().join("kaleidescope-doc-pages");
+    let _ = fs::remove_dir_all(&dir);
+
+    let output = kaleidescope(&["doc", "-o", dir.to_str().unwrap(), "--title", "Squares", "-e", SOURCE]);
+    assert!(output.status.success());
+
+    let markdown = fs::read_to_string(dir.join("index.md")).unwrap();
+    assert!(markdown.starts_with("# Squares\n\n- [`def square(x)`](#square)\n"));
+    assert!(markdown.contains("## `def sumsq(a, b)`\n\nSum of the squares,\nof `a` and `b`.\n\nCalls [`square`](#square), [`cube`](#cube).\n"));
+    assert!(markdown.ends_with("<a id=\"cube\"></a>\n## `extern cube(x)`\n"));
+
+    let html = fs::read_to_string(dir.join("index.html")).unwrap();
+    assert!(html.contains("<section id=\"square\">\n<h2><code>def square(x)</code></h2>\n<p>The square of `x`.</p>\n</section>"));
+}
+
+#[test]
+fn doc_comments_are_kept_in_the_ast() {
+    let output = kaleidescope(&["parse", "-e", SOURCE]);
+    let json = String::from_utf8(output.stdout).unwrap();
+    assert!(json.contains("\"doc\": \"Sum of the squares,\\nof `a` and `b`.\""));
+    assert_eq!(json.matches("\"doc\"").count(), 2);
+
+    // Comments other than doc comments don't document anything
+    let output = kaleidescope(&["parse", "--emit", "sexpr", "-e", "## Dangling\n1 + 2"]);
+    assert!(output.status.success());
+}
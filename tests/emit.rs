@@ -0,0 +1,49 @@
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn write(name: &str, contents: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("kaleidescope-emit-{}", name));
+    File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+    path
+}
+
+fn emit(kind: &str, name: &str, source: &str) -> String {
+    let source = write(name, source);
+    let output = Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs"))
+        .args(["parse", &format!("--emit={}", kind), source.to_str().unwrap()])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn sexprs_show_how_operators_group() {
+    let source = "\
+        def f(a, b) if a < b then -a * (b + 1) else g()\n\
+        pure extern sin(x)\n\
+        1 + 2 * 3 - 4 - 5\n\
+        f(1, 2) && !sin(3) || 1\n";
+
+    assert_eq!(emit("sexpr", "group.k", source), "\
+        (def (f a b) (if (< a b) (* (- a) (+ b 1)) (g)))\n\
+        (pure extern (sin x))\n\
+        (- (- (+ 1 (* 2 3)) 4) 5)\n\
+        (|| (&& (f 1 2) (! (sin 3))) 1)\n");
+}
+
+#[test]
+fn ast_dot_has_a_node_per_ast_node() {
+    let dot = emit("ast-dot", "dot.k", "1 + 2 * x\n");
+
+    assert!(dot.starts_with("digraph ast {\n"));
+    assert!(dot.ends_with("}\n"));
+
+    // file, +, 1, *, 2 and x
+    assert_eq!(dot.matches("[label=").count(), 6);
+    assert!(dot.contains("[label=\"*\", shape=circle]"));
+}
@@ -0,0 +1,52 @@
+extern crate kaleidescope_rs;
+
+use std::thread;
+
+use kaleidescope_rs::limits::{Limit, Limits, DEFAULT_MAX_DEPTH};
+use kaleidescope_rs::{Engine, EvalError, Value};
+
+const COUNT: &str = "def count(n) if n == 0 then 0 else 1 + (0 + (0 * n + (0 + -(0 - count(n - 1)))))";
+
+/// Count down from `n` with `load` and then with `call`, in an engine with
+/// `limits`, if any, on a thread with `stack_size` bytes of stack.
+fn count(stack_size: usize, limits: Option<Limits>, n: usize) -> (Result<String, String>, Result<String, EvalError>) {
+    thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn(move || {
+            let mut engine = Engine::new();
+            if let Some(limits) = limits {
+                engine.set_limits(limits);
+            }
+
+            engine.load(COUNT).unwrap();
+
+            let loaded = engine.load(&format!("count({})", n)).map(|values| values[0].to_string());
+            let called = engine.call("count", &[Value::from(n as f64)]).map(|value| value.to_string());
+            (loaded, called)
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+#[test]
+fn deep_recursion_is_stopped_within_the_stack_of_a_main_thread() {
+    let n = DEFAULT_MAX_DEPTH - 1;
+    assert_eq!(count(8 << 20, None, n), (Ok(n.to_string()), Ok(n.to_string())));
+
+    assert_eq!(
+        count(8 << 20, None, 1_000_000),
+        (
+            Err(format!("limit exceeded: calls nested more than {} deep", DEFAULT_MAX_DEPTH)),
+            Err(EvalError::LimitExceeded(Limit::Depth(DEFAULT_MAX_DEPTH)))
+        )
+    );
+}
+
+#[test]
+fn larger_stacks_can_allow_deeper_recursion() {
+    let limits = Limits { max_depth: Some(5000), ..Limits::default() };
+
+    assert_eq!(count(1 << 30, Some(limits.clone()), 4999), (Ok("4999".to_string()), Ok("4999".to_string())));
+    assert_eq!(count(1 << 30, Some(limits), 5000).1, Err(EvalError::LimitExceeded(Limit::Depth(5000))));
+}
@@ -0,0 +1,67 @@
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::process::{Command, Output};
+
+/// Run the program `source`, written to a file named after `name`.
+fn run(name: &str, source: &str) -> Output {
+    let path = env::temp_dir().join(format!("kaleidescope-eval-{}.k", name));
+    File::create(&path).unwrap().write_all(source.as_bytes()).unwrap();
+
+    Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs")).arg(&path).output().unwrap()
+}
+
+#[test]
+fn top_level_expressions_print_their_values() {
+    let output = run("values", "def add(x, y) x + y\nextern sqrt(x)\n-(4)\nadd(1, 2)\nsqrt(add(7, 9))\n!0\n3 < 2\n");
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "-4\n3\n4\n1\n0\n");
+}
+
+#[test]
+fn binary_operators_follow_their_precedence() {
+    let output = run("precedence", "1 - 2 - 3\n2 * 3 + 4 * 5\n1 + 2 * 3 - 4\n8 / 4 / 2\n1 + 1 == 2 && 3 < 4\n");
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "-4\n26\n3\n1\n1\n");
+}
+
+#[test]
+fn semicolons_separate_top_level_expressions() {
+    let output = run("semicolons", "1 + 1; 2 * 2;;\n3;\n");
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\n4\n3\n");
+}
+
+#[test]
+fn logical_operators_only_evaluate_what_they_need() {
+    let output = run("short-circuit", "extern putchard(c)\n0 && putchard(65)\n1 || putchard(66)\n1 && putchard(67)\n");
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "0\n1\nC0\n");
+}
+
+#[test]
+fn conditionals_only_evaluate_the_branch_taken() {
+    let output = run("if", "extern putchard(c)\nif 1 then putchard(65) else putchard(66)\nif 0 < -1 then 1 else 2\n");
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "A0\n2\n");
+}
+
+#[test]
+fn if_then_and_else_are_reserved() {
+    for source in &["def if(x) x\n", "def f(then) then\n", "def f(x) else\n"] {
+        assert!(!run("reserved", source).status.success(), "{}", source);
+    }
+}
+
+#[test]
+fn calling_an_undefined_function_fails() {
+    let output = run("undefined", "def f(x) nope(x)\nf(1)\n");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("undefined function `nope`"));
+}
@@ -0,0 +1,45 @@
+use std::process::{Command, Output};
+
+fn run(flags: &[&str], sources: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs"));
+    command.arg("run").args(flags);
+
+    for source in sources {
+        command.arg("-e").arg(source);
+    }
+
+    command.output().unwrap()
+}
+
+#[test]
+fn externs_call_c_functions_in_the_process_and_linked_libraries() {
+    let output = run(&[], &["extern fmax(x, y)  extern copysign(x, y)", "fmax(2, 7) + copysign(3, -1)"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "4\n");
+
+    let output = run(&["--link", "libm.so.6"], &["extern fdim(x, y)", "fdim(5, 3)"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\n");
+
+    // Linked functions are still externs as far as the allowlist goes
+    let output = run(&["--allow-externs", "sin"], &["extern fmax(x, y)", "fmax(2, 7)"]);
+    assert_eq!(output.status.code(), Some(6));
+}
+
+#[test]
+fn unresolved_externs_are_reported_before_running() {
+    let output = run(&[], &["def f(x) x\nf(1)", "extern fmax(x, y)\n  extern no_such_function(x)"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(output.stdout, b"");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "error: -e #2:2:3: unresolved extern `no_such_function`: no native or C function has its name\n"
+    );
+
+    let output = run(&[], &["extern fmax(a, b, c, d, e, f, g)"]);
+    assert_eq!(output.status.code(), Some(4));
+
+    let output = run(&["--link", "/no/such/library.so"], &["1"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with("error: unable to link `/no/such/library.so`: "));
+}
@@ -0,0 +1,105 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn fmt(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs"))
+        .arg("fmt")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Format `source` from standard input with the given flags.
+fn format(source: &str, flags: &[&str]) -> String {
+    let output = fmt(flags, source);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn write(name: &str, contents: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("kaleidescope-format-{}.k", name));
+    File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+    path
+}
+
+const MESSY: &str = "# leading comment\n\
+                     def   f(x)   ((x+1))*(2)   # trailing\n\n\n\
+                     # before g\n\
+                     def g(a, b) (a - (b - 1)) - (a * b) + -(a)\n\
+                     extern putchard(c)\n\
+                     f(1)\n";
+
+#[test]
+fn comments_are_kept() {
+    let formatted = format(MESSY, &[]);
+
+    assert!(formatted.starts_with("# leading comment\n"), "{}", formatted);
+    assert!(formatted.contains(" # trailing\n"), "{}", formatted);
+    assert!(formatted.contains("\n# before g\ndef g"), "{}", formatted);
+}
+
+#[test]
+fn only_needed_parentheses_are_kept() {
+    assert_eq!(
+        format(MESSY, &[]),
+        "# leading comment\n\
+         def f(x) (x + 1) * 2 # trailing\n\n\
+         # before g\n\
+         def g(a, b) a - (b - 1) - a * b + -a\n\n\
+         extern putchard(c)\n\
+         f(1)\n"
+    );
+}
+
+#[test]
+fn long_lines_are_wrapped_at_the_width() {
+    let source = "def long(alpha, beta, gamma) alpha * beta + beta * gamma + gamma * alpha + alpha * beta * gamma\n";
+
+    assert_eq!(format(source, &["--width", "120"]).lines().count(), 1);
+    assert_eq!(
+        format(source, &["--width", "40"]),
+        "def long(alpha, beta, gamma)\n    alpha * beta\n        + beta * gamma\n        + gamma * alpha\n        + alpha * beta * gamma\n"
+    );
+}
+
+#[test]
+fn formatting_is_idempotent() {
+    let long = "def long(alpha, beta, gamma) if alpha < beta then alpha * beta + gamma else (beta - gamma) * alpha\n";
+
+    for &(source, width) in &[(MESSY, "80"), (long, "80"), (long, "30")] {
+        let once = format(source, &["--width", width]);
+        assert_eq!(format(&once, &["--width", width]), once);
+    }
+}
+
+#[test]
+fn files_are_rewritten_in_place() {
+    let path = write("in-place", "def f(x)  x+1\n");
+
+    let output = fmt(&[path.to_str().unwrap()], "");
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "def f(x) x + 1\n");
+}
+
+#[test]
+fn check_fails_on_unformatted_files_without_changing_them() {
+    let formatted = write("formatted", "def f(x) x\n");
+    let unformatted = write("unformatted", "def f(x)  x\n");
+
+    assert_eq!(fmt(&["--check", formatted.to_str().unwrap()], "").status.code(), Some(0));
+
+    let output = fmt(&["--check", unformatted.to_str().unwrap()], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("is not formatted"));
+    assert_eq!(fs::read_to_string(&unformatted).unwrap(), "def f(x)  x\n");
+}
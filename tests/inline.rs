@@ -0,0 +1,108 @@
+mod common;
+
+use common::kaleidescope;
+
+/// Optimize `source` with the given flags and return the program it became,
+/// as s-expressions.
+fn build(source: &str, flags: &[&str]) -> String {
+    let output = kaleidescope("build", &[&["--no-prelude", "--emit", "sexpr"], flags].concat(), &[source]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Run `source` with the given flags and return what it printed.
+fn run(source: &str, flags: &[&str]) -> String {
+    let output = kaleidescope("run", &[&["--no-prelude"], flags].concat(), &[source]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn small_functions_are_inlined() {
+    assert_eq!(build("def sq(x) x * x\nsq(3)\n", &["-O1"]), "(* 3 3)\n");
+}
+
+#[test]
+fn functions_over_the_threshold_are_kept() {
+    let source = "def big(x) x * x * x * x * x * x * x\nbig(2)\n";
+
+    assert_eq!(build(source, &["-O1"]), "(def (big x) (* (* (* (* (* (* x x) x) x) x) x) x))\n(big 2)\n");
+    assert_eq!(build(source, &["-O1", "--inline-threshold", "64"]), "(* (* (* (* (* (* 2 2) 2) 2) 2) 2) 2)\n");
+}
+
+#[test]
+fn noinline_functions_are_kept() {
+    assert_eq!(build("@noinline def f(x) x\nf(2)\n", &["-O1"]), "(def @noinline (f x) x)\n(f 2)\n");
+}
+
+#[test]
+fn recursive_functions_are_not_inlined() {
+    let source = "def fact(n) if n < 2 then 1 else n * fact(n - 1)\nfact(5)\n";
+
+    assert_eq!(build(source, &["-O3"]), "(def (fact n) (if (< n 2) 1 (* n (fact (- n 1)))))\n(fact 5)\n");
+}
+
+#[test]
+fn unused_pure_arguments_are_dropped() {
+    assert_eq!(build("def k(x, y) x\nk(1, 2 + 3)\n", &["-O1"]), "1\n");
+}
+
+#[test]
+fn unused_effectful_arguments_are_kept() {
+    let source = "extern putchard(c)\ndef k(x, y) x\nk(1, putchard(65))\n";
+
+    assert!(build(source, &["-O1"]).contains("(k 1 (putchard 65))"));
+    assert_eq!(run(source, &["-O1"]), "A1\n");
+}
+
+#[test]
+fn effectful_arguments_keep_their_order() {
+    let in_order = "extern putchard(c)\ndef one(x, y) x + y\none(putchard(65), putchard(66))\n";
+    let swapped = "extern putchard(c)\ndef two(x, y) y + x\ntwo(putchard(65), putchard(66))\n";
+
+    assert!(build(in_order, &["-O1"]).ends_with("(+ (putchard 65) (putchard 66))\n"));
+    assert!(build(swapped, &["-O1"]).contains("(two (putchard 65) (putchard 66))"));
+
+    for source in &[in_order, swapped] {
+        assert_eq!(run(source, &["-O1"]), run(source, &[]));
+    }
+}
+
+#[test]
+fn arguments_that_can_fail_are_kept() {
+    let source = "pure extern num(s)\ndef k(x, y) x\nk(1, num(\"abc\"))\n";
+
+    assert!(build(source, &["-O1"]).contains("(k 1 (num \"abc\"))"));
+
+    for level in &["-O0", "-O1"] {
+        let output = kaleidescope("run", &["--no-prelude", level], &[source]);
+        assert_eq!(output.status.code(), Some(5));
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "error: `num` failed: \"abc\" is not a number\n");
+    }
+}
+
+#[test]
+fn arguments_that_can_fail_keep_their_order() {
+    let source = "pure extern num(s)\ndef two(x, y) y + x\ntwo(num(\"a\"), num(\"b\"))\n";
+
+    assert!(build(source, &["-O1"]).contains("(two (num \"a\") (num \"b\"))"));
+
+    // Once the first use has succeeded, so will the others
+    let source = "def sq(x) x * x\n@noinline def f(a) sq(a - 1)\nf(3)\n";
+    assert_eq!(build(source, &["-O1"]), "(def @noinline (f a) (let (%0 (- a 1)) (* %0 %0)))\n(f 3)\n");
+}
+
+#[test]
+fn free_names_are_not_captured_by_the_caller() {
+    let source = "def f(a) y + a\n@noinline def g(y) f(2)\ng(40)\n";
+
+    assert!(build(source, &["-O1"]).contains("(f 2)"));
+
+    for level in &["-O0", "-O1"] {
+        let output = kaleidescope("run", &["--no-prelude", level], &[source]);
+        assert_eq!(output.status.code(), Some(5));
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "error: unknown variable `y`\n");
+    }
+}
@@ -0,0 +1,88 @@
+extern crate kaleidescope_rs;
+
+mod common;
+
+use std::process::Output;
+use std::thread;
+
+use common::kaleidescope;
+use kaleidescope_rs::limits::{Limit, DEFAULT_MAX_DEPTH};
+use kaleidescope_rs::{EvalError, Value};
+
+const FIB: &str = "def fib(x) if x < 3 then 1 else fib(x - 1) + fib(x - 2)";
+const COUNT: &str = "def count(n) if n == 0 then 0 else 1 + count(n - 1)";
+
+fn run(flags: &[&str], sources: &[&str]) -> Output {
+    kaleidescope("run", flags, sources)
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn runaway_programs_are_stopped() {
+    let output = run(&["--fuel", "1000"], &[FIB, "fib(5)", "fib(1000)"]);
+    assert_eq!(output.status.code(), Some(6));
+    assert_eq!(String::from_utf8(output.stdout.clone()).unwrap(), "5\n");
+    assert_eq!(stderr(&output), "error: limit exceeded: ran out of fuel after evaluating 1000 expressions\n");
+
+    let output = run(&["--timeout", "50"], &[FIB, "fib(1000)"]);
+    assert_eq!(output.status.code(), Some(6));
+    assert_eq!(stderr(&output), "error: limit exceeded: ran for longer than 50ms\n");
+}
+
+#[test]
+fn deep_recursion_is_an_error_rather_than_a_crash() {
+    let output = run(&[], &[COUNT, "count(9000)", "count(1000000)"]);
+    assert_eq!(output.status.code(), Some(6));
+    assert_eq!(String::from_utf8(output.stdout.clone()).unwrap(), "9000\n");
+    assert_eq!(stderr(&output), "error: limit exceeded: calls nested more than 10000 deep\n");
+
+    let output = run(&["--max-depth", "3"], &[COUNT, "count(2)", "count(3)"]);
+    assert_eq!(output.status.code(), Some(6));
+    assert_eq!(stderr(&output), "error: limit exceeded: calls nested more than 3 deep\n");
+}
+
+/// Run `source` with the library's default limits on a thread with the 8 MiB
+/// stack of a main thread, and print the values.
+fn run_on_a_main_thread_stack(source: String) -> Result<Vec<String>, EvalError> {
+    thread::Builder::new()
+        .stack_size(8 << 20)
+        .spawn(move || {
+            let values = kaleidescope_rs::run(&kaleidescope_rs::parse(&source).unwrap())?;
+            Ok(values.iter().map(Value::to_string).collect())
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+#[test]
+fn the_default_depth_fits_the_stack_of_a_main_thread() {
+    // Calls nested inside several expressions take the most stack per level
+    let nested = "def count(n) if n == 0 then 0 else 1 + (0 + (0 * n + (0 + -(0 - count(n - 1)))))";
+
+    assert_eq!(
+        run_on_a_main_thread_stack(format!("{}  count({})", nested, DEFAULT_MAX_DEPTH - 1)),
+        Ok(vec![(DEFAULT_MAX_DEPTH - 1).to_string()])
+    );
+    assert_eq!(
+        run_on_a_main_thread_stack(format!("{}  count(1000000)", nested)),
+        Err(EvalError::LimitExceeded(Limit::Depth(DEFAULT_MAX_DEPTH)))
+    );
+}
+
+#[test]
+fn only_allowed_externs_can_be_called() {
+    let program = ["extern sqrt(x)", "extern printd(x)", "sqrt(16)", "printd(1)"];
+
+    let output = run(&["--allow-externs", "sqrt, fabs"], &program);
+    assert_eq!(output.status.code(), Some(6));
+    assert_eq!(String::from_utf8(output.stdout.clone()).unwrap(), "4\n");
+    assert_eq!(stderr(&output), "error: limit exceeded: extern `printd` is not allowed\n");
+
+    // Runtime errors keep their own status
+    let output = run(&["--allow-externs", ""], &["def f(x) g(x)", "f(1)"]);
+    assert_eq!(output.status.code(), Some(5));
+}
@@ -0,0 +1,92 @@
+use std::io::prelude::*;
+use std::process::{Command, Output, Stdio};
+
+fn lint(args: &[&str], source: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs"))
+        .arg("lint")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(source.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// The rule of each finding, in order.
+fn rules(args: &[&str], source: &str) -> Vec<String> {
+    let output = String::from_utf8(lint(args, source).stdout).unwrap();
+
+    output.lines().map(|line| {
+        let start = line.find('[').unwrap() + 1;
+        let end = line.find(']').unwrap();
+        line[start..end].to_string()
+    }).collect()
+}
+
+#[test]
+fn every_rule_finds_its_mistake() {
+    let source = "\
+        def f(a, b) a\n\
+        def g(x) if x == x then 1 else 2\n\
+        def h(x) if 1 < 2 then x else 0\n\
+        def k(x) x % (1 - 1)\n\
+        def m(x, y) x / y == 0.5\n\
+        extern sin(x)\n\
+        def sin(x) x\n";
+
+    assert_eq!(rules(&[], source), [
+        "unused-parameter",
+        "self-comparison",
+        "constant-condition",
+        "remainder-by-zero",
+        "float-equality",
+        "shadowed-extern",
+    ]);
+}
+
+#[test]
+fn clean_code_has_no_findings() {
+    let source = "def fib(x) if x < 3 then 1 else fib(x - 1) + fib(x - 2)\nfib(10) == 55\n";
+    let output = lint(&[], source);
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn comments_allow_rules_for_one_item() {
+    let source = "\
+        # lint: allow(unused-parameter)\n\
+        def f(a, b) a\n\
+        def g(a, b) a  # lint: allow(unused-parameter)\n\
+        def h(a, b) a\n";
+
+    let output = String::from_utf8(lint(&[], source).stdout).unwrap();
+    assert_eq!(output.lines().count(), 1);
+    assert!(output.starts_with("standard input:4:1: warning[unused-parameter]"));
+}
+
+#[test]
+fn severities_are_configurable_and_later_flags_win() {
+    let source = "def f(a, b) a\ndef k(x) x % 0\n";
+
+    assert!(!lint(&[], source).status.success());
+    assert!(lint(&["-W", "remainder-by-zero"], source).status.success());
+    assert_eq!(rules(&["-A", "all", "-D", "unused-parameter"], source), ["unused-parameter"]);
+    assert!(!lint(&["-A", "all", "-D", "unused-parameter"], source).status.success());
+    assert_eq!(lint(&["-D", "no-such-rule"], source).status.code(), Some(2));
+}
+
+#[test]
+fn json_output_is_machine_readable() {
+    let output = lint(&["--format", "json"], "def f(a, b) a\n");
+    let output = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.trim_start().starts_with('['));
+    assert!(output.contains("\"rule\": \"unused-parameter\""));
+    assert!(output.contains("\"severity\": \"warn\""));
+    assert!(output.contains("\"line\": 1"));
+}
@@ -0,0 +1,170 @@
+#[macro_use]
+extern crate serde_json;
+
+use std::io::prelude::*;
+use std::process::{Command, Stdio};
+
+use serde_json::Value;
+
+const URI: &str = "file:///main.k";
+
+const PROGRAM: &str = "\
+## Doubles `x`.
+def double(x) x * 2
+
+def quad(y) double(double(y))
+
+quad(3)
+";
+
+/// Open `text` in a language server, send it `requests` with ids from 2 up,
+/// then shut it down, and read everything it sends back.
+fn session(text: &str, requests: &[Value]) -> Vec<Value> {
+    let open = json!({
+        "method": "textDocument/didOpen",
+        "params": { "textDocument": { "uri": URI, "languageId": "kaleidescope", "version": 1, "text": text } },
+    });
+
+    let mut messages = vec![json!({ "id": 1, "method": "initialize", "params": {} }), open];
+    for (id, request) in requests.iter().enumerate() {
+        let mut request = request.clone();
+        request["id"] = Value::from(id + 2);
+        messages.push(request);
+    }
+    messages.push(json!({ "id": 0, "method": "shutdown" }));
+    messages.push(json!({ "method": "exit" }));
+
+    serve(&messages.into_iter().map(frame).collect::<String>())
+}
+
+/// `message` as JSON-RPC, framed by its header.
+fn frame(mut message: Value) -> String {
+    message["jsonrpc"] = Value::from("2.0");
+
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+/// Send `input` to a language server that should exit cleanly, and read
+/// everything it sends back.
+fn serve(input: &str) -> Vec<Value> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs"))
+        .arg("lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+
+    let mut stdout = String::from_utf8(output.stdout).unwrap();
+    let mut messages: Vec<Value> = Vec::new();
+
+    while let Some(n) = stdout.find("\r\n\r\n") {
+        let length: usize = stdout["Content-Length: ".len()..n].parse().unwrap();
+        messages.push(serde_json::from_str(&stdout[n + 4..n + 4 + length]).unwrap());
+        stdout = stdout[n + 4 + length..].to_string();
+    }
+
+    messages
+}
+
+/// A request at `line` and `character` of the document.
+fn at(method: &str, line: u64, character: u64) -> Value {
+    json!({
+        "method": method,
+        "params": {
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character },
+            "context": { "includeDeclaration": true },
+        },
+    })
+}
+
+fn result(messages: &[Value], id: u64) -> &Value {
+    &messages.iter().find(|message| message["id"] == id).unwrap()["result"]
+}
+
+fn diagnostics(messages: &[Value]) -> &Value {
+    &messages.iter().find(|message| message["method"] == "textDocument/publishDiagnostics").unwrap()["params"]["diagnostics"]
+}
+
+/// `[line, character]` of the start of each location.
+fn starts(locations: &Value) -> Vec<(u64, u64)> {
+    locations.as_array().unwrap().iter()
+        .map(|location| {
+            let start = &location["range"]["start"];
+            (start["line"].as_u64().unwrap(), start["character"].as_u64().unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn initialize_reports_capabilities() {
+    let messages = session(PROGRAM, &[]);
+
+    let capabilities = &result(&messages, 1)["capabilities"];
+    assert_eq!(capabilities["hoverProvider"], true);
+    assert_eq!(capabilities["definitionProvider"], true);
+    assert_eq!(capabilities["referencesProvider"], true);
+    assert_eq!(diagnostics(&messages), &json!([]));
+    assert_eq!(result(&messages, 0), &Value::Null);
+}
+
+#[test]
+fn hover_shows_the_signature_and_doc_comment() {
+    let messages = session(PROGRAM, &[at("textDocument/hover", 3, 12), at("textDocument/hover", 3, 26)]);
+
+    assert_eq!(result(&messages, 2)["contents"]["value"], "```\ndef double(x)\n```\nDoubles `x`.");
+    assert_eq!(result(&messages, 3)["contents"]["value"], "```\ny\n```\nparameter of `def quad(y)`");
+}
+
+#[test]
+fn definition_and_references_find_every_use() {
+    let messages = session(PROGRAM, &[at("textDocument/definition", 3, 12), at("textDocument/references", 1, 4)]);
+
+    assert_eq!(starts(&json!([result(&messages, 2)])), [(1, 4)]);
+    assert_eq!(starts(result(&messages, 3)), [(1, 4), (3, 12), (3, 19)]);
+}
+
+#[test]
+fn problems_are_published_as_diagnostics() {
+    let messages = session("def f(x) y\nnope(1)\n", &[]);
+
+    let diagnostics = diagnostics(&messages);
+    assert_eq!(diagnostics[0]["message"], "unknown variable `y`");
+    assert_eq!(starts(diagnostics), [(0, 9), (1, 0)]);
+    assert_eq!(diagnostics[1]["message"], "call to undefined function `nope`");
+}
+
+#[test]
+fn half_typed_documents_do_not_crash_the_server() {
+    for text in &["def f(x) x.", "def f(x) x +", ".", "def f(x) \"open"] {
+        let messages = session(text, &[at("textDocument/hover", 0, 9)]);
+
+        assert_eq!(diagnostics(&messages).as_array().unwrap().len(), 1, "{}", text);
+    }
+}
+
+#[test]
+fn malformed_messages_get_a_parse_error() {
+    let input = [
+        "Content-Length: 9\r\n\r\n{not json".to_string(),
+        "Content-Length: lots\r\n\r\n{}".to_string(),
+        frame(json!({ "id": 1, "method": "initialize", "params": {} })),
+        frame(json!({ "id": 0, "method": "shutdown" })),
+        frame(json!({ "method": "exit" })),
+    ].concat();
+
+    let messages = serve(&input);
+    let errors: Vec<&Value> = messages.iter().filter(|message| message.get("error").is_some()).collect();
+
+    assert_eq!(errors.len(), 2);
+    for error in errors {
+        assert_eq!(error["id"], Value::Null);
+        assert_eq!(error["error"]["code"], json!(-32700));
+    }
+    assert!(result(&messages, 1).get("capabilities").is_some());
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use common::kaleidescope;
+
+/// What the program printed, and what it reported on standard error.
+fn outputs(source: &str, flags: &[&str]) -> (String, String) {
+    let output = kaleidescope("run", flags, &[source]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    (String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap())
+}
+
+const FIB: &str = "@memo def fib(n) if n < 2 then n else fib(n - 1) + fib(n - 2)\nfib(30)\n";
+
+#[test]
+fn repeated_calls_hit_the_cache() {
+    assert_eq!(
+        outputs(FIB, &[]),
+        (
+            "832040\n".to_string(),
+            "memoization statistics:\n    fib: 28 hits, 31 misses, 0 evictions, 31 entries\n".to_string()
+        )
+    );
+}
+
+#[test]
+fn full_caches_evict_entries() {
+    let source = "@memo def f(x) x\nf(1)\nf(2)\nf(1)\n";
+
+    let (stdout, stderr) = outputs(source, &["--memo-capacity", "1"]);
+    assert_eq!(stdout, "1\n2\n1\n");
+    assert!(stderr.contains("f: 0 hits, 3 misses, 2 evictions, 1 entries"), "{}", stderr);
+
+    let (_, stderr) = outputs(source, &["--memo-capacity", "2"]);
+    assert!(stderr.contains("f: 1 hits, 2 misses, 0 evictions, 2 entries"), "{}", stderr);
+}
+
+#[test]
+fn memo_auto_memoizes_pure_recursive_functions() {
+    let source = "def fib(n) if n < 2 then n else fib(n - 1) + fib(n - 2)\ndef sq(x) x * x\nfib(10)\nsq(2)\n";
+
+    let (stdout, stderr) = outputs(source, &["--memo-auto"]);
+    assert_eq!(stdout, "55\n4\n");
+    assert_eq!(stderr, "memoization statistics:\n    fib: 8 hits, 11 misses, 0 evictions, 11 entries\n");
+
+    let (_, stderr) = outputs(source, &[]);
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn memoizing_effectful_functions_warns() {
+    let (_, stderr) = outputs("extern printd(x)\n@memo def log(x) printd(x)\nlog(1)\n", &[]);
+
+    assert!(stderr.starts_with("warning: `log` is effectful"), "{}", stderr);
+}
+
+#[test]
+fn memoized_functions_are_not_inlined() {
+    let source = "@memo def sq(x) x * x\nsq(3)\nsq(3)\n";
+
+    for flags in &[&["-O1"][..], &["-O3"][..]] {
+        let (stdout, stderr) = outputs(source, flags);
+        assert_eq!(stdout, "9\n9\n");
+        assert!(stderr.contains("sq: 1 hits, 1 misses"), "{}", stderr);
+    }
+
+    assert_eq!(outputs(FIB, &["-O3"]), outputs(FIB, &[]));
+}
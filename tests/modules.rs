@@ -0,0 +1,70 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A fresh directory holding `files`, each a relative path and its source.
+fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = env::temp_dir().join(format!("kaleidescope-modules-{}", name));
+    let _ = fs::remove_dir_all(&dir);
+
+    for &(path, source) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, source).unwrap();
+    }
+
+    dir
+}
+
+fn kaleidescope(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs")).current_dir(dir).args(args).output().unwrap()
+}
+
+#[test]
+fn modules_have_namespaces_of_their_own() {
+    let dir = project("namespaces", &[
+        ("lib/stats.k", "def square(x) x * x\ndef sumsq(a, b) square(a) + square(b)\nprintd(99)"),
+        ("vendor/geometry/area.k", "def square(x) x * x * 10"),
+        ("main.k", "import \"lib/stats.k\"\nimport geometry.area as a\ndef square(x) 0\nsumsq(1, 2) + a.square(2) + square(5)"),
+    ]);
+
+    // The module's own calls stay in the module, and its top-level expressions never run
+    let output = kaleidescope(&dir, &["run", "-I", "vendor", "main.k"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "45\n");
+
+    // A built program needs no modules to run
+    let output = kaleidescope(&dir, &["build", "-I", "vendor", "-o", "main.json", "main.k"]);
+    assert!(output.status.success());
+
+    let output = kaleidescope(&dir, &["run", "--from-ast", "main.json"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "45\n");
+
+    let output = kaleidescope(&dir, &["test", "-I", "vendor", "-e", "import geometry.area  test area geometry.area.square(1) == 10"]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn bad_imports_are_reported_before_running() {
+    let dir = project("errors", &[
+        ("a.k", "import \"b.k\"\ndef f(x) x"),
+        ("b.k", "import \"a.k\"\ndef g(x) x"),
+        ("one.k", "def f(x) 1"),
+        ("two.k", "def f(x) 2"),
+    ]);
+
+    let cases = [
+        ("import \"a.k\"", "error: b.k: import cycle: a.k -> b.k -> a.k\n"),
+        ("import \"one.k\"  import \"two.k\"  f(0)", "error: -e #1: `f` is defined by both modules `one` and `two`\n"),
+        ("import \"one.k\" as m  m.g(0)", "error: -e #1: module `m` has no function `g`\n"),
+        ("m.f(0)", "error: -e #1: no module is imported as `m`\n"),
+        ("import missing", "error: -e #1: module `missing` is not in any directory of the search path\n"),
+    ];
+
+    for &(source, error) in &cases {
+        let output = kaleidescope(&dir, &["run", "-e", source]);
+        assert_eq!(output.status.code(), Some(4), "{}", source);
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), error);
+    }
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::kaleidescope;
+
+#[test]
+fn prelude_functions_need_no_declaration() {
+    let output = kaleidescope("run", &[], &["max(floor(2.5), min(7, 3)) + abs(-1) + sin(pi() / 2) + pow(2, 3)", "printd(e() > 2.7)"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "13\n1\n0\n");
+
+    // Only the program's own functions are summarized
+    let output = kaleidescope("check", &[], &["def f(x) sqrt(x) * 2"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "def f(x): pure\n");
+}
+
+#[test]
+fn constants_are_functions_of_no_arguments() {
+    let output = kaleidescope("run", &[], &["pi() * 2 > 6.28", "e() < 2.72"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n1\n");
+
+    let output = kaleidescope("check", &[], &["def area(r) pi * r * r"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "error: unknown variable `pi`, but there is a function `pi()`\n");
+}
+
+#[test]
+fn programs_can_shadow_the_prelude_or_do_without_it() {
+    let program = ["def max(a, b, c) if a > b then a else if b > c then b else c", "max(1, 5, 2) + min(4, 6)"];
+
+    let output = kaleidescope("check", &[], &program);
+    assert!(output.status.success());
+
+    let output = kaleidescope("run", &[], &program);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "9\n");
+
+    let output = kaleidescope("run", &["--no-prelude"], &program);
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "error: call to undefined function `min`\n");
+
+    let output = kaleidescope("check", &["--no-prelude"], &["def f(x) sqrt(x)"]);
+    assert_eq!(output.status.code(), Some(4));
+}
+
+#[test]
+fn built_programs_leave_the_prelude_out() {
+    let output = kaleidescope("build", &["--emit", "sexpr"], &["def f(x) cos(x)", "f(0)"]);
+    assert!(output.status.success());
+    assert!(!String::from_utf8(output.stdout).unwrap().contains("extern"));
+}
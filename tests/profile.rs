@@ -0,0 +1,106 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::process::Command;
+
+const PROGRAM: &str = "\
+def fib(x) if x < 3 then 1 else fib(x - 1) + fib(x - 2)
+def twice(y) fib(y) * 2
+def unused(z) z
+
+twice(6) + twice(3)
+";
+
+#[test]
+fn profile_reports_calls_and_writes_folded_stacks() {
+    let dir = env::temp_dir();
+    let source = dir.join("kaleidescope-profile.k");
+    let folded = dir.join("kaleidescope-profile.folded");
+    File::create(&source).unwrap().write_all(PROGRAM.as_bytes()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs"))
+        .args(["run", "--profile", "--folded-stacks"])
+        .arg(&folded)
+        .arg(&source)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "20\n");
+
+    // fib(6) makes 15 calls and fib(3) makes 3, at most 5 and 2 deep
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let rows: Vec<Vec<&str>> = stderr.lines().skip(2).map(|line| line.split_whitespace().collect()).collect();
+    let row = |name: &str| rows.iter().find(|row| row[0] == name).cloned().unwrap();
+
+    assert!(stderr.starts_with("profile:\nfunction "));
+    assert_eq!(rows.len(), 2);
+    assert_eq!(row("fib")[1], "18");
+    assert_eq!(row("fib")[4], "5");
+    assert_eq!(row("twice")[1], "2");
+    assert_eq!(row("twice")[4], "1");
+
+    let stacks: Vec<String> = fs::read_to_string(&folded).unwrap().lines()
+        .map(|line| {
+            let (stack, nanoseconds) = line.rsplit_once(' ').unwrap();
+            assert!(nanoseconds.parse::<u64>().is_ok(), "{}", line);
+            stack.to_string()
+        })
+        .collect();
+
+    assert_eq!(stacks, ["twice", "twice;fib", "twice;fib;fib", "twice;fib;fib;fib", "twice;fib;fib;fib;fib", "twice;fib;fib;fib;fib;fib"]);
+}
+
+/// Run `source` with `--profile` and return its report, one row of columns per function.
+fn report(name: &str, source: &str) -> Vec<Vec<String>> {
+    let path = env::temp_dir().join(format!("kaleidescope-profile-{}.k", name));
+    File::create(&path).unwrap().write_all(source.as_bytes()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs"))
+        .args(["run", "--profile"])
+        .arg(&path)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let report = &stderr[stderr.find("profile:\n").unwrap() + "profile:\n".len()..];
+    report.lines().map(|line| line.split_whitespace().map(str::to_string).collect()).collect()
+}
+
+#[test]
+fn the_report_has_a_column_for_each_measure() {
+    let rows = report("columns", PROGRAM);
+
+    assert_eq!(rows[0], ["function", "calls", "inclusive", "exclusive", "max", "depth"]);
+    for row in &rows[1..] {
+        assert_eq!(row.len(), 5);
+        assert!(row[2].ends_with("ms") && row[3].ends_with("ms"), "{:?}", row);
+
+        // A function's own time is part of its time with the calls it makes
+        let ms = |column: &str| column.trim_end_matches("ms").parse::<f64>().unwrap();
+        assert!(ms(&row[3]) <= ms(&row[2]), "{:?}", row);
+    }
+}
+
+#[test]
+fn mutually_recursive_calls_are_each_counted() {
+    let source = "def even(n) if n == 0 then 1 else odd(n - 1)\n\
+                  def odd(n) if n == 0 then 0 else even(n - 1)\n\
+                  even(10)\n";
+    let rows = report("mutual", source);
+    let row = |name: &str| rows.iter().find(|row| row[0] == name).cloned().unwrap();
+
+    assert_eq!((row("even")[1].as_str(), row("even")[4].as_str()), ("6", "6"));
+    assert_eq!((row("odd")[1].as_str(), row("odd")[4].as_str()), ("5", "5"));
+}
+
+#[test]
+fn cached_calls_are_not_counted() {
+    let rows = report("memo", "@memo def fib(n) if n < 2 then n else fib(n - 1) + fib(n - 2)\nfib(10)\n");
+    let fib = rows.iter().find(|row| row[0] == "fib").unwrap();
+
+    // Only the 11 calls that missed the cache ran a body
+    assert_eq!(fib[1], "11");
+}
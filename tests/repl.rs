@@ -0,0 +1,71 @@
+use std::env;
+use std::io::prelude::*;
+use std::process::{Command, Output, Stdio};
+
+/// Type `input` into a session, with history kept out of the real home directory.
+fn repl(input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs"))
+        .args(["repl", "--no-prelude"])
+        .env("HOME", env::temp_dir())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+fn stderr(output: &Output) -> &str {
+    std::str::from_utf8(&output.stderr).unwrap()
+}
+
+#[test]
+fn unfinished_input_continues_on_the_next_line() {
+    let output = repl("def f(x)\n  x + 1\nf(1)\n(1 +\n2)\n");
+
+    assert_eq!(stdout(&output), "2\n3\n");
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn an_empty_line_ends_unfinished_input() {
+    let output = repl("1 +\n\n2 + 2\n");
+
+    assert_eq!(stdout(&output), "4\n");
+    assert_eq!(stderr(&output), "error: Unexpected token, `Token < End-of-file >`\n");
+}
+
+#[test]
+fn definitions_can_be_replaced() {
+    let output = repl("def f(x) x + 1\nf(1)\ndef f(x) x * 10\nf(2)\n:defs\n");
+
+    assert_eq!(stdout(&output), "2\n20\ndef f(x): pure\n");
+}
+
+#[test]
+fn errors_leave_the_session_usable() {
+    let output = repl("def f(x) x\nnope(3)\n)\nf(4)\n");
+
+    assert_eq!(stdout(&output), "4\n");
+    assert_eq!(
+        stderr(&output),
+        "error: call to undefined function `nope`\nerror: Unexpected token, `Token < Closing Delimiter: Paren `)` >`\n"
+    );
+}
+
+#[test]
+fn reset_forgets_definitions() {
+    let output = repl("def f(x) x\n:reset\n:defs\nf(1)\n");
+
+    assert_eq!(stdout(&output), "");
+    assert_eq!(stderr(&output), "error: call to undefined function `f`\n");
+}
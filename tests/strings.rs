@@ -0,0 +1,61 @@
+use std::env;
+use std::fs;
+use std::process::{Command, Output};
+
+fn kaleidescope(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kaleidescope-rs")).args(args).output().unwrap()
+}
+
+fn stdout(output: Output) -> String {
+    String::from_utf8(output.stdout).unwrap()
+}
+
+const SOURCE: &str = r#"def greet(name) "hello, " + name + "!\n"
+def plural(n, word) str(n) + " " + (if n == 1 then word else word + "s")
+
+print(greet("world"))
+len("tab\there")
+plural(num(" 3 "), "\"apple\"")
+"apple" < "banana"
+"1" == 1
+"#;
+
+#[test]
+fn strings_are_joined_compared_and_converted() {
+    let output = kaleidescope(&["run", "-e", SOURCE]);
+    assert!(output.status.success());
+    assert_eq!(stdout(output), "hello, world!\n0\n8\n\"3 \\\"apple\\\"s\"\n1\n0\n");
+}
+
+#[test]
+fn strings_round_trip_through_every_form() {
+    let path = env::temp_dir().join("kaleidescope-strings.json");
+
+    let output = kaleidescope(&["build", "-o", path.to_str().unwrap(), "-e", SOURCE]);
+    assert!(output.status.success());
+    assert!(fs::read_to_string(&path).unwrap().contains("{\n          \"kind\": \"string\",\n          \"value\": \"!\\n\"\n        }"));
+
+    let output = kaleidescope(&["run", "--from-ast", path.to_str().unwrap()]);
+    assert!(stdout(output).starts_with("hello, world!\n0\n8\n"));
+
+    let output = kaleidescope(&["parse", "--emit", "sexpr", "-e", r#"print("a\"b\\c")"#]);
+    assert_eq!(stdout(output), "(print \"a\\\"b\\\\c\")\n");
+}
+
+#[test]
+fn misusing_strings_is_an_error() {
+    let cases = [
+        (r#""a" - "b""#, 5, "error: `-` needs numbers, not strings\n"),
+        (r#""a" < 1"#, 5, "error: `<` needs two numbers or two strings, not a number and a string\n"),
+        (r#"sqrt("4")"#, 5, "error: `sqrt` takes numbers, but argument 1 is a string\n"),
+        (r#"num("four")"#, 5, "error: `num` failed: \"four\" is not a number\n"),
+        (r#""unterminated"#, 4, "error: -e #1: unterminated string\n"),
+        (r#""\q""#, 4, "error: -e #1: unknown escape sequence `\\q`\n"),
+    ];
+
+    for &(source, code, error) in &cases {
+        let output = kaleidescope(&["run", "-e", source]);
+        assert_eq!(output.status.code(), Some(code), "{}", source);
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), error);
+    }
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use std::process::Output;
+
+use common::kaleidescope;
+
+const LIBRARY: &str = "\
+extern assert_eq(a, b)
+extern assert_close(a, b, eps)
+
+def fib(x) if x < 3 then 1 else fib(x - 1) + fib(x - 2)
+def even(x) assert_eq(x % 2, 0)
+
+fib(10)
+";
+
+const TESTS: &str = "\
+test fib_ten assert_eq(fib(10), 55)
+test fib_wrong
+    assert_eq(fib(1), 1) && assert_eq(fib(10), 56)
+test helper even(4) && even(3)
+test close assert_close(0.1 + 0.2, 0.3, 0.000001)
+test boolean fib(5) == 6
+";
+
+/// Run the tests in `LIBRARY` and `TESTS`, passing `args` to the runner.
+fn test(args: &[&str]) -> Output {
+    kaleidescope("test", args, &[LIBRARY, TESTS])
+}
+
+#[test]
+fn tests_are_discovered_across_files_and_reported() {
+    let output = test(&[]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.starts_with("running 5 tests\n"));
+    assert!(stdout.contains("test fib_ten ... ok\n"));
+    assert!(stdout.contains("test fib_wrong ... FAILED\n"));
+    assert!(stdout.contains("test close ... ok\n"));
+    assert!(stdout.ends_with("test result: FAILED. 2 passed; 3 failed; 0 filtered out\n"));
+
+    // Top-level expressions don't run
+    assert!(!stdout.contains("55\n"));
+}
+
+#[test]
+fn failures_point_at_the_failing_expression() {
+    let stdout = String::from_utf8(test(&[]).stdout).unwrap();
+
+    assert!(stdout.contains("-e #2:3:29 ----\nassert_eq(fib(10), 56)\n`assert_eq` failed: 55 != 56\n"));
+    assert!(stdout.contains("-e #1:5:13 ----\nassert_eq(x % 2, 0)\n`assert_eq` failed: 1 != 0\n"));
+    assert!(stdout.contains("-e #2:6:14 ----\nfib(5) == 6\nevaluated to 0\n"));
+}
+
+#[test]
+fn tests_can_be_filtered_by_name() {
+    let output = test(&["--filter", "fib", "--filter", "close"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(stdout.starts_with("running 3 tests\n"));
+    assert!(stdout.ends_with("test result: FAILED. 2 passed; 1 failed; 2 filtered out\n"));
+
+    let output = test(&["--filter", "fib_ten"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().starts_with("running 1 test\n"));
+}
+
+#[test]
+fn assertions_come_with_the_prelude() {
+    let source = "def check(x) assert_eq(x, x) && assert_close(x, 1, 0.5)\ntest one check(1)\ntest two check(2)";
+    let output = kaleidescope("test", &[], &[source]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(stdout.contains("test one ... ok\n"), "{}", stdout);
+    assert!(stdout.contains("-e #1:1:33 ----\nassert_close(x, 1, 0.5)\n"), "{}", stdout);
+}
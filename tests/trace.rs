@@ -0,0 +1,46 @@
+mod common;
+
+use std::process::Output;
+
+use common::kaleidescope;
+
+const PROGRAM: [&str; 3] = [
+    "def double(x) x * 2",
+    "def f(x) if x > 0 && double(x) > 3 then double(x) else 0",
+    "f(2) + f(0)",
+];
+
+fn trace(flags: &[&str]) -> Output {
+    kaleidescope("run", flags, &PROGRAM)
+}
+
+#[test]
+fn calls_are_traced_by_depth() {
+    let output = trace(&["--trace"]);
+
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "4\n");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "\
+-> f(x = 2)
+  -> double(x = 2)
+  <- double = 4
+  -> double(x = 2)
+  <- double = 4
+<- f = 4
+-> f(x = 0)
+<- f = 0
+");
+}
+
+#[test]
+fn binary_operations_filters_and_limits() {
+    let stderr = String::from_utf8(trace(&["--trace-binary", "--trace-filter", "f"]).stderr).unwrap();
+
+    // `&&` doesn't evaluate what it doesn't need, and `double` isn't traced
+    assert!(stderr.starts_with("-> f(x = 2)\n  2 > 0 = 1\n  4 > 3 = 1\n  1 && 1 = 1\n<- f = 4\n"));
+    assert!(stderr.contains("  0 > 0 = 0\n  0 && _ = 0\n"));
+    assert!(!stderr.contains("double"));
+    assert!(!stderr.contains("4 + 0"));
+
+    let stderr = String::from_utf8(trace(&["--trace", "--trace-limit", "2"]).stderr).unwrap();
+    assert_eq!(stderr, "-> f(x = 2)\n  -> double(x = 2)\n... stopped tracing after 2 events\n");
+}